@@ -0,0 +1,253 @@
+//! Passphrase-encrypted secret keys, see `KEY_ENCODING_SPEC.md` for the format.
+
+use crate::errors::DecryptSecretKeyError;
+use crate::keys::SecretKey;
+
+/// Prefix of every encrypted secret key, followed by the format version
+const PREFIX: &str = "kulfi-encrypted-key";
+const VERSION: &str = "v1";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Argon2id cost parameters, stored in the encrypted key so they can be raised later
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct KdfParams {
+    /// memory in KiB
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+/// The argon2 crate's defaults, as recommended by OWASP
+const DEFAULT_KDF_PARAMS: KdfParams = KdfParams {
+    m_cost: 19 * 1024,
+    t_cost: 2,
+    p_cost: 1,
+};
+
+/// Refuse to spend more than 1 GiB of memory on a key file we did not write
+const MAX_M_COST: u32 = 1024 * 1024;
+/// Or more than 16 passes over it, so a key file can not keep us busy for hours either
+const MAX_T_COST: u32 = 16;
+/// Lanes are computed one after the other, but each one needs memory of its own
+const MAX_P_COST: u32 = 16;
+
+impl SecretKey {
+    /// Encrypt the key with a passphrase
+    ///
+    /// The result is a single line of text that can be stored wherever a hex encoded key is
+    /// accepted. Every call uses a fresh salt and nonce, so encrypting the same key twice gives
+    /// different results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kulfi_id52::SecretKey;
+    ///
+    /// let secret_key = SecretKey::generate();
+    /// let encrypted = secret_key.encrypt("correct horse battery staple");
+    /// assert!(SecretKey::is_encrypted(&encrypted));
+    ///
+    /// let decrypted = SecretKey::decrypt(&encrypted, "correct horse battery staple").unwrap();
+    /// assert_eq!(decrypted.id52(), secret_key.id52());
+    /// ```
+    pub fn encrypt(&self, passphrase: &str) -> String {
+        self.encrypt_with(passphrase, DEFAULT_KDF_PARAMS)
+    }
+
+    fn encrypt_with(&self, passphrase: &str, params: KdfParams) -> String {
+        use crypto_secretbox::aead::{Aead, KeyInit};
+        use rand::RngCore;
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+        let key = derive_key(passphrase, &salt, params).expect("default params are valid");
+        let ciphertext = crypto_secretbox::XChaCha20Poly1305::new(&key.into())
+            .encrypt(&nonce.into(), self.to_bytes().as_slice())
+            .expect("encrypting 32 bytes can not fail");
+
+        format!(
+            "{PREFIX}:{VERSION}:{}:{}:{}:{}:{}:{}",
+            params.m_cost,
+            params.t_cost,
+            params.p_cost,
+            data_encoding::HEXLOWER.encode(&salt),
+            data_encoding::HEXLOWER.encode(&nonce),
+            data_encoding::HEXLOWER.encode(&ciphertext),
+        )
+    }
+
+    /// Decrypt a key encrypted with [`SecretKey::encrypt`]
+    ///
+    /// Fails if the passphrase is wrong, the key was tampered with, or it uses a version of the
+    /// format we do not know.
+    pub fn decrypt(encrypted: &str, passphrase: &str) -> Result<Self, DecryptSecretKeyError> {
+        use crypto_secretbox::aead::{Aead, KeyInit};
+
+        let err = |reason: &str| DecryptSecretKeyError {
+            reason: reason.to_string(),
+        };
+
+        let parts: Vec<&str> = encrypted.trim().split(':').collect();
+        match parts.as_slice() {
+            [
+                PREFIX,
+                VERSION,
+                m_cost,
+                t_cost,
+                p_cost,
+                salt,
+                nonce,
+                ciphertext,
+            ] => {
+                let params = KdfParams {
+                    m_cost: m_cost.parse().map_err(|_| err("invalid memory cost"))?,
+                    t_cost: t_cost.parse().map_err(|_| err("invalid time cost"))?,
+                    p_cost: p_cost.parse().map_err(|_| err("invalid parallelism"))?,
+                };
+                if params.m_cost > MAX_M_COST {
+                    return Err(err("memory cost is too high"));
+                }
+                if params.t_cost > MAX_T_COST {
+                    return Err(err("time cost is too high"));
+                }
+                if params.p_cost > MAX_P_COST {
+                    return Err(err("parallelism is too high"));
+                }
+
+                let salt = decode_hex(salt).ok_or_else(|| err("invalid salt"))?;
+                let nonce: [u8; NONCE_LEN] = decode_hex(nonce)
+                    .and_then(|n| n.try_into().ok())
+                    .ok_or_else(|| err("invalid nonce"))?;
+                let ciphertext = decode_hex(ciphertext).ok_or_else(|| err("invalid ciphertext"))?;
+
+                let key = derive_key(passphrase, &salt, params)?;
+                let bytes: [u8; 32] = crypto_secretbox::XChaCha20Poly1305::new(&key.into())
+                    .decrypt(&nonce.into(), ciphertext.as_slice())
+                    .map_err(|_| err("wrong passphrase, or the key is corrupted"))?
+                    .try_into()
+                    .map_err(|_| err("invalid key length"))?;
+
+                Ok(SecretKey::from_bytes(&bytes))
+            }
+            [PREFIX, version, ..] => Err(err(&format!("unsupported version {version}"))),
+            _ => Err(err("not an encrypted secret key")),
+        }
+    }
+
+    /// Check if `s` looks like an encrypted key, as opposed to a hex or base32 one
+    pub fn is_encrypted(s: &str) -> bool {
+        s.trim_start().starts_with(PREFIX)
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: KdfParams,
+) -> Result<[u8; 32], DecryptSecretKeyError> {
+    let err = |e: argon2::Error| DecryptSecretKeyError {
+        reason: format!("key derivation failed: {e}"),
+    };
+
+    let params =
+        argon2::Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32)).map_err(err)?;
+    let mut key = [0u8; 32];
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(err)?;
+    Ok(key)
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    data_encoding::HEXLOWER.decode(s.as_bytes()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters, the defaults take a while in debug builds
+    const TEST_PARAMS: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let secret_key = SecretKey::generate();
+        let encrypted = secret_key.encrypt_with("hunter2", TEST_PARAMS);
+        assert!(encrypted.starts_with("kulfi-encrypted-key:v1:64:1:1:"));
+        assert!(SecretKey::is_encrypted(&encrypted));
+        assert!(!SecretKey::is_encrypted(&secret_key.to_string()));
+
+        let decrypted = SecretKey::decrypt(&encrypted, "hunter2").unwrap();
+        assert_eq!(decrypted.to_bytes(), secret_key.to_bytes());
+
+        // a fresh salt and nonce every time
+        assert_ne!(encrypted, secret_key.encrypt_with("hunter2", TEST_PARAMS));
+    }
+
+    #[test]
+    fn test_decrypt_failures() {
+        let secret_key = SecretKey::generate();
+        let encrypted = secret_key.encrypt_with("hunter2", TEST_PARAMS);
+
+        assert!(SecretKey::decrypt(&encrypted, "hunter3").is_err());
+
+        // flipping a bit of the ciphertext is caught by the tag
+        let mut tampered = encrypted.clone();
+        let last = tampered.pop().unwrap();
+        tampered.push(if last == '0' { '1' } else { '0' });
+        assert!(SecretKey::decrypt(&tampered, "hunter2").is_err());
+
+        let future = encrypted.replacen(":v1:", ":v2:", 1);
+        let e = SecretKey::decrypt(&future, "hunter2").err().unwrap();
+        assert!(e.to_string().contains("unsupported version v2"));
+
+        assert!(SecretKey::decrypt(&secret_key.to_string(), "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_cost_limits() {
+        let secret_key = SecretKey::generate();
+        let encrypted = secret_key.encrypt_with("hunter2", TEST_PARAMS);
+
+        // refused before any key derivation, so these return right away
+        for (costs, reason) in [
+            (
+                format!(":{}:1:1:", MAX_M_COST + 1),
+                "memory cost is too high",
+            ),
+            (
+                format!(":64:{}:1:", MAX_T_COST + 1),
+                "time cost is too high",
+            ),
+            (
+                format!(":64:1:{}:", MAX_P_COST + 1),
+                "parallelism is too high",
+            ),
+        ] {
+            let expensive = encrypted.replacen(":64:1:1:", &costs, 1);
+            let e = SecretKey::decrypt(&expensive, "hunter2").err().unwrap();
+            assert!(e.to_string().contains(reason), "{e}");
+        }
+    }
+
+    #[test]
+    fn test_decrypt_known_vector() {
+        // key-1 from test-keys/, encrypted with passphrase "kulfi" and TEST_PARAMS, must keep
+        // decrypting as long as v1 is supported
+        let encrypted = include_str!("../../test-keys/key-1.encrypted.txt");
+        let decrypted = SecretKey::decrypt(encrypted, "kulfi").unwrap();
+        assert_eq!(
+            decrypted.id52(),
+            "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60"
+        );
+    }
+}
@@ -79,3 +79,60 @@ impl fmt::Display for InvalidSignatureBytesError {
 }
 
 impl Error for InvalidSignatureBytesError {}
+
+/// Error when decrypting a passphrase-encrypted secret key
+#[derive(Debug, Clone)]
+pub struct DecryptSecretKeyError {
+    pub reason: String,
+}
+
+impl fmt::Display for DecryptSecretKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to decrypt secret key: {}", self.reason)
+    }
+}
+
+impl Error for DecryptSecretKeyError {}
+
+/// Error when importing a key from OpenSSH, PKCS#8 or SPKI format
+#[derive(Debug, Clone)]
+pub struct KeyFormatError {
+    pub format: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for KeyFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {} key: {}", self.format, self.reason)
+    }
+}
+
+impl Error for KeyFormatError {}
+
+/// Error when restoring a secret key from a mnemonic
+#[derive(Debug, Clone)]
+pub struct ParseMnemonicError {
+    pub reason: String,
+}
+
+impl fmt::Display for ParseMnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid mnemonic: {}", self.reason)
+    }
+}
+
+impl Error for ParseMnemonicError {}
+
+/// Error when signing or parsing an identity rotation document
+#[derive(Debug, Clone)]
+pub struct RotationError {
+    pub reason: String,
+}
+
+impl fmt::Display for RotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid rotation: {}", self.reason)
+    }
+}
+
+impl Error for RotationError {}
@@ -214,6 +214,11 @@ impl FromStr for SecretKey {
         } else {
             // For backward compatibility, also try BASE32_NOPAD
             let input = s.to_ascii_uppercase();
+            if data_encoding::BASE32_NOPAD.decode_len(input.len()) != Ok(32) {
+                return Err(ParseSecretKeyError {
+                    reason: format!("expected 64 hex or 52 base32 characters, got {}", s.len()),
+                });
+            }
             let mut result = [0u8; 32];
             data_encoding::BASE32_NOPAD
                 .decode_mut(input.as_bytes(), &mut result)
@@ -304,6 +309,13 @@ mod tests {
         assert_eq!(parsed.to_bytes(), secret_key.to_bytes());
     }
 
+    #[test]
+    fn test_secret_key_invalid_length() {
+        assert!(SecretKey::from_str("").is_err());
+        assert!(SecretKey::from_str("not a key").is_err());
+        assert!(SecretKey::from_str("-----BEGIN PUBLIC KEY-----").is_err());
+    }
+
     #[test]
     fn test_signature_verification() {
         let secret_key = SecretKey::generate();
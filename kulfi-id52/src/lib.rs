@@ -1,8 +1,16 @@
+mod encrypted;
 mod errors;
+mod formats;
 mod keys;
+mod mnemonic;
+mod rotation;
+mod vanity;
 
 pub use errors::{
-    InvalidKeyBytesError, InvalidSignatureBytesError, ParseId52Error, ParseSecretKeyError,
+    DecryptSecretKeyError, InvalidKeyBytesError, InvalidSignatureBytesError, KeyFormatError,
+    ParseId52Error, ParseMnemonicError, ParseSecretKeyError, RotationError,
     SignatureVerificationError,
 };
 pub use keys::{PublicKey, SecretKey, Signature};
+pub use rotation::{Rotation, SignedRotation};
+pub use vanity::VanityPrefix;
@@ -0,0 +1,147 @@
+//! Mnemonic backups of secret keys, see `KEY_ENCODING_SPEC.md` for the format.
+
+use crate::errors::ParseMnemonicError;
+use crate::keys::SecretKey;
+
+/// 32 bytes of entropy and an 8 bit checksum, 11 bits per word
+const MNEMONIC_WORDS: usize = 24;
+
+impl SecretKey {
+    /// Encode the key as 24 words from the BIP39 English word list
+    ///
+    /// The 32 secret key bytes are used as BIP39 entropy, so the last word carries a checksum
+    /// and typos are caught by [`SecretKey::from_mnemonic`]. The words are not a wallet seed,
+    /// the BIP39 seed derivation is not used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kulfi_id52::SecretKey;
+    ///
+    /// let secret_key = SecretKey::generate();
+    /// let words = secret_key.to_mnemonic();
+    /// assert_eq!(words.split(' ').count(), 24);
+    ///
+    /// let restored = SecretKey::from_mnemonic(&words).unwrap();
+    /// assert_eq!(restored.id52(), secret_key.id52());
+    /// ```
+    pub fn to_mnemonic(&self) -> String {
+        bip39::Mnemonic::from_entropy(&self.to_bytes())
+            .expect("32 bytes is a valid entropy length")
+            .to_string()
+    }
+
+    /// Restore a key from the words written by [`SecretKey::to_mnemonic`]
+    ///
+    /// Case and whitespace, including line breaks, do not matter. Fails on unknown words, a
+    /// wrong number of words, or a bad checksum.
+    pub fn from_mnemonic(words: &str) -> Result<Self, ParseMnemonicError> {
+        let words = words.to_lowercase();
+        let count = words.split_whitespace().count();
+        if count != MNEMONIC_WORDS {
+            return Err(ParseMnemonicError {
+                reason: format!("expected {MNEMONIC_WORDS} words, got {count}"),
+            });
+        }
+
+        let mnemonic =
+            bip39::Mnemonic::parse_in(bip39::Language::English, &words).map_err(|e| {
+                ParseMnemonicError {
+                    reason: e.to_string(),
+                }
+            })?;
+        let (entropy, len) = mnemonic.to_entropy_array();
+        let bytes: [u8; 32] = entropy[..len]
+            .try_into()
+            .expect("24 words are always 32 bytes");
+        Ok(SecretKey::from_bytes(&bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_mnemonic_roundtrip() {
+        let secret_key = SecretKey::generate();
+        let words = secret_key.to_mnemonic();
+        assert_eq!(words.split(' ').count(), MNEMONIC_WORDS);
+
+        let restored = SecretKey::from_mnemonic(&words).unwrap();
+        assert_eq!(restored.to_bytes(), secret_key.to_bytes());
+
+        // as written down: upper case, one word per line
+        let restored = SecretKey::from_mnemonic(&words.to_uppercase().replace(' ', "\n")).unwrap();
+        assert_eq!(restored.to_bytes(), secret_key.to_bytes());
+    }
+
+    #[test]
+    fn test_mnemonic_errors() {
+        let words = SecretKey::generate().to_mnemonic();
+        let mut words: Vec<&str> = words.split(' ').collect();
+
+        let e = SecretKey::from_mnemonic(&words[1..].join(" "))
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("expected 24 words, got 23"));
+
+        words[0] = "kulfi";
+        assert!(SecretKey::from_mnemonic(&words.join(" ")).is_err());
+
+        // every word is valid, but the checksum word of all zero bytes is `art`
+        let bad_checksum = ["abandon"; 24].join(" ");
+        assert!(SecretKey::from_mnemonic(&bad_checksum).is_err());
+    }
+
+    #[test]
+    fn test_bip39_vectors() {
+        // the 256 bit vectors from the BIP39 spec, the words must stay standard
+        let vectors = [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth title",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+            ),
+        ];
+
+        for (hex, words) in vectors {
+            let secret_key = SecretKey::from_str(hex).unwrap();
+            assert_eq!(secret_key.to_mnemonic(), words);
+            assert_eq!(SecretKey::from_mnemonic(words).unwrap().to_string(), hex);
+        }
+    }
+
+    #[test]
+    fn test_known_vectors() {
+        // the baseline keys in test-keys/, must keep restoring to the same id52
+        let vectors = [
+            (
+                include_str!("../../test-keys/key-1.mnemonic.txt"),
+                "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60",
+            ),
+            (
+                include_str!("../../test-keys/key-2.mnemonic.txt"),
+                "e87aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9as9b7v80",
+            ),
+            (
+                include_str!("../../test-keys/key-3.mnemonic.txt"),
+                "mlk9ubnvu8r1sk06j4tjb98njra0od5d2dglpm8gubbvg4glthng",
+            ),
+        ];
+
+        for (words, id52) in vectors {
+            let secret_key = SecretKey::from_mnemonic(words).unwrap();
+            assert_eq!(secret_key.id52(), id52);
+            assert_eq!(secret_key.to_mnemonic(), words.trim());
+        }
+    }
+}
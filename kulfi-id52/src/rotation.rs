@@ -0,0 +1,262 @@
+//! Signed statements that an ID52 is superseded by another, see `KEY_ENCODING_SPEC.md`
+
+use crate::errors::RotationError;
+use crate::keys::{PublicKey, SecretKey, Signature};
+use std::fmt;
+use std::str::FromStr;
+
+/// The first line of a rotation document, the version of the format
+const HEADER: &str = "kulfi-rotation-v1";
+
+/// "ID52 `old` is superseded by ID52 `new` as of `since`", before it is signed
+///
+/// Both keys sign the statement: the old one says where it moved, and the new one agrees, so a
+/// leaked key can not be used to point an ID52 at someone else's. Someone holding the leaked key
+/// can still sign a rotation to a key of their own, which is why two different rotations of the
+/// same ID52 should be treated as a sign of compromise, not followed.
+///
+/// # Examples
+///
+/// ```
+/// use kulfi_id52::{Rotation, SecretKey, SignedRotation};
+///
+/// let old = SecretKey::generate();
+/// let new = SecretKey::generate();
+/// let rotation = Rotation {
+///     old: old.public_key(),
+///     new: new.public_key(),
+///     since: 1_800_000_000,
+/// };
+///
+/// let document = rotation.sign(&old, &new).unwrap().to_string();
+/// let signed: SignedRotation = document.parse().unwrap();
+/// assert_eq!(signed.rotation(), &rotation);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rotation {
+    pub old: PublicKey,
+    pub new: PublicKey,
+    /// Unix time in seconds from when `new` is to be used instead of `old`
+    pub since: u64,
+}
+
+impl Rotation {
+    /// Sign with the secret keys of both `old` and `new`
+    pub fn sign(&self, old: &SecretKey, new: &SecretKey) -> Result<SignedRotation, RotationError> {
+        if old.public_key() != self.old {
+            return Err(err(format!("the secret key is not of {}", self.old)));
+        }
+        if new.public_key() != self.new {
+            return Err(err(format!("the secret key is not of {}", self.new)));
+        }
+        if self.old == self.new {
+            return Err(err("an ID52 can not be superseded by itself"));
+        }
+        let message = self.message();
+        Ok(SignedRotation {
+            rotation: self.clone(),
+            old_signature: old.sign(message.as_bytes()).to_bytes(),
+            new_signature: new.sign(message.as_bytes()).to_bytes(),
+        })
+    }
+
+    /// The text both keys sign, the document without the signature lines
+    fn message(&self) -> String {
+        format!(
+            "{HEADER}\nold: {}\nnew: {}\nsince: {}\n",
+            self.old, self.new, self.since
+        )
+    }
+}
+
+/// A [`Rotation`] with valid signatures of both keys
+///
+/// The only ways to get one are [`Rotation::sign`] and parsing, which checks the signatures, so
+/// holding a `SignedRotation` means it is genuine. The text form is the signed statement followed
+/// by the two signatures in hex, and can be published as a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedRotation {
+    rotation: Rotation,
+    old_signature: [u8; 64],
+    new_signature: [u8; 64],
+}
+
+impl SignedRotation {
+    pub fn rotation(&self) -> &Rotation {
+        &self.rotation
+    }
+
+    /// The ID52 that is superseded
+    pub fn old_key(&self) -> &PublicKey {
+        &self.rotation.old
+    }
+
+    /// The ID52 to use instead
+    pub fn new_key(&self) -> &PublicKey {
+        &self.rotation.new
+    }
+
+    /// Unix time in seconds from when the rotation applies
+    pub fn since(&self) -> u64 {
+        self.rotation.since
+    }
+
+    /// Whether the rotation applies at unix time `now`
+    pub fn is_active(&self, now: u64) -> bool {
+        self.rotation.since <= now
+    }
+}
+
+impl fmt::Display for SignedRotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}old-signature: {}\nnew-signature: {}\n",
+            self.rotation.message(),
+            data_encoding::HEXLOWER.encode(&self.old_signature),
+            data_encoding::HEXLOWER.encode(&self.new_signature)
+        )
+    }
+}
+
+impl FromStr for SignedRotation {
+    type Err = RotationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.trim().lines().map(str::trim);
+        if lines.next() != Some(HEADER) {
+            return Err(err(format!("expected the first line to be {HEADER}")));
+        }
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|rest| rest.strip_prefix(':'))
+                .map(str::trim)
+                .ok_or_else(|| err(format!("expected {name}: on the next line")))
+        };
+
+        let old = PublicKey::from_str(field("old")?).map_err(err)?;
+        let new = PublicKey::from_str(field("new")?).map_err(err)?;
+        let since = field("since")?
+            .parse()
+            .map_err(|e| err(format!("since: {e}")))?;
+        let old_signature = signature(field("old-signature")?)?;
+        let new_signature = signature(field("new-signature")?)?;
+        if lines.next().is_some() {
+            return Err(err("unexpected lines after new-signature"));
+        }
+
+        let rotation = Rotation { old, new, since };
+        let message = rotation.message();
+        for (key, bytes) in [(&old, &old_signature), (&new, &new_signature)] {
+            let signature = Signature::from_bytes(bytes).map_err(err)?;
+            key.verify(message.as_bytes(), &signature)
+                .map_err(|_| err(format!("the signature of {key} is not valid")))?;
+        }
+        if old == new {
+            return Err(err("an ID52 can not be superseded by itself"));
+        }
+
+        Ok(SignedRotation {
+            rotation,
+            old_signature,
+            new_signature,
+        })
+    }
+}
+
+fn signature(hex: &str) -> Result<[u8; 64], RotationError> {
+    data_encoding::HEXLOWER_PERMISSIVE
+        .decode(hex.as_bytes())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| err("a signature is 128 hex characters"))
+}
+
+fn err(reason: impl ToString) -> RotationError {
+    RotationError {
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation() -> (SecretKey, SecretKey, Rotation) {
+        let old = SecretKey::generate();
+        let new = SecretKey::generate();
+        let rotation = Rotation {
+            old: old.public_key(),
+            new: new.public_key(),
+            since: 1_800_000_000,
+        };
+        (old, new, rotation)
+    }
+
+    #[test]
+    fn test_rotation_roundtrip() {
+        let (old, new, rotation) = rotation();
+        let signed = rotation.sign(&old, &new).unwrap();
+        let document = signed.to_string();
+        assert!(document.starts_with("kulfi-rotation-v1\n"));
+        assert_eq!(document.lines().count(), 6);
+
+        let parsed: SignedRotation = document.parse().unwrap();
+        assert_eq!(parsed, signed);
+        assert_eq!(parsed.old_key(), &old.public_key());
+        assert_eq!(parsed.new_key(), &new.public_key());
+        assert!(!parsed.is_active(1_799_999_999));
+        assert!(parsed.is_active(1_800_000_000));
+
+        // as pasted from somewhere: indented, with blank lines around it
+        let indented: String = document.lines().map(|l| format!("  {l}\n")).collect();
+        assert_eq!(
+            format!("\n{indented}\n").parse::<SignedRotation>().unwrap(),
+            signed
+        );
+    }
+
+    #[test]
+    fn test_rotation_wrong_keys() {
+        let (old, new, rotation) = rotation();
+        assert!(rotation.sign(&new, &new).is_err());
+        assert!(rotation.sign(&old, &old).is_err());
+
+        let same = Rotation {
+            old: old.public_key(),
+            new: old.public_key(),
+            since: 0,
+        };
+        assert!(same.sign(&old, &old).is_err());
+    }
+
+    #[test]
+    fn test_rotation_tampered() {
+        let (old, new, rotation) = rotation();
+        let document = rotation.sign(&old, &new).unwrap().to_string();
+
+        // pointing the old ID52 somewhere else breaks both signatures
+        let other = SecretKey::generate().id52();
+        let moved = document.replace(&new.id52(), &other);
+        let e = moved.parse::<SignedRotation>().err().unwrap();
+        assert!(e.to_string().contains("is not valid"), "{e}");
+
+        let later = document.replace("since: 1800000000", "since: 1900000000");
+        assert!(later.parse::<SignedRotation>().is_err());
+
+        // signed by the old key only
+        let mut lines: Vec<&str> = document.lines().collect();
+        let old_signature = lines[4].replace("old-", "new-");
+        lines[5] = &old_signature;
+        assert!(lines.join("\n").parse::<SignedRotation>().is_err());
+
+        assert!("kulfi-rotation-v2".parse::<SignedRotation>().is_err());
+        assert!(
+            format!("{document}extra: 1")
+                .parse::<SignedRotation>()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Searching for keys whose ID52 starts with a chosen prefix
+
+use crate::errors::ParseId52Error;
+use crate::keys::SecretKey;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// The characters of BASE32_DNSSEC, the encoding of ID52
+const ALPHABET: &str = "0123456789abcdefghijklmnopqrstuv";
+
+/// How often [`SecretKey::generate_with_prefix`] reports progress
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Keys each thread tries between updates of the shared attempt counter
+const BATCH: u64 = 64;
+
+/// A prefix an ID52 can start with, for [`SecretKey::generate_with_prefix`]
+///
+/// Parsing checks every character is in the ID52 alphabet, `0-9` and `a-v`. Upper case is
+/// accepted and turned into lower case.
+///
+/// # Examples
+///
+/// ```
+/// use kulfi_id52::VanityPrefix;
+///
+/// let prefix: VanityPrefix = "Ab".parse().unwrap();
+/// assert_eq!(prefix.as_str(), "ab");
+/// assert_eq!(prefix.expected_attempts(), 1024.0);
+///
+/// // w, x, y and z are not in the alphabet
+/// assert!("wow".parse::<VanityPrefix>().is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VanityPrefix(String);
+
+impl VanityPrefix {
+    /// The prefix, in lower case
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// How many keys have to be tried on average to find a match, each character multiplies it
+    /// by 32
+    pub fn expected_attempts(&self) -> f64 {
+        32f64.powi(self.0.len() as i32)
+    }
+}
+
+impl std::str::FromStr for VanityPrefix {
+    type Err = ParseId52Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |reason: String| ParseId52Error {
+            input: s.to_string(),
+            reason,
+        };
+
+        let prefix = s.to_ascii_lowercase();
+        if prefix.is_empty() {
+            return Err(err("prefix is empty".to_string()));
+        }
+        if prefix.len() > 52 {
+            return Err(err(format!(
+                "prefix is {} characters, an ID52 has 52",
+                prefix.len()
+            )));
+        }
+        if let Some(c) = prefix.chars().find(|c| !ALPHABET.contains(*c)) {
+            return Err(err(format!(
+                "'{c}' can not appear in an ID52, only 0-9 and a-v can"
+            )));
+        }
+        Ok(VanityPrefix(prefix))
+    }
+}
+
+impl std::fmt::Display for VanityPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl SecretKey {
+    /// Generate keys on all cores till one has an ID52 starting with `prefix`
+    ///
+    /// This takes [`VanityPrefix::expected_attempts`] tries on average, about a million for 4
+    /// characters, and 32 times more for every character after. `on_progress` is called on the
+    /// calling thread every second with the number of keys tried so far.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kulfi_id52::SecretKey;
+    ///
+    /// let prefix = "k".parse().unwrap();
+    /// let secret_key = SecretKey::generate_with_prefix(&prefix, |_attempts| {});
+    /// assert!(secret_key.id52().starts_with('k'));
+    /// ```
+    pub fn generate_with_prefix(prefix: &VanityPrefix, mut on_progress: impl FnMut(u64)) -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let done = &AtomicBool::new(false);
+        let attempts = &AtomicU64::new(0);
+        let (found_tx, found_rx) = std::sync::mpsc::channel();
+
+        std::thread::scope(|s| {
+            for _ in 0..threads {
+                let found_tx = found_tx.clone();
+                s.spawn(move || search(prefix.as_str(), done, attempts, found_tx));
+            }
+            drop(found_tx);
+
+            loop {
+                match found_rx.recv_timeout(PROGRESS_INTERVAL) {
+                    Ok(secret_key) => {
+                        // the other threads stop at their next batch, the scope waits for them
+                        done.store(true, Ordering::Relaxed);
+                        return secret_key;
+                    }
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                        on_progress(attempts.load(Ordering::Relaxed))
+                    }
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                        unreachable!("threads only stop searching once a key is found")
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn search(
+    prefix: &str,
+    done: &AtomicBool,
+    attempts: &AtomicU64,
+    found: std::sync::mpsc::Sender<SecretKey>,
+) {
+    while !done.load(Ordering::Relaxed) {
+        for _ in 0..BATCH {
+            let secret_key = SecretKey::generate();
+            if secret_key.id52().starts_with(prefix) {
+                let _ = found.send(secret_key);
+                return;
+            }
+        }
+        attempts.fetch_add(BATCH, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prefix() {
+        assert_eq!("i66".parse::<VanityPrefix>().unwrap().as_str(), "i66");
+        assert_eq!("KULFI".parse::<VanityPrefix>().unwrap().as_str(), "kulfi");
+
+        assert!("".parse::<VanityPrefix>().is_err());
+        assert!("malai-".parse::<VanityPrefix>().is_err());
+        assert!("a".repeat(53).parse::<VanityPrefix>().is_err());
+
+        let e = "xyz".parse::<VanityPrefix>().err().unwrap();
+        assert!(e.to_string().contains("'x' can not appear in an ID52"));
+    }
+
+    #[test]
+    fn test_expected_attempts() {
+        let prefix: VanityPrefix = "abc".parse().unwrap();
+        assert_eq!(prefix.expected_attempts(), 32768.0);
+    }
+
+    #[test]
+    fn test_generate_with_prefix() {
+        let prefix: VanityPrefix = "v0".parse().unwrap();
+        let secret_key = SecretKey::generate_with_prefix(&prefix, |_| {});
+        assert!(secret_key.id52().starts_with("v0"));
+    }
+}
@@ -0,0 +1,398 @@
+//! the identity registry, `$kulfi/identities/<id52>/identity.json` for every identity created,
+//! imported or restored on this machine, and `$kulfi/default-identity` with the id52 of the
+//! default one.
+//!
+//! mostly the registry only records where the secret key is, the key itself stays in the keyring
+//! or the key file. on machines without a keyring, e.g., servers, the key can be kept next to the
+//! record, in `$kulfi/identities/<id52>/secret-key`. for keyring and key file identities the
+//! registry is bookkeeping: an identity missing from it still works, and failing to update it is
+//! only logged.
+
+use eyre::WrapErr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const IDENTITIES: &str = "identities";
+pub const IDENTITY_JSON: &str = "identity.json";
+pub const DEFAULT_IDENTITY: &str = "default-identity";
+pub const SECRET_KEY: &str = "secret-key";
+
+/// where the secret key of an identity is stored.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Storage {
+    /// the system keyring, under the id52
+    Keyring,
+    /// a key file, as written by `malai keygen --file`
+    File { path: PathBuf },
+    /// `identities/<id52>/secret-key` in the kulfi folder, see `save_secret_key()`
+    Folder,
+}
+
+impl std::fmt::Display for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Storage::Keyring => write!(f, "keyring"),
+            Storage::File { path } => write!(f, "file {}", path.display()),
+            Storage::Folder => write!(f, "kulfi folder"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdentityRecord {
+    pub id52: String,
+    /// a name to tell identities apart, e.g., the service using it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub storage: Storage,
+    /// the `.malai.id52` files pointing at this identity
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub id52_files: Vec<PathBuf>,
+}
+
+impl IdentityRecord {
+    /// reads the secret key from wherever `storage` says it is, `dir` is the kulfi folder the
+    /// record is from.
+    pub fn secret_key(&self, dir: &Path) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
+        let (id52, secret_key) = match &self.storage {
+            Storage::Keyring => crate::secret::handle_identity(self.id52.clone())?,
+            Storage::File { path } => crate::secret::read_secret_file(path)?,
+            Storage::Folder => crate::secret::read_secret_file(
+                &dir.join(IDENTITIES).join(&self.id52).join(SECRET_KEY),
+            )?,
+        };
+        if id52 != self.id52 {
+            return Err(eyre::anyhow!(
+                "the secret key stored for {} is of {id52}",
+                self.id52
+            ));
+        }
+        Ok((id52, secret_key))
+    }
+}
+
+/// adds an identity to the registry, or updates where it is stored if it is already there. the
+/// label and creation time of an existing record are kept, and `id52_file` is added to its list.
+pub fn record_identity(
+    dir: &Path,
+    id52: &str,
+    storage: Storage,
+    id52_file: Option<&Path>,
+) -> eyre::Result<IdentityRecord> {
+    let mut record = match read_record(dir, id52)? {
+        Some(record) => record,
+        None => IdentityRecord {
+            id52: id52.to_string(),
+            label: None,
+            created_at: chrono::Utc::now(),
+            storage: storage.clone(),
+            id52_files: vec![],
+        },
+    };
+    record.storage = storage;
+    if let Some(id52_file) = id52_file {
+        add_id52_file(&mut record, id52_file);
+    }
+    write_record(dir, &record)?;
+    Ok(record)
+}
+
+/// stores the secret key in the identity folder, encrypted if `passphrase` is given, and adds the
+/// identity to the registry. an existing secret key is never overwritten.
+pub fn save_secret_key(
+    dir: &Path,
+    secret_key: &kulfi_id52::SecretKey,
+    passphrase: Option<&str>,
+    id52_file: Option<&Path>,
+) -> eyre::Result<IdentityRecord> {
+    use std::io::Write;
+
+    let id52 = secret_key.id52();
+    let folder = crate::mkdir(&dir.join(IDENTITIES), &id52)?;
+    let path = folder.join(SECRET_KEY);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let content = match passphrase {
+        Some(passphrase) => secret_key.encrypt(passphrase),
+        None => secret_key.to_string(),
+    };
+    options
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{content}"))
+        .wrap_err_with(|| format!("failed to write {path:?}"))?;
+
+    record_identity(dir, &id52, Storage::Folder, id52_file)
+}
+
+/// the registry record of `id52`, if there is one.
+pub fn read_identity(dir: &Path, id52: &str) -> eyre::Result<Option<IdentityRecord>> {
+    read_record(dir, id52)
+}
+
+/// remembers that `id52_file` points at `id52`, if `id52` is in the registry.
+pub fn note_id52_file(dir: &Path, id52: &str, id52_file: &Path) -> eyre::Result<()> {
+    let Some(mut record) = read_record(dir, id52)? else {
+        return Ok(());
+    };
+    if add_id52_file(&mut record, id52_file) {
+        write_record(dir, &record)?;
+    }
+    Ok(())
+}
+
+/// all identities in the registry, oldest first.
+pub fn list_identities(dir: &Path) -> eyre::Result<Vec<IdentityRecord>> {
+    let identities = dir.join(IDENTITIES);
+    let entries = match std::fs::read_dir(&identities) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to read {identities:?}")),
+    };
+
+    let mut records = vec![];
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read {identities:?}"))?;
+        if !entry.path().join(IDENTITY_JSON).exists() {
+            continue;
+        }
+        match read_record(dir, &entry.file_name().to_string_lossy())? {
+            Some(record) => records.push(record),
+            None => continue,
+        }
+    }
+    records.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    Ok(records)
+}
+
+/// the identity with id52 or label `name`.
+pub fn find_identity(dir: &Path, name: &str) -> eyre::Result<IdentityRecord> {
+    if let Some(record) = read_record(dir, name)? {
+        return Ok(record);
+    }
+    list_identities(dir)?
+        .into_iter()
+        .find(|r| r.label.as_deref() == Some(name))
+        .ok_or_else(|| {
+            eyre::anyhow!("no identity with id52 or label `{name}`, see `malai identity list`")
+        })
+}
+
+/// sets, or with `None` removes, the label of an identity. labels are unique.
+pub fn set_label(dir: &Path, name: &str, label: Option<String>) -> eyre::Result<IdentityRecord> {
+    let mut record = find_identity(dir, name)?;
+    if let Some(label) = &label {
+        if label.trim().is_empty() || label.contains(char::is_control) {
+            return Err(eyre::anyhow!(
+                "label `{label}` is empty or has control characters"
+            ));
+        }
+        if kulfi_id52::PublicKey::from_str(label).is_ok() {
+            return Err(eyre::anyhow!("label `{label}` is an id52"));
+        }
+        if let Some(other) = list_identities(dir)?
+            .into_iter()
+            .find(|r| r.label.as_ref() == Some(label) && r.id52 != record.id52)
+        {
+            return Err(eyre::anyhow!(
+                "label `{label}` is already used by {}",
+                other.id52
+            ));
+        }
+    }
+    record.label = label;
+    write_record(dir, &record)?;
+    Ok(record)
+}
+
+/// removes an identity from the registry, e.g., once its keyring entry is deleted.
+pub fn forget_identity(dir: &Path, id52: &str) -> eyre::Result<()> {
+    if kulfi_id52::PublicKey::from_str(id52).is_err() {
+        return Ok(());
+    }
+    let path = dir.join(IDENTITIES).join(id52);
+    match std::fs::remove_dir_all(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to remove {path:?}")),
+    }
+    if default_identity(dir)?.as_deref() == Some(id52) {
+        let path = dir.join(DEFAULT_IDENTITY);
+        std::fs::remove_file(&path).wrap_err_with(|| format!("failed to remove {path:?}"))?;
+    }
+    Ok(())
+}
+
+/// the id52 of the default identity, if one is set.
+pub fn default_identity(dir: &Path) -> eyre::Result<Option<String>> {
+    let path = dir.join(DEFAULT_IDENTITY);
+    match std::fs::read_to_string(&path) {
+        Ok(id52) => Ok(Some(id52.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).wrap_err_with(|| format!("failed to read {path:?}")),
+    }
+}
+
+/// makes the identity with id52 or label `name` the default one.
+pub fn set_default_identity(dir: &Path, name: &str) -> eyre::Result<IdentityRecord> {
+    let record = find_identity(dir, name)?;
+    write_atomic(&dir.join(DEFAULT_IDENTITY), record.id52.as_bytes())?;
+    Ok(record)
+}
+
+fn read_record(dir: &Path, id52: &str) -> eyre::Result<Option<IdentityRecord>> {
+    // anything else could be a path, e.g., `../..`
+    if kulfi_id52::PublicKey::from_str(id52).is_err() {
+        return Ok(None);
+    }
+    let path = dir.join(IDENTITIES).join(id52).join(IDENTITY_JSON);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to read {path:?}")),
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .wrap_err_with(|| format!("failed to parse {path:?}"))
+}
+
+fn write_record(dir: &Path, record: &IdentityRecord) -> eyre::Result<()> {
+    let folder = crate::mkdir(&dir.join(IDENTITIES), &record.id52)?;
+    let content = serde_json::to_vec_pretty(record)?;
+    write_atomic(&folder.join(IDENTITY_JSON), &content)
+}
+
+/// writes to a temporary file first, so a crash never leaves a half written file behind.
+fn write_atomic(path: &Path, content: &[u8]) -> eyre::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).wrap_err_with(|| format!("failed to write {tmp:?}"))?;
+    std::fs::rename(&tmp, path).wrap_err_with(|| format!("failed to write {path:?}"))
+}
+
+/// returns true if the file was not in the list yet.
+fn add_id52_file(record: &mut IdentityRecord, id52_file: &Path) -> bool {
+    let id52_file = std::path::absolute(id52_file).unwrap_or_else(|_| id52_file.to_path_buf());
+    if record.id52_files.contains(&id52_file) {
+        return false;
+    }
+    record.id52_files.push(id52_file);
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::Storage;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("kulfi-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn registry() {
+        let dir = temp_dir("registry");
+        let first = kulfi_id52::SecretKey::generate().id52();
+        let second = kulfi_id52::SecretKey::generate().id52();
+
+        assert!(super::list_identities(&dir).unwrap().is_empty());
+
+        super::record_identity(&dir, &first, Storage::Keyring, None).unwrap();
+        let key_file = dir.join("second.key");
+        super::record_identity(
+            &dir,
+            &second,
+            Storage::File {
+                path: key_file.clone(),
+            },
+            Some(std::path::Path::new("web/.malai.id52")),
+        )
+        .unwrap();
+
+        let list = super::list_identities(&dir).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id52, first);
+        assert_eq!(list[1].storage, Storage::File { path: key_file });
+        assert!(list[1].id52_files[0].is_absolute());
+        assert!(list[1].id52_files[0].ends_with("web/.malai.id52"));
+
+        // labels are unique, and identities can be found by them
+        super::set_label(&dir, &first, Some("web".to_string())).unwrap();
+        assert!(super::set_label(&dir, &second, Some("web".to_string())).is_err());
+        assert!(super::set_label(&dir, &second, Some(first.clone())).is_err());
+        assert_eq!(super::find_identity(&dir, "web").unwrap().id52, first);
+        assert!(super::find_identity(&dir, "api").is_err());
+        assert!(super::find_identity(&dir, "../..").is_err());
+        super::forget_identity(&dir, "..").unwrap();
+        assert!(dir.exists());
+
+        // recording again keeps the label, and notes more id52 files
+        let record = super::record_identity(&dir, &first, Storage::Keyring, None).unwrap();
+        assert_eq!(record.label.as_deref(), Some("web"));
+        super::note_id52_file(&dir, &first, std::path::Path::new("/srv/web/.malai.id52")).unwrap();
+        super::note_id52_file(&dir, &first, std::path::Path::new("/srv/web/.malai.id52")).unwrap();
+        assert_eq!(
+            super::find_identity(&dir, "web").unwrap().id52_files.len(),
+            1
+        );
+
+        assert_eq!(super::default_identity(&dir).unwrap(), None);
+        super::set_default_identity(&dir, "web").unwrap();
+        assert_eq!(super::default_identity(&dir).unwrap(), Some(first.clone()));
+
+        super::forget_identity(&dir, &first).unwrap();
+        assert_eq!(super::default_identity(&dir).unwrap(), None);
+        assert_eq!(super::list_identities(&dir).unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn secret_key_in_folder() {
+        let dir = temp_dir("folder");
+        let secret_key = kulfi_id52::SecretKey::generate();
+        let id52 = secret_key.id52();
+
+        let record = super::save_secret_key(&dir, &secret_key, None, None).unwrap();
+        assert_eq!(record.storage, Storage::Folder);
+        assert!(super::save_secret_key(&dir, &secret_key, None, None).is_err());
+
+        let (loaded, loaded_key) = record.secret_key(&dir).unwrap();
+        assert_eq!(loaded, id52);
+        assert_eq!(loaded_key.to_bytes(), secret_key.to_bytes());
+        assert_eq!(
+            crate::secret::get_secret_key(&id52, dir.to_str().unwrap())
+                .unwrap()
+                .to_bytes(),
+            secret_key.to_bytes()
+        );
+
+        // a record pointing at someone else's key is refused
+        let other = kulfi_id52::SecretKey::generate().id52();
+        let mut wrong = record.clone();
+        wrong.id52 = other.clone();
+        std::fs::create_dir_all(dir.join(super::IDENTITIES).join(&other)).unwrap();
+        std::fs::copy(
+            dir.join(super::IDENTITIES)
+                .join(&id52)
+                .join(super::SECRET_KEY),
+            dir.join(super::IDENTITIES)
+                .join(&other)
+                .join(super::SECRET_KEY),
+        )
+        .unwrap();
+        assert!(wrong.secret_key(&dir).is_err());
+
+        super::forget_identity(&dir, &id52).unwrap();
+        assert!(!dir.join(super::IDENTITIES).join(&id52).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
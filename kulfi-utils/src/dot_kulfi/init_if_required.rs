@@ -1,9 +1,15 @@
-/// this function is called on startup, and initializes the kulfi directory if it doesn't exist
+/// the kulfi directory, locked by this process for as long as this is alive.
+#[derive(Debug)]
+pub struct DataDir {
+    pub path: std::path::PathBuf,
+    _lock: file_guard::FileGuard<Box<std::fs::File>>,
+}
+
+/// this function is called on startup, and initializes the kulfi directory if it doesn't exist.
+/// it then takes the exclusive lock on `lock`, `kulfi.lock` or `malai.lock`, so two instances do
+/// not use the same directory, and fails if another instance holds it.
 #[tracing::instrument]
-pub async fn init_if_required(
-    dir: &std::path::Path,
-    // client_pools: kulfi_utils::HttpConnectionPools,
-) -> eyre::Result<std::path::PathBuf> {
+pub async fn init_if_required(dir: &std::path::Path, lock: &str) -> eyre::Result<DataDir> {
     use eyre::WrapErr;
 
     if !dir.exists() {
@@ -17,14 +23,43 @@ pub async fn init_if_required(
         tokio::fs::create_dir_all(&dir)
             .await
             .wrap_err_with(|| format!("failed to create dot_kulfi directory: {dir:?}"))?;
-        // let identities = kulfi_utils::mkdir(dir, "identities")?;
-        // kulfi_utils::mkdir(dir, "logs")?;
-        //
-        // super::lock_file(dir).wrap_err_with(|| "failed to create lock file")?;
-
-        // // we always create the default identity
-        // kulfi::Identity::create(&identities, client_pools).await?;
     }
 
-    Ok(dir.to_path_buf())
+    // the identity registry may have created the directory already, so these are always checked
+    crate::mkdir(dir, super::IDENTITIES)?;
+    crate::mkdir(dir, super::LOGS)?;
+
+    let lock_file = Box::new(super::lock_file(dir, lock)?);
+    let lock = super::exclusive(lock_file).await.wrap_err_with(|| {
+        format!(
+            "{dir:?} is in use by another instance, set {} to use a different directory",
+            super::DATA_DIR_ENV_VAR
+        )
+    })?;
+
+    Ok(DataDir {
+        path: dir.to_path_buf(),
+        _lock: lock,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    #[tokio::test]
+    async fn layout() {
+        let dir = std::env::temp_dir().join(format!("kulfi-layout-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // the lock is per process, other processes are kept out, this one is not
+        let data_dir = super::init_if_required(&dir, super::super::MALAI_LOCK)
+            .await
+            .unwrap();
+        assert_eq!(data_dir.path, dir);
+        assert!(dir.join(super::super::IDENTITIES).is_dir());
+        assert!(dir.join(super::super::LOGS).is_dir());
+        assert!(dir.join(super::super::MALAI_LOCK).is_file());
+
+        drop(data_dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -6,17 +6,44 @@
 //! C:\Users\Alice\AppData\Roaming\FifthTry\kulfi\data.
 //!
 //! The folder contains a lock file, `$kulfi/kulfi.lock, which is used to ensure only one instance
-//! of `kulfi` is running.
+//! of `kulfi` is running, and `$kulfi/malai.lock` for `malai run`. They are taken by
+//! `init_if_required()`.
 //!
 //! The folder contains more folders like `identities`, `logs` and maybe `config.json` etc. in
-//! the future.
+//! the future. `logs` is for the log files of long running instances.
 //!
 //! The identities folder is the most interesting one, it contains one folder for every identity
 //! that exists on this machine. The content of single `identity` folder is described
-//! in `identity/create.rs`.
+//! in `identities.rs`.
+//!
+//! The `rotations` folder has the identity rotations this machine follows, see `rotations.rs`.
+//!
+//! `$KULFI_DATA_DIR` overrides the location, e.g., for a service with its own state directory.
 
+mod identities;
 mod init_if_required;
 mod lock;
+mod rotations;
+
+pub use identities::{
+    DEFAULT_IDENTITY, IDENTITIES, IDENTITY_JSON, IdentityRecord, SECRET_KEY, Storage,
+    default_identity, find_identity, forget_identity, list_identities, note_id52_file,
+    read_identity, record_identity, save_secret_key, set_default_identity, set_label,
+};
+pub use init_if_required::{DataDir, init_if_required};
+pub use lock::{KULFI_LOCK, MALAI_LOCK, exclusive, kulfi_lock_file, lock_file, malai_lock_file};
+pub use rotations::{ROTATIONS, read_rotation, resolve_rotation, save_rotation};
+
+pub const LOGS: &str = "logs";
+
+pub const DATA_DIR_ENV_VAR: &str = "KULFI_DATA_DIR";
 
-pub use init_if_required::init_if_required;
-pub use lock::{KULFI_LOCK, MALAI_LOCK, exclusive, kulfi_lock_file, malai_lock_file};
+/// the kulfi folder, `$KULFI_DATA_DIR` if set, else the platform specific location above.
+pub fn dir() -> eyre::Result<std::path::PathBuf> {
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV_VAR) {
+        return Ok(dir.into());
+    }
+    directories::ProjectDirs::from("com", "FifthTry", "kulfi")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .ok_or_else(|| eyre::anyhow!("failed to find the home directory, set {DATA_DIR_ENV_VAR}"))
+}
@@ -0,0 +1,150 @@
+//! the identity rotations this machine knows about, `$kulfi/rotations/<old id52>`, each a
+//! `kulfi_id52::SignedRotation` document. connections to an id52 with an active rotation go to the
+//! id52 it was rotated to instead, see `follow_rotation()`.
+//!
+//! there is at most one rotation per id52. a different one for the same id52 means its key is
+//! in someone else's hands too, and is refused, see `SignedRotation` for why.
+
+use eyre::WrapErr;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const ROTATIONS: &str = "rotations";
+
+/// rotations followed in a row at most, a longer chain is most likely a loop.
+const MAX_ROTATIONS: usize = 8;
+
+/// stores `rotation`, returns false if it was already stored. fails if a different rotation of the
+/// same id52 is stored.
+pub fn save_rotation(dir: &Path, rotation: &kulfi_id52::SignedRotation) -> eyre::Result<bool> {
+    let old = rotation.old_key().to_string();
+    if let Some(existing) = read_rotation(dir, &old)? {
+        if &existing == rotation {
+            return Ok(false);
+        }
+        return Err(eyre::anyhow!(
+            "{old} is already rotated to {}, a second rotation to {} means its secret key has \
+             leaked, so neither is followed automatically. remove {:?} if the stored one is wrong",
+            existing.new_key(),
+            rotation.new_key(),
+            dir.join(ROTATIONS).join(&old)
+        ));
+    }
+
+    let folder = crate::mkdir(dir, ROTATIONS)?;
+    let path = folder.join(&old);
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, rotation.to_string())
+        .wrap_err_with(|| format!("failed to write {tmp:?}"))?;
+    std::fs::rename(&tmp, &path).wrap_err_with(|| format!("failed to write {path:?}"))?;
+    Ok(true)
+}
+
+/// the rotation of `id52`, whether it is active yet or not.
+pub fn read_rotation(dir: &Path, id52: &str) -> eyre::Result<Option<kulfi_id52::SignedRotation>> {
+    // anything else could be a path, e.g., `../..`
+    if kulfi_id52::PublicKey::from_str(id52).is_err() {
+        return Ok(None);
+    }
+    let path = dir.join(ROTATIONS).join(id52);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to read {path:?}")),
+    };
+    let rotation: kulfi_id52::SignedRotation = content
+        .parse()
+        .wrap_err_with(|| format!("failed to parse {path:?}"))?;
+    if rotation.old_key().to_string() != id52 {
+        return Err(eyre::anyhow!(
+            "{path:?} is the rotation of {}",
+            rotation.old_key()
+        ));
+    }
+    Ok(Some(rotation))
+}
+
+/// the id52 `id52` is rotated to, following rotations of rotations, or `id52` itself. rotations
+/// that are not active at `now`, unix time in seconds, are not followed.
+pub fn resolve_rotation(dir: &Path, id52: &str, now: u64) -> eyre::Result<String> {
+    let mut current = id52.to_string();
+    for _ in 0..MAX_ROTATIONS {
+        match read_rotation(dir, &current)? {
+            Some(rotation) if rotation.is_active(now) => current = rotation.new_key().to_string(),
+            _ => return Ok(current),
+        }
+        if current == id52 {
+            return Err(eyre::anyhow!("the rotations of {id52} lead back to it"));
+        }
+    }
+    Err(eyre::anyhow!(
+        "{id52} is rotated more than {MAX_ROTATIONS} times in a row, likely a loop"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    fn rotate(
+        from: &kulfi_id52::SecretKey,
+        to: &kulfi_id52::SecretKey,
+        since: u64,
+    ) -> kulfi_id52::SignedRotation {
+        kulfi_id52::Rotation {
+            old: from.public_key(),
+            new: to.public_key(),
+            since,
+        }
+        .sign(from, to)
+        .unwrap()
+    }
+
+    #[test]
+    fn rotations() {
+        let dir = std::env::temp_dir().join(format!("kulfi-rotations-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let [a, b, c, d] = std::array::from_fn(|_| kulfi_id52::SecretKey::generate());
+
+        assert_eq!(
+            super::resolve_rotation(&dir, &a.id52(), 100).unwrap(),
+            a.id52()
+        );
+
+        assert!(super::save_rotation(&dir, &rotate(&a, &b, 10)).unwrap());
+        assert!(!super::save_rotation(&dir, &rotate(&a, &b, 10)).unwrap());
+        // a second rotation of the same id52 is refused
+        assert!(super::save_rotation(&dir, &rotate(&a, &c, 10)).is_err());
+
+        // chains are followed, but only as far as they are active
+        super::save_rotation(&dir, &rotate(&b, &c, 50)).unwrap();
+        assert_eq!(
+            super::resolve_rotation(&dir, &a.id52(), 5).unwrap(),
+            a.id52()
+        );
+        assert_eq!(
+            super::resolve_rotation(&dir, &a.id52(), 20).unwrap(),
+            b.id52()
+        );
+        assert_eq!(
+            super::resolve_rotation(&dir, &a.id52(), 50).unwrap(),
+            c.id52()
+        );
+
+        super::save_rotation(&dir, &rotate(&c, &a, 60)).unwrap();
+        assert!(super::resolve_rotation(&dir, &a.id52(), 100).is_err());
+        assert_eq!(
+            super::resolve_rotation(&dir, &d.id52(), 100).unwrap(),
+            d.id52()
+        );
+
+        // a file under the wrong name is not trusted
+        std::fs::copy(
+            dir.join(super::ROTATIONS).join(a.id52()),
+            dir.join(super::ROTATIONS).join(d.id52()),
+        )
+        .unwrap();
+        assert!(super::read_rotation(&dir, &d.id52()).is_err());
+        assert!(super::read_rotation(&dir, "../..").unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -159,6 +159,9 @@ async fn connection_manager_(
     remote_node_id52: RemoteID52,
     graceful: crate::Graceful,
 ) -> eyre::Result<()> {
+    // connections to a rotated id52 go to the one it was rotated to
+    let remote_node_id52 = crate::follow_rotation(&remote_node_id52);
+
     // Convert ID52 to iroh::EndpointId
     let remote_endpoint_id = {
         use std::str::FromStr;
@@ -174,42 +177,18 @@ async fn connection_manager_(
         Ok(v) => v,
         Err(e) => {
             tracing::error!("failed to create connection: {e:?}");
+            crate::metrics::inc(crate::metrics::CONNECTION_ERRORS, &[("stage", "connect")]);
             return Err(eyre::anyhow!("failed to create connection: {e:?}"));
         }
     };
 
-    // Spawn a task that watches and logs connection type changes (relay vs direct)
-    if let Some(mut conn_type_watcher) = self_endpoint.conn_type(remote_endpoint_id) {
-        use iroh::Watcher;
-        let remote = remote_node_id52.clone();
-        let graceful_clone = graceful.clone();
-        graceful.spawn(async move {
-            tracing::info!(
-                remote = %remote,
-                conn_type = ?conn_type_watcher.get(),
-                "initial connection type"
-            );
-            use futures_util::StreamExt;
-            let mut stream = conn_type_watcher.stream_updates_only();
-            loop {
-                tokio::select! {
-                    _ = graceful_clone.cancelled() => break,
-                    item = stream.next() => {
-                        match item {
-                            Some(conn_type) => {
-                                tracing::info!(
-                                    remote = %remote,
-                                    conn_type = ?conn_type,
-                                    "connection type changed"
-                                );
-                            }
-                            None => break,
-                        }
-                    }
-                }
-            }
-        });
-    }
+    let _connection_gauge = crate::metrics::track(
+        crate::metrics::PEER_CONNECTIONS,
+        &[("peer", &remote_node_id52), ("direction", "outgoing")],
+    );
+
+    let _conn_type_watcher =
+        crate::watch_conn_type(&self_endpoint, remote_endpoint_id, remote_node_id52.clone());
 
     let timeout = std::time::Duration::from_secs(12);
     let mut idle_counter = 0;
@@ -232,6 +211,7 @@ async fn connection_manager_(
                 tracing::info!("woken up");
                 if let Err(e) = crate::ping(&conn).await {
                     tracing::error!("pinging failed: {e:?}");
+                    crate::metrics::inc(crate::metrics::CONNECTION_ERRORS, &[("stage", "ping")]);
                     break;
                 }
                 idle_counter += 1;
@@ -273,6 +253,7 @@ async fn connection_manager_(
                 // this in future when we are performance optimising things.
                 if let Err(e) = handle_request(&conn, header, reply_channel).await {
                     tracing::error!("failed to handle request: {e:?}");
+                    crate::metrics::inc(crate::metrics::CONNECTION_ERRORS, &[("stage", "stream")]);
                     // note: we are intentionally not calling conn.close(). why? so that if some existing
                     // stream is still open, if we explicitly call close on the connection, that stream will
                     // immediately fail as well, and we do not want that. we want to let the stream fail
@@ -300,6 +281,8 @@ async fn handle_request(
 
     tracing::trace!("handling request: {header:?}");
 
+    let start = std::time::Instant::now();
+    let protocol = crate::metrics::protocol_label(&header.protocol);
     let (mut send, mut recv) = match conn.open_bi().await {
         Ok(v) => {
             tracing::trace!("opened bi-stream");
@@ -333,7 +316,19 @@ async fn handle_request(
             .wrap_err_with(|| "failed to write newline")?;
     }
 
-    let msg = crate::next_string(&mut recv).await?;
+    let mut msg = crate::next_string(&mut recv).await?;
+
+    if msg == crate::TOKEN_REQUIRED {
+        // without a token we send an empty line, and let the server say no in its own way
+        if header.token.is_none() {
+            tracing::warn!("the service requires an access token, we have none");
+        }
+        send.write_all(format!("{}\n", header.token.unwrap_or_default()).as_bytes())
+            .await
+            .wrap_err_with(|| "failed to write access token")?;
+        tracing::trace!("wrote access token");
+        msg = crate::next_string(&mut recv).await?;
+    }
 
     if msg != crate::ACK {
         tracing::error!("failed to read ack: {msg:?}");
@@ -341,6 +336,15 @@ async fn handle_request(
     }
 
     tracing::trace!("received ack");
+    crate::metrics::observe(
+        crate::metrics::STREAM_ACK_DURATION,
+        &[("protocol", &protocol)],
+        start.elapsed(),
+    );
+    crate::metrics::inc(
+        crate::metrics::STREAMS,
+        &[("protocol", &protocol), ("direction", "outgoing")],
+    );
 
     reply_channel.send(Ok((send, recv))).unwrap_or_else(|e| {
         tracing::error!("failed to send reply: {e:?}");
@@ -7,8 +7,45 @@ pub struct Graceful {
     tracker: tokio_util::task::TaskTracker,
     show_info_tx: tokio::sync::watch::Sender<bool>,
     show_info_rx: tokio::sync::watch::Receiver<bool>,
+    drain_timeout: std::time::Duration,
+    mode: ShutdownMode,
 }
 
+/// what `Graceful::shutdown()` does on ctrl-c.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// the first ctrl-c shows info, a second one within 3 seconds drains. when stdin is not a
+    /// terminal, e.g., under systemd or docker, the first one drains.
+    ///
+    /// this is the default on purpose: someone at a terminal running `malai expose-http` wants the
+    /// info again more often than a way to send SIGUSR1, and scripts, tests and supervisors have no
+    /// terminal, or can pick `Drain` or `Immediate`. SIGUSR1 shows info in every mode.
+    #[default]
+    Interactive,
+    /// cancels all tasks and returns without waiting for them.
+    Immediate,
+    /// cancels all tasks and waits up to the drain timeout for them to exit.
+    Drain,
+}
+
+impl std::str::FromStr for ShutdownMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interactive" => Ok(ShutdownMode::Interactive),
+            "immediate" => Ok(ShutdownMode::Immediate),
+            "drain" => Ok(ShutdownMode::Drain),
+            _ => Err(format!(
+                "unknown shutdown mode `{s}`, expected interactive, immediate or drain"
+            )),
+        }
+    }
+}
+
+/// how long `shutdown()` waits for tasks to exit, unless changed with `with_drain_timeout()`.
+pub const DEFAULT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
 impl Default for Graceful {
     fn default() -> Self {
         Self::new()
@@ -24,6 +61,26 @@ impl Graceful {
             tracker: tokio_util::task::TaskTracker::new(),
             show_info_tx,
             show_info_rx,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            mode: ShutdownMode::default(),
+        }
+    }
+
+    pub fn with_shutdown_mode(mut self, mode: ShutdownMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// how long `shutdown()` waits for pending tasks before giving up on them.
+    pub fn with_drain_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// asks everyone waiting in `show_info()` to print their info, SIGUSR1 does the same.
+    pub fn trigger_show_info(&self) {
+        if let Err(e) = self.show_info_tx.send(true) {
+            tracing::error!("failed to send show info signal: {e:?}");
         }
     }
 
@@ -44,49 +101,96 @@ impl Graceful {
         self.tracker.spawn(task)
     }
 
+    /// waits for a signal, or `cancel()`, and then stops everything as per the shutdown mode.
+    ///
+    /// SIGTERM and `cancel()` always drain, ctrl-c depends on the mode, and SIGUSR1 shows info
+    /// without stopping anything.
     pub async fn shutdown(&self) -> eyre::Result<()> {
+        use std::io::IsTerminal;
+
+        let mut signals = Signals::new()?;
         loop {
-            tokio::signal::ctrl_c()
-                .await
-                .wrap_err_with(|| "failed to get ctrl-c signal handler")?;
+            tokio::select! {
+                _ = signals.ctrl_c.recv() => {}
+                _ = signals.terminate.recv() => {
+                    tracing::info!("Received SIGTERM, shutting down.");
+                    return self.drain().await;
+                }
+                _ = signals.user_defined1.recv() => {
+                    tracing::debug!("Received SIGUSR1, showing info.");
+                    self.trigger_show_info();
+                    continue;
+                }
+                _ = self.cancel.cancelled() => {
+                    tracing::info!("Cancelled, shutting down.");
+                    return self.drain().await;
+                }
+            }
+
+            match self.mode {
+                ShutdownMode::Immediate => {
+                    tracing::info!("Received ctrl-c, shutting down without waiting.");
+                    self.cancel.cancel();
+                    self.tracker.close();
+                    return Ok(());
+                }
+                ShutdownMode::Drain => {
+                    tracing::info!("Received ctrl-c, shutting down.");
+                    return self.drain().await;
+                }
+                ShutdownMode::Interactive if !std::io::stdin().is_terminal() => {
+                    tracing::info!("Received ctrl-c, shutting down.");
+                    return self.drain().await;
+                }
+                ShutdownMode::Interactive => {}
+            }
 
             tracing::debug!("Received ctrl-c signal, showing info.");
             tracing::debug!("Pending tasks: {}", self.tracker.len());
-
-            self.show_info_tx
-                .send(true)
-                .inspect_err(|e| tracing::error!("failed to send show info signal: {e:?}"))?;
+            self.trigger_show_info();
 
             tokio::select! {
-                _ = tokio::signal::ctrl_c() => {
+                _ = signals.ctrl_c.recv() => {
                     tracing::info!("Received second ctrl-c signal, shutting down.");
-                    tracing::debug!("Pending tasks: {}", self.tracker.len());
+                    return self.drain().await;
+                }
+                _ = signals.terminate.recv() => {
+                    tracing::info!("Received SIGTERM, shutting down.");
+                    return self.drain().await;
+                }
+                _ = self.cancel.cancelled() => {
+                    tracing::info!("Cancelled, shutting down.");
+                    return self.drain().await;
+                }
+                _ = tokio::time::sleep(std::time::Duration::from_secs(3)) => {
+                    tracing::debug!("Timeout expired. Continuing...");
+                    println!("Did not receive ctrl+c within 3 secs. Press ctrl+c in quick succession to exit.");
+                }
+            }
+        }
+    }
 
-                    self.cancel.cancel();
-                    self.tracker.close();
+    /// cancels all tasks, and waits up to the drain timeout for them to exit.
+    async fn drain(&self) -> eyre::Result<()> {
+        crate::systemd::notify_stopping();
+        tracing::debug!("Pending tasks: {}", self.tracker.len());
+
+        self.cancel.cancel();
+        self.tracker.close();
 
-                    let mut count = 0;
-                    loop {
-                        tokio::select! {
-                            _ = self.tracker.wait() => {
-                                tracing::info!("All tasks have exited.");
-                                break;
-                            }
-                            _ = tokio::time::sleep(std::time::Duration::from_secs(3)) => {
-                                count += 1;
-                                if count > 10 {
-                                    eprintln!("Timeout expired, {} pending tasks. Exiting...", self.tracker.len());
-                                    break;
-                                }
-                                tracing::debug!("Pending tasks: {}", self.tracker.len());
-                            }
-                        }
-                    }
+        let deadline = tokio::time::Instant::now() + self.drain_timeout;
+        loop {
+            tokio::select! {
+                _ = self.tracker.wait() => {
+                    tracing::info!("All tasks have exited.");
+                    break;
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    eprintln!("Timeout expired, {} pending tasks. Exiting...", self.tracker.len());
                     break;
                 }
                 _ = tokio::time::sleep(std::time::Duration::from_secs(3)) => {
-                    tracing::debug!("Timeout expired. Continuing...");
-                    println!("Did not receive ctrl+c within 3 secs. Press ctrl+c in quick succession to exit.");
+                    tracing::debug!("Pending tasks: {}", self.tracker.len());
                 }
             }
         }
@@ -94,7 +198,142 @@ impl Graceful {
         Ok(())
     }
 
+    /// a graceful that is also cancelled when this one is, but can be cancelled on its own, e.g.,
+    /// to stop one service out of many. its tasks are still waited for on shutdown.
+    pub fn child(&self) -> Self {
+        Self {
+            cancel: self.cancel.child_token(),
+            tracker: self.tracker.clone(),
+            show_info_tx: self.show_info_tx.clone(),
+            show_info_rx: self.show_info_rx.clone(),
+            drain_timeout: self.drain_timeout,
+            mode: self.mode,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
     pub fn cancelled(&self) -> tokio_util::sync::WaitForCancellationFuture<'_> {
         self.cancel.cancelled()
     }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+}
+
+/// the signals `shutdown()` waits for. they are set up once, so one that arrives while
+/// `shutdown()` is busy with another, e.g., showing info, is not missed.
+struct Signals {
+    ctrl_c: Signal,
+    terminate: Signal,
+    user_defined1: Signal,
+}
+
+#[cfg(unix)]
+use tokio::signal::unix::Signal;
+
+impl Signals {
+    #[cfg(unix)]
+    fn new() -> eyre::Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        Ok(Self {
+            ctrl_c: signal(SignalKind::interrupt())
+                .wrap_err_with(|| "failed to get ctrl-c signal handler")?,
+            terminate: signal(SignalKind::terminate())
+                .wrap_err_with(|| "failed to get SIGTERM signal handler")?,
+            user_defined1: signal(SignalKind::user_defined1())
+                .wrap_err_with(|| "failed to get SIGUSR1 signal handler")?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> eyre::Result<Self> {
+        Ok(Self {
+            ctrl_c: Signal::CtrlC(
+                tokio::signal::windows::ctrl_c()
+                    .wrap_err_with(|| "failed to get ctrl-c signal handler")?,
+            ),
+            terminate: Signal::Never,
+            user_defined1: Signal::Never,
+        })
+    }
+}
+
+/// there is no SIGTERM or SIGUSR1 on windows.
+#[cfg(not(unix))]
+enum Signal {
+    CtrlC(tokio::signal::windows::CtrlC),
+    Never,
+}
+
+#[cfg(not(unix))]
+impl Signal {
+    async fn recv(&mut self) -> Option<()> {
+        match self {
+            Signal::CtrlC(ctrl_c) => ctrl_c.recv().await,
+            Signal::Never => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShutdownMode;
+
+    fn slow_task(graceful: &super::Graceful, done: std::sync::Arc<std::sync::atomic::AtomicBool>) {
+        let graceful_for_task = graceful.clone();
+        graceful.spawn(async move {
+            graceful_for_task.cancelled().await;
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            done.store(true, std::sync::atomic::Ordering::SeqCst);
+        });
+    }
+
+    #[tokio::test]
+    async fn cancel_drains() {
+        let graceful = super::Graceful::new().with_shutdown_mode(ShutdownMode::Drain);
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        slow_task(&graceful, done.clone());
+
+        graceful.child().cancel();
+        assert!(!graceful.is_cancelled());
+
+        graceful.cancel();
+        graceful.shutdown().await.unwrap();
+        assert!(done.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn drain_timeout() {
+        let graceful =
+            super::Graceful::new().with_drain_timeout(std::time::Duration::from_millis(10));
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        slow_task(&graceful, done.clone());
+
+        graceful.cancel();
+        graceful.shutdown().await.unwrap();
+        assert!(!done.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn trigger_show_info() {
+        let graceful = super::Graceful::new();
+        let mut waiting = graceful.child();
+        graceful.trigger_show_info();
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiting.show_info())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn parse_mode() {
+        assert_eq!("drain".parse(), Ok(ShutdownMode::Drain));
+        assert_eq!("immediate".parse(), Ok(ShutdownMode::Immediate));
+        assert!("later".parse::<ShutdownMode>().is_err());
+    }
 }
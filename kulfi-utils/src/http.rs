@@ -24,7 +24,7 @@ impl From<hyper::http::request::Parts> for Request {
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct Response {
     pub status: u16,
     pub headers: Vec<(String, Vec<u8>)>,
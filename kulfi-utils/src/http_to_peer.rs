@@ -10,6 +10,7 @@ pub async fn http_to_peer(
     use http_body_util::BodyExt;
 
     tracing::debug!("peer_proxy: {remote_node_id52}");
+    let start = std::time::Instant::now();
 
     let (mut send, mut recv) = crate::get_stream(
         self_endpoint,
@@ -37,6 +38,11 @@ pub async fn http_to_peer(
                     .ok_or_else(|| eyre::anyhow!("chunk data is None"))?;
                 tracing::trace!("sending chunk of size: {}", data.len());
                 send.write_all(data).await?;
+                crate::metrics::add(
+                    crate::metrics::SERVICE_BYTES,
+                    &[("service", remote_node_id52), ("direction", "out")],
+                    data.len() as f64,
+                );
             }
             Err(e) => {
                 tracing::error!("error reading chunk: {e:?}");
@@ -50,15 +56,27 @@ pub async fn http_to_peer(
     let r: crate::http::Response = crate::next_json(&mut recv).await?;
 
     tracing::debug!("got response header: {:?}", r);
+    // the body is streamed after we return, so this is the time to the response head
+    crate::metrics::observe(
+        crate::metrics::REQUEST_DURATION,
+        &[("service", remote_node_id52)],
+        start.elapsed(),
+    );
 
     let stream = tokio_util::io::ReaderStream::new(recv);
 
     use futures_util::TryStreamExt;
 
+    let service = remote_node_id52.to_string();
     let stream_body = http_body_util::StreamBody::new(
         stream
-            .map_ok(|b| {
+            .map_ok(move |b| {
                 tracing::trace!("got chunk of size: {}", b.len());
+                crate::metrics::add(
+                    crate::metrics::SERVICE_BYTES,
+                    &[("service", &service), ("direction", "in")],
+                    b.len() as f64,
+                );
                 hyper::body::Frame::data(b)
             })
             .map_err(|e| {
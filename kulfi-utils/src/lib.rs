@@ -7,34 +7,42 @@ mod graceful;
 pub mod http;
 mod http_connection_manager;
 mod http_to_peer;
+pub mod metrics;
 mod peer_to_http;
 mod ping;
 pub mod protocol;
+mod rotation;
 pub mod secret;
+pub mod systemd;
 mod tcp;
+mod token;
 mod udp;
 mod utils;
 mod utils_iroh;
 
 pub use get_endpoint::get_endpoint;
 pub use get_stream::{PeerStreamSenders, get_stream};
-pub use graceful::Graceful;
+pub use graceful::{DEFAULT_DRAIN_TIMEOUT, Graceful, ShutdownMode};
 pub use http::ProxyResult;
 pub use http_connection_manager::{HttpConnectionManager, HttpConnectionPool, HttpConnectionPools};
 pub use http_to_peer::{http_to_peer, http_to_peer_non_streaming};
 pub use peer_to_http::peer_to_http;
 pub use ping::{PONG, ping};
 pub use protocol::{APNS_IDENTITY, Protocol, ProtocolHeader};
+pub use rotation::{fetch_rotation, follow_rotation};
 pub use secret::{
     ID52_FILE, SECRET_KEY_FILE, generate_and_save_key, generate_secret_key, get_secret_key,
-    read_or_create_key,
+    read_key, read_or_create_key,
 };
 pub use tcp::{peer_to_tcp, pipe_tcp_stream_over_iroh, tcp_to_peer};
-pub use udp::{UdpToPeerParams, peer_to_udp, read_framed_datagram, udp_to_peer, write_framed_datagram};
-pub use utils::mkdir;
+pub use token::{AccessToken, read_access_token};
+pub use udp::{
+    UdpToPeerParams, peer_to_udp, read_framed_datagram, udp_to_peer, write_framed_datagram,
+};
+pub use utils::{constant_time_eq, mkdir};
 pub use utils_iroh::{
     accept_bi, accept_bi_any, accept_bi_with, get_remote_id52, global_iroh_endpoint, next_json,
-    next_string,
+    next_string, watch_conn_type,
 };
 
 // Deprecated helper functions - use kulfi_id52 directly
@@ -52,3 +60,7 @@ pub use utils::{id52_to_public_key, public_key_to_id52};
 pub type IDMap = std::sync::Arc<tokio::sync::Mutex<Vec<(String, (u16, iroh::endpoint::Endpoint))>>>;
 
 pub const ACK: &str = "ack";
+/// sent instead of [`ACK`] by services that require an access token, the client then sends its
+/// token, or an empty line, and waits for the [`ACK`]. clients never send a token unasked, so
+/// services that do not need one never see it.
+pub const TOKEN_REQUIRED: &str = "token";
@@ -0,0 +1,381 @@
+//! Prometheus metrics for exposers and bridges.
+//!
+//! nothing is recorded until `serve()` is called, so programs that do not ask for metrics do not
+//! pay for them. we do not pull in a metrics crate, the handful of metrics we have are kept in a
+//! single mutex protected registry and rendered in the prometheus text format by hand.
+//!
+//! the `service` label is the local address for exposers (e.g., `127.0.0.1:3000`), and the remote
+//! id52 for bridges.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+pub const PEER_CONNECTIONS: &str = "kulfi_peer_connections";
+pub const STREAMS: &str = "kulfi_streams_total";
+pub const SERVICE_BYTES: &str = "kulfi_service_bytes_total";
+pub const REQUEST_DURATION: &str = "kulfi_request_duration_seconds";
+pub const STREAM_ACK_DURATION: &str = "kulfi_stream_ack_duration_seconds";
+pub const CONNECTION_TYPE: &str = "kulfi_connection_type";
+pub const CONNECTION_ERRORS: &str = "kulfi_connection_errors_total";
+
+const METRICS: &[(&str, &str, &str)] = &[
+    (
+        PEER_CONNECTIONS,
+        "gauge",
+        "Open iroh connections, by peer and direction.",
+    ),
+    (
+        STREAMS,
+        "counter",
+        "Bidirectional streams opened, by protocol and direction.",
+    ),
+    (
+        SERVICE_BYTES,
+        "counter",
+        "Bytes proxied, by service and direction (in: from the peer, out: to the peer).",
+    ),
+    (
+        REQUEST_DURATION,
+        "histogram",
+        "Time taken to proxy an http request, by service.",
+    ),
+    (
+        STREAM_ACK_DURATION,
+        "histogram",
+        "Time from opening a stream to receiving the ack of its protocol header.",
+    ),
+    (
+        CONNECTION_TYPE,
+        "gauge",
+        "1 for the current connection type (direct, relay, mixed, none) of each peer.",
+    ),
+    (
+        CONNECTION_ERRORS,
+        "counter",
+        "Connection errors, by stage (connect, ping, stream, accept, token).",
+    ),
+];
+
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Histogram {
+    /// one count per bucket, not cumulative, the last one is +Inf
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<(&'static str, Labels), f64>,
+    gauges: BTreeMap<(&'static str, Labels), f64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(Default::default);
+
+fn key(name: &'static str, labels: &[(&'static str, &str)]) -> (&'static str, Labels) {
+    (
+        name,
+        labels.iter().map(|(k, v)| (*k, v.to_string())).collect(),
+    )
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// records from now on, even if `serve()` is not called, e.g., to `render()` them elsewhere.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn inc(name: &'static str, labels: &[(&'static str, &str)]) {
+    add(name, labels, 1.0)
+}
+
+pub fn add(name: &'static str, labels: &[(&'static str, &str)], v: f64) {
+    if !is_enabled() {
+        return;
+    }
+    *REGISTRY
+        .lock()
+        .unwrap()
+        .counters
+        .entry(key(name, labels))
+        .or_default() += v;
+}
+
+/// series that go back to zero are dropped, so per peer gauges do not grow forever.
+pub fn gauge_add(name: &'static str, labels: &[(&'static str, &str)], delta: f64) {
+    if !is_enabled() {
+        return;
+    }
+    let mut registry = REGISTRY.lock().unwrap();
+    let k = key(name, labels);
+    let v = registry.gauges.entry(k.clone()).or_default();
+    *v += delta;
+    if *v == 0.0 {
+        registry.gauges.remove(&k);
+    }
+}
+
+pub fn gauge_set(name: &'static str, labels: &[(&'static str, &str)], v: f64) {
+    if !is_enabled() {
+        return;
+    }
+    let mut registry = REGISTRY.lock().unwrap();
+    if v == 0.0 {
+        registry.gauges.remove(&key(name, labels));
+    } else {
+        registry.gauges.insert(key(name, labels), v);
+    }
+}
+
+pub fn observe(name: &'static str, labels: &[(&'static str, &str)], duration: std::time::Duration) {
+    if !is_enabled() {
+        return;
+    }
+    let secs = duration.as_secs_f64();
+    let mut registry = REGISTRY.lock().unwrap();
+    let h = registry.histograms.entry(key(name, labels)).or_default();
+    if h.counts.is_empty() {
+        h.counts = vec![0; BUCKETS.len() + 1];
+    }
+    let bucket = BUCKETS
+        .iter()
+        .position(|b| secs <= *b)
+        .unwrap_or(BUCKETS.len());
+    h.counts[bucket] += 1;
+    h.sum += secs;
+    h.count += 1;
+}
+
+/// every series of the gauge `name`, e.g., to list connected peers.
+pub fn gauge_values(name: &str) -> Vec<(Labels, f64)> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .gauges
+        .iter()
+        .filter(|((n, _), _)| *n == name)
+        .map(|((_, labels), v)| (labels.clone(), *v))
+        .collect()
+}
+
+/// a gauge that is incremented now and decremented when the guard is dropped.
+pub struct GaugeGuard {
+    name: &'static str,
+    labels: Vec<(&'static str, String)>,
+}
+
+pub fn track(name: &'static str, labels: &[(&'static str, &str)]) -> GaugeGuard {
+    gauge_add(name, labels, 1.0);
+    GaugeGuard {
+        name,
+        labels: labels.iter().map(|(k, v)| (*k, v.to_string())).collect(),
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        let labels: Vec<_> = self.labels.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        gauge_add(self.name, &labels, -1.0);
+    }
+}
+
+/// the label value for a protocol, e.g., `http`.
+pub fn protocol_label(protocol: &crate::Protocol) -> String {
+    format!("{protocol:?}").to_lowercase()
+}
+
+/// the label value for an iroh connection type.
+pub fn conn_type_label(conn_type: &iroh::endpoint::ConnectionType) -> &'static str {
+    match conn_type {
+        iroh::endpoint::ConnectionType::Direct(_) => "direct",
+        iroh::endpoint::ConnectionType::Relay(_) => "relay",
+        iroh::endpoint::ConnectionType::Mixed(_, _) => "mixed",
+        iroh::endpoint::ConnectionType::None => "none",
+    }
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(&'static str, String)], extra: Option<(&str, &str)>) -> String {
+    let mut parts: Vec<_> = labels
+        .iter()
+        .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+        .collect();
+    if let Some((k, v)) = extra {
+        parts.push(format!("{k}=\"{v}\""));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+/// all metrics in the prometheus text exposition format.
+pub fn render() -> String {
+    use std::fmt::Write;
+
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    for (name, kind, help) in METRICS {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} {kind}").unwrap();
+
+        for ((n, labels), v) in registry.counters.iter().chain(registry.gauges.iter()) {
+            if n == name {
+                writeln!(out, "{name}{} {v}", format_labels(labels, None)).unwrap();
+            }
+        }
+
+        for ((n, labels), h) in registry.histograms.iter() {
+            if n != name {
+                continue;
+            }
+            let mut cumulative = 0;
+            for (i, count) in h.counts.iter().enumerate() {
+                cumulative += count;
+                let le = BUCKETS
+                    .get(i)
+                    .map_or_else(|| "+Inf".to_string(), |b| b.to_string());
+                writeln!(
+                    out,
+                    "{name}_bucket{} {cumulative}",
+                    format_labels(labels, Some(("le", &le)))
+                )
+                .unwrap();
+            }
+            writeln!(out, "{name}_sum{} {}", format_labels(labels, None), h.sum).unwrap();
+            writeln!(
+                out,
+                "{name}_count{} {}",
+                format_labels(labels, None),
+                h.count
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+/// serves `render()` at `http://<addr>/metrics`, returns the address it is listening on.
+pub async fn serve(
+    addr: std::net::SocketAddr,
+    graceful: crate::Graceful,
+) -> eyre::Result<std::net::SocketAddr> {
+    use eyre::WrapErr;
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .wrap_err_with(|| format!("failed to bind metrics server to {addr}"))?;
+    let addr = listener.local_addr()?;
+    enable();
+    tracing::info!("serving metrics at http://{addr}/metrics");
+
+    let graceful_for_accept = graceful.clone();
+    graceful.spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                _ = graceful_for_accept.cancelled() => break,
+                r = listener.accept() => match r {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::error!("metrics server failed to accept: {e:?}");
+                        break;
+                    }
+                },
+            };
+
+            graceful_for_accept.spawn(async move {
+                let service = hyper::service::service_fn(|r| async move {
+                    Ok::<_, std::convert::Infallible>(if r.uri().path() == "/metrics" {
+                        let mut resp = crate::http::bytes_to_resp::<std::convert::Infallible>(
+                            render().into_bytes(),
+                            hyper::StatusCode::OK,
+                        );
+                        resp.headers_mut().insert(
+                            hyper::header::CONTENT_TYPE,
+                            hyper::header::HeaderValue::from_static(
+                                "text/plain; version=0.0.4; charset=utf-8",
+                            ),
+                        );
+                        resp
+                    } else {
+                        crate::http::bytes_to_resp(
+                            b"not found\n".to_vec(),
+                            hyper::StatusCode::NOT_FOUND,
+                        )
+                    })
+                });
+                if let Err(e) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!("metrics connection error: {e:?}");
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn render() {
+        super::enable();
+        let peer = [("peer", "a\"b"), ("direction", "incoming")];
+        let guard = super::track(super::PEER_CONNECTIONS, &peer);
+        super::inc(
+            super::STREAMS,
+            &[("protocol", "http"), ("direction", "incoming")],
+        );
+        super::inc(
+            super::STREAMS,
+            &[("protocol", "http"), ("direction", "incoming")],
+        );
+        super::observe(
+            super::REQUEST_DURATION,
+            &[("service", "127.0.0.1:3000")],
+            std::time::Duration::from_millis(30),
+        );
+
+        let out = super::render();
+        assert!(out.contains("# TYPE kulfi_streams_total counter\n"));
+        assert!(out.contains("kulfi_streams_total{protocol=\"http\",direction=\"incoming\"} 2\n"));
+        assert!(out.contains("kulfi_peer_connections{peer=\"a\\\"b\",direction=\"incoming\"} 1\n"));
+        assert!(out.contains(
+            "kulfi_request_duration_seconds_bucket{service=\"127.0.0.1:3000\",le=\"0.025\"} 0\n"
+        ));
+        assert!(out.contains(
+            "kulfi_request_duration_seconds_bucket{service=\"127.0.0.1:3000\",le=\"0.05\"} 1\n"
+        ));
+        assert!(out.contains(
+            "kulfi_request_duration_seconds_bucket{service=\"127.0.0.1:3000\",le=\"+Inf\"} 1\n"
+        ));
+        assert!(
+            out.contains("kulfi_request_duration_seconds_count{service=\"127.0.0.1:3000\"} 1\n")
+        );
+
+        drop(guard);
+        assert!(!super::render().contains("kulfi_peer_connections{"));
+    }
+}
@@ -36,10 +36,16 @@ pub async fn peer_to_http(
 
     use futures_util::TryStreamExt;
     let stream = tokio_util::io::ReaderStream::new(recv);
+    let service = addr.to_string();
     let stream_body = http_body_util::StreamBody::new(
         stream
-            .map_ok(|b| {
+            .map_ok(move |b| {
                 tracing::trace!("got chunk of size: {}", b.len());
+                crate::metrics::add(
+                    crate::metrics::SERVICE_BYTES,
+                    &[("service", &service), ("direction", "in")],
+                    b.len() as f64,
+                );
                 hyper::body::Frame::data(b)
             })
             .map_err(|e| {
@@ -86,6 +92,11 @@ pub async fn peer_to_http(
                     .ok_or_else(|| eyre::anyhow!("chunk data is None"))?;
                 tracing::trace!("sending chunk of size: {}", data.len());
                 send.write_all(data).await?;
+                crate::metrics::add(
+                    crate::metrics::SERVICE_BYTES,
+                    &[("service", addr), ("direction", "out")],
+                    data.len() as f64,
+                );
             }
             Err(e) => {
                 tracing::error!("error reading chunk: {e:?}");
@@ -95,6 +106,11 @@ pub async fn peer_to_http(
     }
 
     tracing::info!("handled http request in {:?}", start.elapsed());
+    crate::metrics::observe(
+        crate::metrics::REQUEST_DURATION,
+        &[("service", addr)],
+        start.elapsed(),
+    );
 
     {
         use colored::Colorize;
@@ -98,6 +98,10 @@ pub enum Protocol {
     Socks5,
     Tcp,
     Udp,
+    /// client asks for the rotation of the id52 in the header `extra`, the server replies with
+    /// the signed rotation document, or nothing, see `rotation.rs`. like `Ping`, every server
+    /// answers it.
+    Rotation,
     // TODO: RTP/"RTCP" for audio video streaming
 }
 
@@ -112,6 +116,8 @@ pub const APNS_IDENTITY: &[u8] = b"/kulfi/identity/0.1";
 pub struct ProtocolHeader {
     pub protocol: Protocol,
     pub extra: Option<String>,
+    /// the access token to send if the server asks for one, see `token.rs`
+    pub token: Option<String>,
 }
 
 impl From<Protocol> for ProtocolHeader {
@@ -119,6 +125,7 @@ impl From<Protocol> for ProtocolHeader {
         Self {
             protocol,
             extra: None,
+            token: None,
         }
     }
 }
@@ -0,0 +1,95 @@
+//! following identity rotations, see `kulfi_id52::SignedRotation` and `dot_kulfi::rotations`.
+//!
+//! every outgoing connection goes to the id52 its target is rotated to, so bridges and clients
+//! follow the rotations this machine knows about. they are learnt from a file with
+//! `malai identity follow`, or from any peer that knows one with `Protocol::Rotation`: the
+//! documents are signed, so it does not matter who hands them out. peers are only asked when told
+//! to, peers from before `Protocol::Rotation` close the connection on it.
+
+/// the id52 to connect to instead of `id52`, `id52` itself if it is not rotated. failures to read
+/// the rotations are logged, and the rotations are not followed then.
+pub fn follow_rotation(id52: &str) -> String {
+    let Ok(dir) = crate::dot_kulfi::dir() else {
+        return id52.to_string();
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    match crate::dot_kulfi::resolve_rotation(&dir, id52, now) {
+        Ok(current) if current == id52 => current,
+        Ok(current) => {
+            tracing::info!("{id52} is rotated, connecting to {current} instead");
+            current
+        }
+        Err(e) => {
+            tracing::warn!("not following the rotations of {id52}: {e:?}");
+            id52.to_string()
+        }
+    }
+}
+
+/// asks `peer` for the rotation of `about`, usually the peer itself. the rotation is checked, but
+/// not stored.
+pub async fn fetch_rotation(
+    self_endpoint: iroh::Endpoint,
+    peer: &str,
+    about: &str,
+    peer_connections: crate::PeerStreamSenders,
+    graceful: crate::Graceful,
+) -> eyre::Result<Option<kulfi_id52::SignedRotation>> {
+    use eyre::WrapErr;
+
+    let (mut send, mut recv) = crate::get_stream(
+        self_endpoint,
+        crate::ProtocolHeader {
+            protocol: crate::Protocol::Rotation,
+            extra: Some(about.to_string()),
+            token: None,
+        },
+        peer.to_string(),
+        peer_connections,
+        graceful,
+    )
+    .await
+    .wrap_err_with(|| format!("failed to ask {peer}, it may be too old to know rotations"))?;
+    send.finish()?;
+
+    let document = recv
+        .read_to_end(MAX_DOCUMENT)
+        .await
+        .wrap_err_with(|| format!("failed to read the reply of {peer}"))?;
+    let document = String::from_utf8(document).wrap_err("the rotation is not text")?;
+    if document.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let rotation: kulfi_id52::SignedRotation = document.parse()?;
+    if rotation.old_key().to_string() != about {
+        return Err(eyre::anyhow!(
+            "{peer} sent the rotation of {} instead of {about}",
+            rotation.old_key()
+        ));
+    }
+    Ok(Some(rotation))
+}
+
+/// a rotation document is about 400 bytes.
+const MAX_DOCUMENT: usize = 4096;
+
+/// replies to a `Protocol::Rotation` stream with the rotation asked about, or nothing.
+pub(crate) async fn answer_rotation(
+    mut send: iroh::endpoint::SendStream,
+    mut recv: iroh::endpoint::RecvStream,
+) -> eyre::Result<()> {
+    let about = crate::next_string(&mut recv).await?;
+    tracing::info!("got asked for the rotation of {about}");
+    let rotation = match crate::dot_kulfi::dir() {
+        Ok(dir) => crate::dot_kulfi::read_rotation(&dir, about.trim())?,
+        Err(_) => None,
+    };
+    if let Some(rotation) = rotation {
+        send.write_all(rotation.to_string().as_bytes()).await?;
+    }
+    send.finish()?;
+    Ok(())
+}
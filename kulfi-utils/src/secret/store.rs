@@ -0,0 +1,175 @@
+//! where `malai run` reads the secret key of a service from. the lookup of `read_or_create_key()`
+//! is for interactive use, on servers the key usually comes from somewhere else, e.g., a password
+//! manager or the credentials systemd hands to the service.
+//!
+//! each store is picked by a key of the service in `malai.toml`:
+//!
+//! - `identity`: `KeyringStore`
+//! - `secret_file`: `FileStore`
+//! - `secret_env`: `EnvStore`
+//! - `secret_command`: `CommandStore`
+//! - `secret_credential`: `CredentialStore`
+
+use eyre::WrapErr;
+
+/// set by systemd to the folder with the credentials of `LoadCredential=` and friends.
+pub const CREDENTIALS_DIRECTORY_ENV_VAR: &str = "CREDENTIALS_DIRECTORY";
+
+pub trait SecretStore: std::fmt::Debug + Send + Sync {
+    /// reads the secret key, and returns it with its id52.
+    fn load(&self) -> eyre::Result<(String, kulfi_id52::SecretKey)>;
+}
+
+/// an identity created with `malai identity create`, by id52 or label. most are in the system
+/// keyring, the ones in the identity registry are read from wherever it says they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyringStore {
+    pub name: String,
+}
+
+impl SecretStore for KeyringStore {
+    fn load(&self) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
+        if let Ok(dir) = crate::dot_kulfi::dir()
+            && let Ok(record) = crate::dot_kulfi::find_identity(&dir, &self.name)
+        {
+            return record.secret_key(&dir);
+        }
+        super::handle_identity(self.name.clone())
+    }
+}
+
+/// a key file in any format `read_secret_file()` takes, encrypted ones included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStore {
+    pub path: std::path::PathBuf,
+}
+
+impl SecretStore for FileStore {
+    fn load(&self) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
+        super::read_secret_file(&self.path)
+    }
+}
+
+/// an environment variable with the secret key, like `KULFI_SECRET_KEY`, but one per service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvStore {
+    pub var: String,
+}
+
+impl SecretStore for EnvStore {
+    fn load(&self) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
+        let secret = std::env::var(&self.var)
+            .wrap_err_with(|| format!("failed to read environment variable {}", self.var))?;
+        super::handle_secret(secret.trim())
+            .wrap_err_with(|| format!("failed to load secret key from {}", self.var))
+    }
+}
+
+/// a shell command printing the secret key, e.g., `pass show malai/web`. its stderr is shown, and
+/// it can ask for a passphrase on the terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandStore {
+    pub command: String,
+}
+
+impl SecretStore for CommandStore {
+    fn load(&self) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
+        let mut command = if cfg!(windows) {
+            let mut c = std::process::Command::new("cmd");
+            c.arg("/C");
+            c
+        } else {
+            let mut c = std::process::Command::new("sh");
+            c.arg("-c");
+            c
+        };
+        let output = command
+            .arg(&self.command)
+            .stdin(std::process::Stdio::inherit())
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .wrap_err_with(|| format!("failed to run `{}`", self.command))?;
+        if !output.status.success() {
+            return Err(eyre::anyhow!(
+                "`{}` failed: {}",
+                self.command,
+                output.status
+            ));
+        }
+        let secret = String::from_utf8(output.stdout)
+            .wrap_err_with(|| format!("`{}` printed something other than text", self.command))?;
+        super::handle_secret(secret.trim())
+            .wrap_err_with(|| format!("failed to load secret key printed by `{}`", self.command))
+    }
+}
+
+/// a systemd credential, e.g., `LoadCredential=web:/etc/malai/web.key` or
+/// `LoadCredentialEncrypted=` in the unit, read from `$CREDENTIALS_DIRECTORY/<name>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CredentialStore {
+    pub name: String,
+}
+
+impl SecretStore for CredentialStore {
+    fn load(&self) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
+        if self.name.is_empty() || self.name.contains(['/', '\\']) || self.name == ".." {
+            return Err(eyre::anyhow!(
+                "invalid credential name `{}`, it is a file name",
+                self.name
+            ));
+        }
+        let dir = std::env::var_os(CREDENTIALS_DIRECTORY_ENV_VAR).ok_or_else(|| {
+            eyre::anyhow!(
+                "{CREDENTIALS_DIRECTORY_ENV_VAR} is not set, secret_credential only works under \
+                 systemd, with LoadCredential={}:<file> in the unit",
+                self.name
+            )
+        })?;
+        super::read_secret_file(&std::path::Path::new(&dir).join(&self.name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SecretStore;
+
+    #[test]
+    fn stores() {
+        let secret_key = kulfi_id52::SecretKey::generate();
+        let id52 = secret_key.id52();
+
+        let dir = std::env::temp_dir().join(format!("kulfi-stores-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("web");
+        std::fs::write(&path, secret_key.to_string()).unwrap();
+
+        let file = super::FileStore { path: path.clone() };
+        assert_eq!(file.load().unwrap().0, id52);
+
+        let missing = super::EnvStore {
+            var: format!("KULFI_TEST_MISSING_{}", std::process::id()),
+        };
+        assert!(missing.load().is_err());
+
+        #[cfg(unix)]
+        {
+            let command = super::CommandStore {
+                command: format!("cat '{}'", path.display()),
+            };
+            assert_eq!(command.load().unwrap().0, id52);
+            let failing = super::CommandStore {
+                command: "exit 3".to_string(),
+            };
+            assert!(failing.load().is_err());
+        }
+
+        // CREDENTIALS_DIRECTORY is not set in tests, names are checked first
+        let credential = super::CredentialStore {
+            name: "../web".to_string(),
+        };
+        let e = credential.load().err().unwrap();
+        assert!(e.to_string().contains("invalid"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
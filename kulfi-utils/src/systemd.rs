@@ -0,0 +1,200 @@
+//! running under systemd, or anything else speaking its protocols.
+//!
+//! `notify_*` tell the service manager when we are ready, stopping, or still alive
+//! (`Type=notify`, `WatchdogSec=`), they do nothing if `NOTIFY_SOCKET` is not set.
+//!
+//! sockets passed to us with socket activation (`LISTEN_FDS`) are picked up by `listen_tcp()` and
+//! `listen_udp()`, which bridges use instead of binding the port themselves. a passed socket is
+//! matched by its port, so `ListenStream=8080` in the `.socket` unit is used by
+//! `malai tcp-bridge <id52> 8080`, and it can listen on any address, not just `127.0.0.1`.
+
+use std::sync::{LazyLock, Mutex};
+
+enum Inherited {
+    Tcp(std::net::TcpListener),
+    Udp(std::net::UdpSocket),
+}
+
+impl Inherited {
+    fn port(&self) -> Option<u16> {
+        match self {
+            Inherited::Tcp(l) => l.local_addr(),
+            Inherited::Udp(s) => s.local_addr(),
+        }
+        .ok()
+        .map(|a| a.port())
+    }
+}
+
+/// the passed sockets not yet taken, read from the environment on first use.
+static INHERITED: LazyLock<Mutex<Vec<Inherited>>> = LazyLock::new(|| {
+    let mut fds = listenfd::ListenFd::from_env();
+    let mut inherited = vec![];
+    for idx in 0..fds.len() {
+        if let Ok(Some(l)) = fds.take_tcp_listener(idx) {
+            inherited.push(Inherited::Tcp(l));
+        } else if let Ok(Some(s)) = fds.take_udp_socket(idx) {
+            inherited.push(Inherited::Udp(s));
+        } else {
+            tracing::warn!("ignoring passed fd #{idx}, it is neither a tcp nor a udp socket");
+        }
+    }
+    if !inherited.is_empty() {
+        tracing::info!("got {} socket(s) from the service manager", inherited.len());
+    }
+    Mutex::new(inherited)
+});
+
+/// the passed tcp listener for `port` if there is one, else binds `127.0.0.1:port`.
+pub async fn listen_tcp(port: u16) -> std::io::Result<tokio::net::TcpListener> {
+    let passed = take(&mut INHERITED.lock().unwrap(), port, |i| match i {
+        Inherited::Tcp(l) => Ok(l),
+        other => Err(other),
+    });
+    match passed {
+        Some(l) => {
+            l.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(l)
+        }
+        None => tokio::net::TcpListener::bind(format!("127.0.0.1:{port}")).await,
+    }
+}
+
+/// the passed udp socket for `port` if there is one, else binds `127.0.0.1:port`.
+pub async fn listen_udp(port: u16) -> std::io::Result<tokio::net::UdpSocket> {
+    let passed = take(&mut INHERITED.lock().unwrap(), port, |i| match i {
+        Inherited::Udp(s) => Ok(s),
+        other => Err(other),
+    });
+    match passed {
+        Some(s) => {
+            s.set_nonblocking(true)?;
+            tokio::net::UdpSocket::from_std(s)
+        }
+        None => tokio::net::UdpSocket::bind(format!("127.0.0.1:{port}")).await,
+    }
+}
+
+/// removes the first socket on `port` that `kind` accepts. port 0 asks for a free port, so it
+/// never matches.
+fn take<T>(
+    inherited: &mut Vec<Inherited>,
+    port: u16,
+    kind: impl Fn(Inherited) -> Result<T, Inherited>,
+) -> Option<T> {
+    if port == 0 {
+        return None;
+    }
+
+    let mut found = None;
+    let mut rest = vec![];
+    for i in inherited.drain(..) {
+        if found.is_none() && i.port() == Some(port) {
+            match kind(i) {
+                Ok(v) => found = Some(v),
+                Err(i) => rest.push(i),
+            }
+        } else {
+            rest.push(i);
+        }
+    }
+    *inherited = rest;
+    found
+}
+
+/// `READY=1`, once all services are started, i.e., their ports are bound.
+pub fn notify_ready() {
+    notify(State::Ready);
+}
+
+/// `STOPPING=1`, when we start draining.
+pub fn notify_stopping() {
+    notify(State::Stopping);
+}
+
+enum State {
+    Ready,
+    Stopping,
+    Watchdog,
+}
+
+#[cfg(unix)]
+fn notify(state: State) {
+    let state = match state {
+        State::Ready => sd_notify::NotifyState::Ready,
+        State::Stopping => sd_notify::NotifyState::Stopping,
+        State::Watchdog => sd_notify::NotifyState::Watchdog,
+    };
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        tracing::warn!("failed to notify the service manager: {e}");
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: State) {}
+
+/// pings the service manager at half the `WatchdogSec=` interval till `graceful` is cancelled,
+/// does nothing if the watchdog is not enabled for us.
+pub fn watchdog(graceful: crate::Graceful) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+
+    tracing::info!("pinging the systemd watchdog every {interval:?}");
+    let graceful_for_task = graceful.clone();
+    graceful.spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = graceful_for_task.cancelled() => break,
+                _ = tick.tick() => notify(State::Watchdog),
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn watchdog_interval() -> Option<std::time::Duration> {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) || usec == 0 {
+        return None;
+    }
+    Some(std::time::Duration::from_micros(usec / 2))
+}
+
+#[cfg(not(unix))]
+fn watchdog_interval() -> Option<std::time::Duration> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::Inherited;
+
+    #[test]
+    fn take_by_port() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tcp_port = tcp.local_addr().unwrap().port();
+        let udp_port = udp.local_addr().unwrap().port();
+        let mut inherited = vec![Inherited::Tcp(tcp), Inherited::Udp(udp)];
+
+        let as_tcp = |i| match i {
+            Inherited::Tcp(l) => Ok(l),
+            other => Err(other),
+        };
+
+        // port 0 and ports nothing was passed for are bound as usual
+        assert!(super::take(&mut inherited, 0, as_tcp).is_none());
+        // the udp socket is not a tcp listener, even on the right port
+        assert!(super::take(&mut inherited, udp_port, as_tcp).is_none());
+        assert_eq!(inherited.len(), 2);
+
+        let l = super::take(&mut inherited, tcp_port, as_tcp).unwrap();
+        assert_eq!(l.local_addr().unwrap().port(), tcp_port);
+        assert_eq!(inherited.len(), 1);
+
+        // each socket is handed out once
+        assert!(super::take(&mut inherited, tcp_port, as_tcp).is_none());
+    }
+}
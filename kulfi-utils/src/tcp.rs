@@ -23,27 +23,48 @@ pub async fn peer_to_tcp(
 
     let stream = tokio::net::TcpStream::connect(addr).await?;
     let (tcp_recv, tcp_send) = tokio::io::split(stream);
-    pipe_tcp_stream_over_iroh(tcp_recv, tcp_send, send, recv).await
+    let (bytes_in, bytes_out) = pipe(tcp_recv, tcp_send, send, recv).await?;
+    crate::metrics::add(
+        crate::metrics::SERVICE_BYTES,
+        &[("service", addr), ("direction", "in")],
+        bytes_in as f64,
+    );
+    crate::metrics::add(
+        crate::metrics::SERVICE_BYTES,
+        &[("service", addr), ("direction", "out")],
+        bytes_out as f64,
+    );
+    Ok(())
 }
 
 pub async fn pipe_tcp_stream_over_iroh(
+    tcp_recv: impl tokio::io::AsyncRead + Unpin + Send + 'static,
+    tcp_send: impl tokio::io::AsyncWrite + Unpin + Send + 'static,
+    send: iroh::endpoint::SendStream,
+    recv: iroh::endpoint::RecvStream,
+) -> eyre::Result<()> {
+    pipe(tcp_recv, tcp_send, send, recv).await.map(|_| ())
+}
+
+/// returns the number of bytes copied from iroh to tcp, and from tcp to iroh.
+async fn pipe(
     mut tcp_recv: impl tokio::io::AsyncRead + Unpin + Send + 'static,
     tcp_send: impl tokio::io::AsyncWrite + Unpin + Send + 'static,
     mut send: iroh::endpoint::SendStream,
     mut recv: iroh::endpoint::RecvStream,
-) -> eyre::Result<()> {
+) -> eyre::Result<(u64, u64)> {
     tracing::trace!("pipe_tcp_stream_over_iroh");
 
     let t = tokio::spawn(async move {
         let mut t = tcp_send;
         let r = tokio::io::copy(&mut recv, &mut t).await;
         tracing::trace!("piping tcp stream, copy done");
-        r.map(|_| ())
+        r
     });
 
     tracing::trace!("copying tcp stream to iroh stream");
 
-    tokio::io::copy(&mut tcp_recv, &mut send).await?;
+    let bytes_out = tokio::io::copy(&mut tcp_recv, &mut send).await?;
 
     tracing::trace!("pipe_tcp_stream_over_iroh copy done");
 
@@ -52,9 +73,9 @@ pub async fn pipe_tcp_stream_over_iroh(
     tracing::trace!("closed send stream");
     drop(send);
 
-    let r = Ok(t.await??);
+    let bytes_in = t.await??;
     tracing::trace!("pipe_tcp_stream_over_iroh done");
-    r
+    Ok((bytes_in, bytes_out))
 }
 
 pub async fn tcp_to_peer(
@@ -0,0 +1,209 @@
+//! access tokens, for sharing a service with a peer for a while.
+//!
+//! the owner of a service signs, with the secret key of the service, that `peer` may use the
+//! `protocol` of the service till `expires`. services started with `require_token` answer every
+//! stream header with `TOKEN_REQUIRED` instead of `ACK`, the peer then sends the token, and the
+//! service acks and checks it. it is checked on every stream, so access can be handed out without
+//! restarting the service or editing its config. nothing is stored, a token is valid till it
+//! expires, to take access back the service has to move to a new identity.
+//!
+//! a token is `kulfi-token-v1.<claims>.<signature>`, the claims are json and both parts are
+//! base64url without padding. the signature is over `kulfi-token-v1.<claims>`.
+
+const PREFIX: &str = "kulfi-token-v1";
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AccessToken {
+    /// the id52 of the service, its secret key signs the token
+    pub service: String,
+    /// the id52 of the peer the token is for, it is useless to anyone else
+    pub peer: String,
+    pub protocol: crate::Protocol,
+    /// unix seconds
+    pub expires: u64,
+}
+
+impl AccessToken {
+    /// the token to hand to `peer`, `secret_key` must be the one of `service`.
+    pub fn sign(&self, secret_key: &kulfi_id52::SecretKey) -> eyre::Result<String> {
+        if secret_key.id52() != self.service {
+            return Err(eyre::anyhow!(
+                "the token is for {}, but the key is of {}",
+                self.service,
+                secret_key.id52()
+            ));
+        }
+
+        let claims = data_encoding::BASE64URL_NOPAD.encode(&serde_json::to_vec(self)?);
+        let signed = format!("{PREFIX}.{claims}");
+        let signature = secret_key.sign(signed.as_bytes());
+        Ok(format!(
+            "{signed}.{}",
+            data_encoding::BASE64URL_NOPAD.encode(&signature.to_bytes())
+        ))
+    }
+
+    /// reads `token` and checks it is signed by the service it is for. it says nothing about
+    /// who may use it, see `check()`.
+    pub fn verify(token: &str) -> eyre::Result<Self> {
+        use eyre::WrapErr;
+
+        let token = token.trim();
+        let (signed, signature) = token
+            .rsplit_once('.')
+            .ok_or_else(|| eyre::anyhow!("not an access token"))?;
+        let claims = match signed.split_once('.') {
+            Some((PREFIX, claims)) => claims,
+            _ => return Err(eyre::anyhow!("not an access token, expected {PREFIX}.")),
+        };
+
+        let claims = data_encoding::BASE64URL_NOPAD
+            .decode(claims.as_bytes())
+            .wrap_err("the claims of the token are not base64url")?;
+        let claims: Self =
+            serde_json::from_slice(&claims).wrap_err("the claims of the token are not valid")?;
+
+        let signature: [u8; 64] = data_encoding::BASE64URL_NOPAD
+            .decode(signature.as_bytes())
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .ok_or_else(|| eyre::anyhow!("the signature of the token is not valid"))?;
+        let signature = kulfi_id52::Signature::from_bytes(&signature)?;
+        let service: kulfi_id52::PublicKey = claims
+            .service
+            .parse()
+            .wrap_err_with(|| format!("the token is for {}, not an id52", claims.service))?;
+        service
+            .verify(signed.as_bytes(), &signature)
+            .map_err(|_| eyre::anyhow!("the token is not signed by {}", claims.service))?;
+
+        Ok(claims)
+    }
+
+    /// errors unless the token lets `peer` use `protocol` of `service` at `now`, unix seconds.
+    pub fn check(
+        &self,
+        service: &str,
+        peer: &str,
+        protocol: &crate::Protocol,
+        now: u64,
+    ) -> eyre::Result<()> {
+        if self.service != service {
+            return Err(eyre::anyhow!("the token is for {}", self.service));
+        }
+        if self.peer != peer {
+            return Err(eyre::anyhow!("the token is for the peer {}", self.peer));
+        }
+        if &self.protocol != protocol {
+            return Err(eyre::anyhow!("the token is for {:?}", self.protocol));
+        }
+        if self.expires <= now {
+            return Err(eyre::anyhow!("the token has expired"));
+        }
+        Ok(())
+    }
+}
+
+/// reads the token `peer` sent after the `TOKEN_REQUIRED` answer to its stream header, acks it,
+/// and checks it lets `peer` use `protocol` of `service`, failures are counted in
+/// `metrics::CONNECTION_ERRORS`. the stream must have been accepted with `require_token`. tokens
+/// are checked when a stream is opened, streams already open are not closed when their token
+/// expires.
+pub async fn read_access_token(
+    send: &mut iroh::endpoint::SendStream,
+    recv: &mut iroh::endpoint::RecvStream,
+    service: &str,
+    peer: &str,
+    protocol: &crate::Protocol,
+) -> eyre::Result<AccessToken> {
+    let result = async {
+        let token = crate::next_string(recv).await?;
+        // the peer waits for this, a rejected one then finds the stream closed, or, for http,
+        // gets an error response
+        crate::utils_iroh::ack(send).await?;
+        let token = AccessToken::verify(&token)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        token.check(service, peer, protocol, now)?;
+        Ok(token)
+    }
+    .await;
+    if result.is_err() {
+        crate::metrics::inc(crate::metrics::CONNECTION_ERRORS, &[("stage", "token")]);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::AccessToken;
+    use crate::Protocol;
+
+    #[test]
+    fn tokens() {
+        let service = kulfi_id52::SecretKey::generate();
+        let other = kulfi_id52::SecretKey::generate();
+        let peer = kulfi_id52::SecretKey::generate().id52();
+        let claims = AccessToken {
+            service: service.id52(),
+            peer: peer.clone(),
+            protocol: Protocol::Tcp,
+            expires: 100,
+        };
+
+        let token = claims.sign(&service).unwrap();
+        assert!(token.starts_with("kulfi-token-v1."));
+        assert!(!token.contains('\n'));
+        let read = AccessToken::verify(&token).unwrap();
+        assert_eq!(read, claims);
+        read.check(&service.id52(), &peer, &Protocol::Tcp, 99)
+            .unwrap();
+
+        // for someone else, something else, or too late
+        assert!(
+            read.check(&other.id52(), &peer, &Protocol::Tcp, 99)
+                .is_err()
+        );
+        assert!(
+            read.check(&service.id52(), &other.id52(), &Protocol::Tcp, 99)
+                .is_err()
+        );
+        assert!(
+            read.check(&service.id52(), &peer, &Protocol::Udp, 99)
+                .is_err()
+        );
+        assert!(
+            read.check(&service.id52(), &peer, &Protocol::Tcp, 100)
+                .is_err()
+        );
+
+        // only the service can sign
+        assert!(claims.sign(&other).is_err());
+        let forged = AccessToken {
+            service: other.id52(),
+            ..claims
+        }
+        .sign(&other)
+        .unwrap();
+        let (_, forged_signature) = forged.rsplit_once('.').unwrap();
+        let (signed, _) = token.rsplit_once('.').unwrap();
+        assert!(AccessToken::verify(&format!("{signed}.{forged_signature}")).is_err());
+
+        // the claims can not be changed
+        let longer = AccessToken {
+            service: service.id52(),
+            peer,
+            protocol: Protocol::Tcp,
+            expires: 1000,
+        }
+        .sign(&service)
+        .unwrap();
+        let (longer_signed, _) = longer.rsplit_once('.').unwrap();
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        assert!(AccessToken::verify(&format!("{longer_signed}.{signature}")).is_err());
+
+        assert!(AccessToken::verify("kulfi-token-v2.e30.AAAA").is_err());
+        assert!(AccessToken::verify("garbage").is_err());
+    }
+}
@@ -15,6 +15,7 @@ pub async fn peer_to_udp(
     let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
     socket.connect(addr).await?;
 
+    let addr_for_recv = addr.to_string();
     let recv_task = {
         let socket = std::sync::Arc::new(socket);
         let socket_for_send = socket.clone();
@@ -28,6 +29,11 @@ pub async fn peer_to_udp(
                             tracing::error!("failed to send UDP datagram to local: {e:?}");
                             break;
                         }
+                        crate::metrics::add(
+                            crate::metrics::SERVICE_BYTES,
+                            &[("service", &addr_for_recv), ("direction", "in")],
+                            data.len() as f64,
+                        );
                     }
                     Err(e) => {
                         tracing::trace!("iroh stream ended: {e:?}");
@@ -46,6 +52,11 @@ pub async fn peer_to_udp(
                         tracing::error!("failed to write framed datagram to iroh: {e:?}");
                         break;
                     }
+                    crate::metrics::add(
+                        crate::metrics::SERVICE_BYTES,
+                        &[("service", addr), ("direction", "out")],
+                        n as f64,
+                    );
                 }
                 Err(e) => {
                     tracing::error!("failed to recv from local UDP socket: {e:?}");
@@ -7,6 +7,12 @@ pub fn mkdir(parent: &std::path::Path, name: &str) -> eyre::Result<std::path::Pa
     Ok(path)
 }
 
+/// compares secrets like bearer tokens without returning early at the first byte that differs,
+/// so how long it takes does not tell how much of a guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 // Deprecated: Use kulfi_id52::PublicKey::from_str instead
 pub fn id52_to_public_key(id: &str) -> eyre::Result<kulfi_id52::PublicKey> {
     use std::str::FromStr;
@@ -17,3 +23,13 @@ pub fn id52_to_public_key(id: &str) -> eyre::Result<kulfi_id52::PublicKey> {
 pub fn public_key_to_id52(key: &kulfi_id52::PublicKey) -> String {
     key.to_string()
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn constant_time_eq() {
+        assert!(super::constant_time_eq(b"abc", b"abc"));
+        assert!(!super::constant_time_eq(b"abc", b"abd"));
+        assert!(!super::constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -8,7 +8,71 @@ pub fn get_remote_id52(conn: &iroh::endpoint::Connection) -> String {
     data_encoding::BASE32_DNSSEC.encode(bytes)
 }
 
-async fn ack(send: &mut iroh::endpoint::SendStream) -> eyre::Result<()> {
+/// logs and records (see `metrics::CONNECTION_TYPE`) the connection type (relay vs direct) of
+/// `remote` till the returned handle is dropped, keep it around as long as the connection.
+pub fn watch_conn_type(
+    endpoint: &iroh::Endpoint,
+    remote: iroh::EndpointId,
+    remote_id52: String,
+) -> Option<tokio_util::task::AbortOnDropHandle<()>> {
+    use iroh::Watcher;
+
+    // clears the gauge when the task is aborted
+    struct Current {
+        remote_id52: String,
+        conn_type: iroh::endpoint::ConnectionType,
+    }
+
+    impl Current {
+        fn set(&self, v: f64) {
+            crate::metrics::gauge_set(
+                crate::metrics::CONNECTION_TYPE,
+                &[
+                    ("peer", &self.remote_id52),
+                    ("type", crate::metrics::conn_type_label(&self.conn_type)),
+                ],
+                v,
+            );
+        }
+    }
+
+    impl Drop for Current {
+        fn drop(&mut self) {
+            self.set(0.0);
+        }
+    }
+
+    let mut conn_type_watcher = endpoint.conn_type(remote)?;
+    Some(tokio_util::task::AbortOnDropHandle::new(tokio::spawn(
+        async move {
+            let mut current = Current {
+                remote_id52,
+                conn_type: conn_type_watcher.get(),
+            };
+            tracing::info!(
+                remote = %current.remote_id52,
+                conn_type = ?current.conn_type,
+                "initial connection type"
+            );
+            current.set(1.0);
+
+            use futures_util::StreamExt;
+            let mut stream = conn_type_watcher.stream_updates_only();
+            while let Some(conn_type) = stream.next().await {
+                tracing::info!(
+                    remote = %current.remote_id52,
+                    conn_type = ?conn_type,
+                    "connection type changed"
+                );
+                current.set(0.0);
+                current.conn_type = conn_type;
+                current.set(1.0);
+            }
+        },
+    )))
+}
+
+pub(crate) async fn ack(send: &mut iroh::endpoint::SendStream) -> eyre::Result<()> {
     tracing::trace!("sending ack");
     send.write_all(format!("{}\n", crate::ACK).as_bytes())
         .await?;
@@ -16,13 +80,21 @@ async fn ack(send: &mut iroh::endpoint::SendStream) -> eyre::Result<()> {
     Ok(())
 }
 
+/// with `require_token` the stream is answered with `TOKEN_REQUIRED`, and the caller must read
+/// the token with `read_access_token` before anything else.
 pub async fn accept_bi(
     conn: &iroh::endpoint::Connection,
     expected: crate::Protocol,
+    require_token: bool,
 ) -> eyre::Result<(iroh::endpoint::SendStream, iroh::endpoint::RecvStream)> {
+    let token_for: &[crate::Protocol] = if require_token {
+        std::slice::from_ref(&expected)
+    } else {
+        &[]
+    };
     loop {
         tracing::trace!("accepting bidirectional stream");
-        match accept_bi_(conn).await? {
+        match accept_bi_(conn, token_for).await? {
             (mut send, _recv, crate::Protocol::Ping) => {
                 tracing::trace!("got ping");
                 tracing::trace!("sending PONG");
@@ -31,6 +103,7 @@ pub async fn accept_bi(
                     .inspect_err(|e| tracing::error!("failed to write PONG: {e:?}"))?;
                 tracing::trace!("sent PONG");
             }
+            (send, recv, crate::Protocol::Rotation) => answer_rotation(send, recv).await,
             (s, r, found) => {
                 tracing::trace!("got bidirectional stream: {found:?}");
                 if found != expected {
@@ -42,17 +115,20 @@ pub async fn accept_bi(
     }
 }
 
+/// like [`accept_bi`], for services that speak more than one protocol.
 pub async fn accept_bi_any(
     conn: &iroh::endpoint::Connection,
     expected: &[crate::Protocol],
+    require_token: bool,
 ) -> eyre::Result<(
     iroh::endpoint::SendStream,
     iroh::endpoint::RecvStream,
     crate::Protocol,
 )> {
+    let token_for = if require_token { expected } else { &[] };
     loop {
         tracing::trace!("accepting bidirectional stream (any)");
-        match accept_bi_(conn).await? {
+        match accept_bi_(conn, token_for).await? {
             (mut send, _recv, crate::Protocol::Ping) => {
                 tracing::trace!("got ping");
                 send.write_all(crate::PONG)
@@ -60,6 +136,7 @@ pub async fn accept_bi_any(
                     .inspect_err(|e| tracing::error!("failed to write PONG: {e:?}"))?;
                 tracing::trace!("sent PONG");
             }
+            (send, recv, crate::Protocol::Rotation) => answer_rotation(send, recv).await,
             (s, r, found) => {
                 tracing::trace!("got bidirectional stream: {found:?}");
                 if !expected.contains(&found) {
@@ -75,7 +152,7 @@ pub async fn accept_bi_with<T: serde::de::DeserializeOwned>(
     conn: &iroh::endpoint::Connection,
     expected: crate::Protocol,
 ) -> eyre::Result<(T, iroh::endpoint::SendStream, iroh::endpoint::RecvStream)> {
-    let (send, mut recv) = accept_bi(conn, expected).await?;
+    let (send, mut recv) = accept_bi(conn, expected, false).await?;
     let next = next_json(&mut recv)
         .await
         .inspect_err(|e| tracing::error!("failed to read next message: {e}"))?;
@@ -83,8 +160,18 @@ pub async fn accept_bi_with<T: serde::de::DeserializeOwned>(
     Ok((next, send, recv))
 }
 
+/// a failed answer only affects its own stream, not the connection.
+async fn answer_rotation(send: iroh::endpoint::SendStream, recv: iroh::endpoint::RecvStream) {
+    if let Err(e) = crate::rotation::answer_rotation(send, recv).await {
+        tracing::error!("failed to answer rotation request: {e:?}");
+    }
+}
+
+/// answers the stream header with `TOKEN_REQUIRED` if the protocol is one of `token_for`, with
+/// `ACK` otherwise.
 async fn accept_bi_(
     conn: &iroh::endpoint::Connection,
+    token_for: &[crate::Protocol],
 ) -> eyre::Result<(
     iroh::endpoint::SendStream,
     iroh::endpoint::RecvStream,
@@ -100,7 +187,20 @@ async fn accept_bi_(
 
     tracing::trace!("msg: {msg:?}");
 
-    ack(&mut send).await?;
+    if token_for.contains(&msg) {
+        tracing::trace!("asking for access token");
+        send.write_all(format!("{}\n", crate::TOKEN_REQUIRED).as_bytes())
+            .await?;
+    } else {
+        ack(&mut send).await?;
+    }
+    crate::metrics::inc(
+        crate::metrics::STREAMS,
+        &[
+            ("protocol", &crate::metrics::protocol_label(&msg)),
+            ("direction", "incoming"),
+        ],
+    );
 
     tracing::trace!("ack sent");
     Ok((send, recv, msg))
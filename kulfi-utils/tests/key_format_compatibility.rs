@@ -0,0 +1,129 @@
+//! Test interop of baseline keys with the OpenSSH, PKCS#8 and SPKI formats
+//!
+//! Companion to `baseline_key_compatibility.rs`: the same baseline keys must survive a round
+//! trip through every standard format, and the copies of key-1 written by `ssh-keygen` and
+//! `openssl` must load as the same identity.
+
+use std::path::Path;
+use std::str::FromStr;
+
+const BASELINE_KEYS: [(&str, &str); 3] = [
+    (
+        "../test-keys/key-1.txt",
+        "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60",
+    ),
+    (
+        "../test-keys/key-2.txt",
+        "e87aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9as9b7v80",
+    ),
+    (
+        "../test-keys/key-3.txt",
+        "mlk9ubnvu8r1sk06j4tjb98njra0od5d2dglpm8gubbvg4glthng",
+    ),
+];
+
+fn load(file_path: &str) -> kulfi_id52::SecretKey {
+    let key_hex = std::fs::read_to_string(file_path)
+        .unwrap_or_else(|_| panic!("Failed to read {}", file_path));
+    kulfi_id52::SecretKey::from_str(key_hex.trim())
+        .unwrap_or_else(|_| panic!("Failed to parse secret key from {}", file_path))
+}
+
+#[test]
+fn test_baseline_secret_keys_roundtrip() {
+    for (file_path, expected_id52) in BASELINE_KEYS {
+        println!("Testing baseline key from {}", file_path);
+        let secret_key = load(file_path);
+
+        let from_openssh = kulfi_id52::SecretKey::from_openssh(&secret_key.to_openssh())
+            .expect("OpenSSH roundtrip should work");
+        let from_pem = kulfi_id52::SecretKey::from_pkcs8_pem(&secret_key.to_pkcs8_pem())
+            .expect("PKCS#8 PEM roundtrip should work");
+        let from_der = kulfi_id52::SecretKey::from_pkcs8_der(&secret_key.to_pkcs8_der())
+            .expect("PKCS#8 DER roundtrip should work");
+
+        for (format, imported) in [
+            ("OpenSSH", from_openssh),
+            ("PKCS#8 PEM", from_pem),
+            ("PKCS#8 DER", from_der),
+        ] {
+            assert_eq!(
+                imported.to_bytes(),
+                secret_key.to_bytes(),
+                "{format} roundtrip changed the key for {file_path}"
+            );
+            assert_eq!(imported.id52(), expected_id52);
+        }
+
+        println!("  ✓ OpenSSH and PKCS#8 roundtrips preserve the key");
+    }
+}
+
+#[test]
+fn test_baseline_public_keys_roundtrip() {
+    for (file_path, expected_id52) in BASELINE_KEYS {
+        println!("Testing baseline public key from {}", file_path);
+        let public_key = load(file_path).public_key();
+
+        let openssh = public_key.to_openssh();
+        assert!(
+            openssh.ends_with(expected_id52),
+            "OpenSSH comment should be the ID52"
+        );
+        let from_openssh =
+            kulfi_id52::PublicKey::from_openssh(&openssh).expect("OpenSSH roundtrip should work");
+        let from_pem = kulfi_id52::PublicKey::from_spki_pem(&public_key.to_spki_pem())
+            .expect("SPKI PEM roundtrip should work");
+        let from_der = kulfi_id52::PublicKey::from_spki_der(&public_key.to_spki_der())
+            .expect("SPKI DER roundtrip should work");
+
+        for (format, imported) in [
+            ("OpenSSH", from_openssh),
+            ("SPKI PEM", from_pem),
+            ("SPKI DER", from_der),
+        ] {
+            assert_eq!(
+                imported.to_string(),
+                expected_id52,
+                "{format} roundtrip changed the ID52 for {file_path}"
+            );
+        }
+
+        println!("  ✓ OpenSSH and SPKI roundtrips preserve the ID52");
+    }
+}
+
+#[test]
+fn test_key_files_from_other_tools() {
+    let (_, expected_id52) = BASELINE_KEYS[0];
+
+    // what `malai identity import` and `secret_file` in malai run go through
+    for file_path in [
+        "../test-keys/key-1.txt",
+        "../test-keys/key-1.openssh.txt",
+        "../test-keys/key-1.pkcs8.txt",
+    ] {
+        let (id52, _) = kulfi_utils::secret::read_secret_file(Path::new(file_path))
+            .unwrap_or_else(|e| panic!("Failed to load {}: {e}", file_path));
+        assert_eq!(
+            id52, expected_id52,
+            "Wrong identity loaded from {file_path}"
+        );
+    }
+
+    let der = std::env::temp_dir().join(format!("kulfi-key-1-{}.der", std::process::id()));
+    std::fs::write(&der, load(BASELINE_KEYS[0].0).to_pkcs8_der()).unwrap();
+    let (id52, _) = kulfi_utils::secret::read_secret_file(&der).expect("Should load PKCS#8 DER");
+    std::fs::remove_file(&der).unwrap();
+    assert_eq!(id52, expected_id52);
+
+    let openssh_pub = std::fs::read_to_string("../test-keys/key-1.openssh-pub.txt").unwrap();
+    let public_key = kulfi_id52::PublicKey::from_openssh(&openssh_pub).unwrap();
+    assert_eq!(public_key.to_string(), expected_id52);
+
+    let spki = std::fs::read_to_string("../test-keys/key-1.spki.txt").unwrap();
+    let public_key = kulfi_id52::PublicKey::from_spki_pem(&spki).unwrap();
+    assert_eq!(public_key.to_string(), expected_id52);
+
+    println!("✅ Keys written by ssh-keygen and openssl load as the baseline identity");
+}
@@ -1,4 +1,5 @@
-pub async fn browse(url: String, graceful: kulfi_utils::Graceful) {
+/// `tokens` are access tokens for the site, if it requires one, see `malai token create`.
+pub async fn browse(url: String, tokens: Vec<String>, graceful: kulfi_utils::Graceful) {
     let (id52, path) = match parse_url(&url) {
         Ok(v) => v,
         Err(e) => {
@@ -8,11 +9,24 @@ pub async fn browse(url: String, graceful: kulfi_utils::Graceful) {
         }
     };
 
-    malai::http_bridge(0, Some(id52.to_string()), graceful, |port| {
-        let url = format!("http://127.0.0.1:{port}/{path}");
-        webbrowser::open(&url).map_err(Into::into)
-    })
+    if let Err(e) = malai::http_bridge(
+        0,
+        Some(id52.to_string()),
+        malai::HttpBridgeConfig {
+            tokens,
+            ..Default::default()
+        },
+        graceful,
+        |port| {
+            let url = format!("http://127.0.0.1:{port}/{path}");
+            webbrowser::open(&url).map_err(Into::into)
+        },
+    )
     .await
+    {
+        eprintln!("Failed to run http bridge: {e:?}");
+        std::process::exit(1);
+    }
 }
 
 /// This function extracts the id52 and the path from the URL
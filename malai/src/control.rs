@@ -0,0 +1,378 @@
+//! Local control api for `malai run`, and `malai ctl` which talks to it.
+//!
+//! with `control = "127.0.0.1:7070"` in the `[malai]` section of `malai.toml`, `malai run` serves
+//! a small json api on that address. only loopback addresses are accepted, and every request
+//! needs `Authorization: Bearer <token>`. the token is generated at startup and written, along
+//! with the address, to `.malai.control` next to `malai.toml` (readable only by the owner), which
+//! is where `malai ctl` finds it.
+//!
+//! - `GET /services`: all services in the config, with their ports and id52s
+//! - `GET /peers`: connected peers and their connection type (direct, relay or mixed)
+//! - `POST /services/<type>.<name>/start` and `POST /services/<type>.<name>/stop`
+//! - `POST /reload`: re-read `malai.toml` and restart the services that changed
+
+use std::sync::Arc;
+
+pub const CONTROL_FILE: &str = ".malai.control";
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ServiceStatus {
+    /// `<type>.<name>`, e.g., `http.my_web_app`
+    pub name: String,
+    pub host: String,
+    pub ports: Vec<PortStatus>,
+    pub active: bool,
+    pub running: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct PortStatus {
+    /// `None` for services without a port, like `folder`
+    pub port: Option<u16>,
+    /// only known while the service is running
+    pub id52: Option<String>,
+    /// how many times `malai run` restarted it after a failure
+    #[serde(default)]
+    pub restarts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// `malai run` gave up restarting it
+    #[serde(default)]
+    pub failed: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct PeerStatus {
+    pub id52: String,
+    /// `incoming` for peers connected to our services, `outgoing` for peers we connected to
+    pub direction: String,
+    pub connections: u64,
+    /// `direct`, `relay`, `mixed` or `none`, if known
+    pub connection_type: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ControlFile {
+    addr: std::net::SocketAddr,
+    token: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// What `malai ctl` asks for.
+#[derive(Debug)]
+pub enum CtlRequest {
+    Services,
+    Peers,
+    Start(String),
+    Stop(String),
+    Reload,
+}
+
+pub(crate) async fn serve(
+    addr: std::net::SocketAddr,
+    services: Arc<crate::run::Services>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    use eyre::WrapErr;
+
+    if !addr.ip().is_loopback() {
+        return Err(eyre::anyhow!(
+            "control api must listen on a loopback address, got {addr}"
+        ));
+    }
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .wrap_err_with(|| format!("failed to bind control api to {addr}"))?;
+    let addr = listener.local_addr()?;
+
+    let token = data_encoding::HEXLOWER.encode(&rand::random::<[u8; 32]>());
+    let control_file = services.control_file();
+    write_control_file(
+        &control_file,
+        &ControlFile {
+            addr,
+            token: token.clone(),
+        },
+    )
+    .wrap_err_with(|| format!("failed to write {}", control_file.display()))?;
+
+    // peers are listed from the connection gauges
+    kulfi_utils::metrics::enable();
+    tracing::info!("control api listening on {addr}");
+
+    let token = Arc::new(token);
+    let graceful_for_accept = graceful.clone();
+    graceful.spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                _ = graceful_for_accept.cancelled() => break,
+                r = listener.accept() => match r {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::error!("control api failed to accept: {e:?}");
+                        break;
+                    }
+                },
+            };
+
+            let services = services.clone();
+            let token = token.clone();
+            graceful_for_accept.spawn(async move {
+                let service = hyper::service::service_fn(move |r| {
+                    let services = services.clone();
+                    let token = token.clone();
+                    async move {
+                        Ok::<_, std::convert::Infallible>(
+                            handle_request(r, &services, &token).await,
+                        )
+                    }
+                });
+                if let Err(e) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!("control api connection error: {e:?}");
+                }
+            });
+        }
+
+        if let Err(e) = std::fs::remove_file(&control_file) {
+            tracing::warn!("failed to remove {}: {e}", control_file.display());
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_request(
+    r: hyper::Request<hyper::body::Incoming>,
+    services: &crate::run::Services,
+    token: &str,
+) -> kulfi_utils::http::ProxyResponse<std::convert::Infallible> {
+    let authorized = r
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|t| kulfi_utils::constant_time_eq(t.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return error(hyper::StatusCode::UNAUTHORIZED, "missing or invalid token");
+    }
+
+    let path = r.uri().path();
+    tracing::info!("control api: {} {path}", r.method());
+
+    match (r.method(), path) {
+        (&hyper::Method::GET, "/services") => json(&services.status().await),
+        (&hyper::Method::GET, "/peers") => json(&peers()),
+        (&hyper::Method::POST, "/reload") => result(services.reload().await),
+        (&hyper::Method::POST, path) => {
+            match path
+                .strip_prefix("/services/")
+                .and_then(|p| p.rsplit_once('/'))
+            {
+                Some((name, "start")) => result(services.start(name).await),
+                Some((name, "stop")) => result(services.stop(name).await),
+                _ => error(hyper::StatusCode::NOT_FOUND, "not found"),
+            }
+        }
+        _ => error(hyper::StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn peers() -> Vec<PeerStatus> {
+    use kulfi_utils::metrics;
+
+    let label = |labels: &metrics::Labels, name: &str| {
+        labels
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v.clone())
+            .unwrap_or_default()
+    };
+
+    let conn_types = metrics::gauge_values(metrics::CONNECTION_TYPE);
+    metrics::gauge_values(metrics::PEER_CONNECTIONS)
+        .into_iter()
+        .map(|(labels, connections)| {
+            let id52 = label(&labels, "peer");
+            PeerStatus {
+                connection_type: conn_types
+                    .iter()
+                    .find(|(l, _)| label(l, "peer") == id52)
+                    .map(|(l, _)| label(l, "type")),
+                direction: label(&labels, "direction"),
+                connections: connections as u64,
+                id52,
+            }
+        })
+        .collect()
+}
+
+fn json<T: serde::Serialize>(v: &T) -> kulfi_utils::http::ProxyResponse<std::convert::Infallible> {
+    let mut resp = kulfi_utils::http::bytes_to_resp(
+        serde_json::to_vec(v).expect("control api responses are always serializable"),
+        hyper::StatusCode::OK,
+    );
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    resp
+}
+
+fn error(
+    status: hyper::StatusCode,
+    msg: &str,
+) -> kulfi_utils::http::ProxyResponse<std::convert::Infallible> {
+    let mut resp = json(&ErrorResponse {
+        error: msg.to_string(),
+    });
+    *resp.status_mut() = status;
+    resp
+}
+
+fn result(r: eyre::Result<()>) -> kulfi_utils::http::ProxyResponse<std::convert::Infallible> {
+    match r {
+        Ok(()) => json(&serde_json::json!({})),
+        Err(e) => error(hyper::StatusCode::BAD_REQUEST, &format!("{e:#}")),
+    }
+}
+
+fn write_control_file(path: &std::path::Path, control: &ControlFile) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(&serde_json::to_vec(control)?)
+}
+
+/// sends `request` to the control api of the `malai run` whose `.malai.control` is in `home`, and
+/// prints the result.
+pub async fn ctl(home: &std::path::Path, request: CtlRequest) -> eyre::Result<()> {
+    use eyre::WrapErr;
+
+    let control_file = home.join(CONTROL_FILE);
+    let control: ControlFile =
+        serde_json::from_str(&std::fs::read_to_string(&control_file).wrap_err_with(|| {
+            format!(
+                "failed to read {}, is `malai run` running with `control` set in malai.toml?",
+                control_file.display()
+            )
+        })?)
+        .wrap_err_with(|| format!("failed to parse {}", control_file.display()))?;
+
+    let (method, path) = match &request {
+        CtlRequest::Services => (reqwest::Method::GET, "/services".to_string()),
+        CtlRequest::Peers => (reqwest::Method::GET, "/peers".to_string()),
+        CtlRequest::Start(name) => (reqwest::Method::POST, format!("/services/{name}/start")),
+        CtlRequest::Stop(name) => (reqwest::Method::POST, format!("/services/{name}/stop")),
+        CtlRequest::Reload => (reqwest::Method::POST, "/reload".to_string()),
+    };
+
+    let resp = reqwest::Client::new()
+        .request(method, format!("http://{}{path}", control.addr))
+        .bearer_auth(&control.token)
+        .send()
+        .await
+        .wrap_err_with(|| format!("failed to connect to control api at {}", control.addr))?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let msg = match serde_json::from_slice::<ErrorResponse>(&resp.bytes().await?) {
+            Ok(e) => e.error,
+            Err(_) => status.to_string(),
+        };
+        return Err(eyre::anyhow!("{msg}"));
+    }
+
+    match request {
+        CtlRequest::Services => {
+            for service in serde_json::from_slice::<Vec<ServiceStatus>>(&resp.bytes().await?)? {
+                for port in service.ports {
+                    let state = if !service.running && service.active {
+                        "stopped"
+                    } else if !service.running {
+                        "inactive"
+                    } else if port.failed {
+                        "failed"
+                    } else {
+                        "running"
+                    };
+                    let addr = match port.port {
+                        Some(p) => format!("{}:{p}", service.host),
+                        None => "-".to_string(),
+                    };
+                    println!(
+                        "{:<24} {:<8} {:<16} {}",
+                        service.name,
+                        state,
+                        addr,
+                        port.id52.as_deref().unwrap_or("-")
+                    );
+                    if let Some(e) = port.last_error {
+                        println!("    {} restart(s), last error: {e}", port.restarts);
+                    }
+                }
+            }
+        }
+        CtlRequest::Peers => {
+            let peers = serde_json::from_slice::<Vec<PeerStatus>>(&resp.bytes().await?)?;
+            if peers.is_empty() {
+                println!("No connected peers.");
+            }
+            for peer in peers {
+                println!(
+                    "{} {:<8} {} connection(s) {}",
+                    peer.id52,
+                    peer.direction,
+                    peer.connections,
+                    peer.connection_type.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        CtlRequest::Start(name) => println!("Started {name}."),
+        CtlRequest::Stop(name) => println!("Stopped {name}."),
+        CtlRequest::Reload => println!("Reloaded."),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use kulfi_utils::metrics;
+
+    #[test]
+    fn peers() {
+        const ID: &str = "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60";
+
+        metrics::enable();
+        let labels = [("peer", ID), ("direction", "incoming")];
+        let _a = metrics::track(metrics::PEER_CONNECTIONS, &labels);
+        let _b = metrics::track(metrics::PEER_CONNECTIONS, &labels);
+        metrics::gauge_set(
+            metrics::CONNECTION_TYPE,
+            &[("peer", ID), ("type", "direct")],
+            1.0,
+        );
+
+        let peers = super::peers();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].id52, ID);
+        assert_eq!(peers[0].direction, "incoming");
+        assert_eq!(peers[0].connections, 2);
+        assert_eq!(peers[0].connection_type.as_deref(), Some("direct"));
+    }
+}
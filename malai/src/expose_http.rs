@@ -4,28 +4,75 @@ pub async fn expose_http(
     bridge: String,
     id52: String,
     secret_key: kulfi_id52::SecretKey,
+    require_token: bool,
     graceful: kulfi_utils::Graceful,
-) {
-    let ep = match kulfi_utils::get_endpoint(secret_key).await {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Failed to bind to iroh network:");
-            eprintln!("{e:?}");
-            std::process::exit(1);
-        }
-    };
+) -> eyre::Result<()> {
+    let handle = start_expose_http(
+        ExposeHttpConfig {
+            host: host.clone(),
+            port,
+            secret_key,
+            require_token,
+        },
+        graceful.clone(),
+    )
+    .await?;
+    kulfi_utils::systemd::notify_ready();
 
     InfoMode::Startup.print(&host, port, &id52, &bridge);
+    handle
+        .wait_showing_info(graceful, || {
+            InfoMode::OnExit.print(&host, port, &id52, &bridge)
+        })
+        .await
+}
 
-    let client_pools = kulfi_utils::HttpConnectionPools::default();
+/// the http service to expose with [`start_expose_http`].
+pub struct ExposeHttpConfig {
+    /// where the service runs, e.g., `127.0.0.1`
+    pub host: String,
+    pub port: u16,
+    /// the identity to expose the service as, peers and bridges connect to its id52
+    pub secret_key: kulfi_id52::SecretKey,
+    /// only serve peers with an access token signed by the identity, others get a `403`
+    pub require_token: bool,
+}
 
-    let mut graceful_mut = graceful.clone();
+/// exposes the http service over the kulfi network, without printing anything.
+pub async fn start_expose_http(
+    config: ExposeHttpConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
+    use eyre::WrapErr;
+
+    let id52 = config.secret_key.id52();
+    let ep = kulfi_utils::get_endpoint(config.secret_key)
+        .await
+        .wrap_err("failed to bind to iroh network")?;
+
+    let require_token = config.require_token.then(|| id52.clone());
+    let graceful = graceful.child();
+    let task = graceful.spawn(serve(
+        ep,
+        config.host,
+        config.port,
+        require_token,
+        graceful.clone(),
+    ));
+    Ok(malai::ServiceHandle::new(Some(id52), None, graceful, task))
+}
+
+async fn serve(
+    ep: iroh::Endpoint,
+    host: String,
+    port: u16,
+    require_token: Option<String>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    let client_pools = kulfi_utils::HttpConnectionPools::default();
 
     loop {
         tokio::select! {
-            _ = graceful_mut.show_info() => {
-                InfoMode::OnExit.print(&host, port, &id52, &bridge);
-            }
             _ = graceful.cancelled() => {
                 tracing::info!("Stopping control server.");
                 break;
@@ -41,6 +88,8 @@ pub async fn expose_http(
 
                 let client_pools = client_pools.clone();
                 let host = host.clone();
+                let require_token = require_token.clone();
+                let ep = ep.clone();
 
                 graceful.spawn(async move {
                     let start = std::time::Instant::now();
@@ -48,10 +97,19 @@ pub async fn expose_http(
                         Ok(c) => c,
                         Err(e) => {
                             tracing::error!("failed to convert incoming to connection: {:?}", e);
+                            kulfi_utils::metrics::inc(
+                                kulfi_utils::metrics::CONNECTION_ERRORS,
+                                &[("stage", "accept")],
+                            );
                             return;
                         }
                     };
-                    if let Err(e) = handle_connection(conn, client_pools, host, port).await {
+                    let _conn_type_watcher = kulfi_utils::watch_conn_type(
+                        &ep,
+                        conn.remote_id(),
+                        kulfi_utils::get_remote_id52(&conn),
+                    );
+                    if let Err(e) = handle_connection(conn, client_pools, host, port, require_token).await {
                         tracing::error!("connection error3: {:?}", e);
                     }
                     tracing::info!("connection handled in {:?}", start.elapsed());
@@ -61,22 +119,46 @@ pub async fn expose_http(
     }
 
     ep.close().await;
+    Ok(())
 }
 
+/// `require_token` is the id52 of the service if peers need an access token for it.
 async fn handle_connection(
     conn: iroh::endpoint::Connection,
     client_pools: kulfi_utils::HttpConnectionPools,
     host: String,
     port: u16,
+    require_token: Option<String>,
 ) -> eyre::Result<()> {
     let remote_id52 = kulfi_utils::get_remote_id52(&conn);
+    let _connection_gauge = kulfi_utils::metrics::track(
+        kulfi_utils::metrics::PEER_CONNECTIONS,
+        &[("peer", &remote_id52), ("direction", "incoming")],
+    );
 
     tracing::info!("new client: {remote_id52}, waiting for bidirectional stream");
     loop {
-        let (mut send, recv) = kulfi_utils::accept_bi(&conn, kulfi_utils::Protocol::Http)
-            .await
-            .inspect_err(|e| tracing::error!("failed to accept bidirectional stream: {e:?}"))?;
+        let (mut send, mut recv) =
+            kulfi_utils::accept_bi(&conn, kulfi_utils::Protocol::Http, require_token.is_some())
+                .await
+                .inspect_err(|e| tracing::error!("failed to accept bidirectional stream: {e:?}"))?;
         tracing::info!("{remote_id52}");
+        let protocol = kulfi_utils::Protocol::Http;
+        if !crate::handle::has_access(
+            &mut send,
+            &mut recv,
+            require_token.as_deref(),
+            &remote_id52,
+            &protocol,
+        )
+        .await
+        {
+            if let Err(e) = forbidden(&mut send).await {
+                tracing::error!("failed to send forbidden: {e:?}");
+            }
+            send.finish()?;
+            continue;
+        }
         let client_pools = client_pools.clone();
         if let Err(e) =
             kulfi_utils::peer_to_http(&format!("{host}:{port}"), client_pools, &mut send, recv)
@@ -89,14 +171,33 @@ async fn handle_connection(
     }
 }
 
+/// the response to requests without a valid access token.
+async fn forbidden(send: &mut iroh::endpoint::SendStream) -> eyre::Result<()> {
+    let body = b"this service needs an access token, ask its owner for one\n";
+    let r = kulfi_utils::http::Response {
+        status: hyper::StatusCode::FORBIDDEN.as_u16(),
+        headers: vec![
+            ("content-type".to_string(), b"text/plain".to_vec()),
+            (
+                "content-length".to_string(),
+                body.len().to_string().into_bytes(),
+            ),
+        ],
+    };
+    send.write_all(&serde_json::to_vec(&r)?).await?;
+    send.write_all(b"\n").await?;
+    send.write_all(body).await?;
+    Ok(())
+}
+
 #[derive(PartialEq, Debug)]
-enum InfoMode {
+pub(crate) enum InfoMode {
     Startup,
     OnExit,
 }
 
 impl InfoMode {
-    fn print(&self, host: &str, port: u16, id52: &str, bridge: &str) {
+    pub(crate) fn print(&self, host: &str, port: u16, id52: &str, bridge: &str) {
         use colored::Colorize;
 
         // Malai: Sharing http://127.0.0.1:3000 at
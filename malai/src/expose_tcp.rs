@@ -3,27 +3,62 @@ pub async fn expose_tcp(
     port: u16,
     id52: String,
     secret_key: kulfi_id52::SecretKey,
+    require_token: bool,
     graceful: kulfi_utils::Graceful,
-) {
-    let ep = match kulfi_utils::get_endpoint(secret_key).await {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Failed to bind to iroh network:");
-            eprintln!("{e:?}");
-            std::process::exit(1);
-        }
-    };
+) -> eyre::Result<()> {
+    let handle = start_expose_tcp(
+        malai::ExposeConfig {
+            host,
+            port,
+            secret_key,
+            require_token,
+        },
+        graceful.clone(),
+    )
+    .await?;
+    kulfi_utils::systemd::notify_ready();
 
     InfoMode::Startup.print(port, &id52);
+    handle
+        .wait_showing_info(graceful, || InfoMode::OnExit.print(port, &id52))
+        .await
+}
+
+/// exposes the tcp service over the kulfi network, without printing anything.
+pub async fn start_expose_tcp(
+    config: malai::ExposeConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
+    use eyre::WrapErr;
+
+    let id52 = config.secret_key.id52();
+    let ep = kulfi_utils::get_endpoint(config.secret_key)
+        .await
+        .wrap_err("failed to bind to iroh network")?;
+
+    let require_token = config.require_token.then(|| id52.clone());
+    let graceful = graceful.child();
+    let task = graceful.spawn(serve(
+        ep,
+        config.host,
+        config.port,
+        require_token,
+        graceful.clone(),
+    ));
+    Ok(malai::ServiceHandle::new(Some(id52), None, graceful, task))
+}
 
-    let mut graceful_mut = graceful.clone();
+async fn serve(
+    ep: iroh::Endpoint,
+    host: String,
+    port: u16,
+    require_token: Option<String>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
     loop {
         let graceful_for_handle_connection = graceful.clone();
 
         tokio::select! {
-            _ = graceful_mut.show_info() => {
-                InfoMode::OnExit.print(port, &id52);
-            }
             _ = graceful.cancelled() => {
                 tracing::info!("Stopping control server.");
                 break;
@@ -37,6 +72,8 @@ pub async fn expose_tcp(
                     }
                 };
                 let host = host.clone();
+                let require_token = require_token.clone();
+                let ep = ep.clone();
 
                 graceful.spawn(async move {
                     let start = std::time::Instant::now();
@@ -44,10 +81,19 @@ pub async fn expose_tcp(
                         Ok(c) => c,
                         Err(e) => {
                             tracing::error!("failed to convert incoming to connection: {:?}", e);
+                            kulfi_utils::metrics::inc(
+                                kulfi_utils::metrics::CONNECTION_ERRORS,
+                                &[("stage", "accept")],
+                            );
                             return;
                         }
                     };
-                    if let Err(e) = handle_connection(conn, host, port, graceful_for_handle_connection).await {
+                    let _conn_type_watcher = kulfi_utils::watch_conn_type(
+                        &ep,
+                        conn.remote_id(),
+                        kulfi_utils::get_remote_id52(&conn),
+                    );
+                    if let Err(e) = handle_connection(conn, host, port, require_token, graceful_for_handle_connection).await {
                         tracing::error!("connection error3: {:?}", e);
                     }
                     tracing::info!("connection handled in {:?}", start.elapsed());
@@ -57,24 +103,46 @@ pub async fn expose_tcp(
     }
 
     ep.close().await;
+    Ok(())
 }
 
+/// `require_token` is the id52 of the service if peers need an access token for it.
 async fn handle_connection(
     conn: iroh::endpoint::Connection,
     host: String,
     port: u16,
+    require_token: Option<String>,
     graceful: kulfi_utils::Graceful,
 ) -> eyre::Result<()> {
     let remote_id52 = kulfi_utils::get_remote_id52(&conn);
+    let _connection_gauge = kulfi_utils::metrics::track(
+        kulfi_utils::metrics::PEER_CONNECTIONS,
+        &[("peer", &remote_id52), ("direction", "incoming")],
+    );
 
     tracing::info!("new client: {remote_id52}, waiting for bidirectional stream");
     loop {
-        let (send, recv) = kulfi_utils::accept_bi(&conn, kulfi_utils::Protocol::Tcp)
-            .await
-            .inspect_err(|e| tracing::error!("failed to accept bidirectional stream: {e:?}"))?;
+        let (mut send, mut recv) =
+            kulfi_utils::accept_bi(&conn, kulfi_utils::Protocol::Tcp, require_token.is_some())
+                .await
+                .inspect_err(|e| tracing::error!("failed to accept bidirectional stream: {e:?}"))?;
         tracing::info!("{remote_id52}");
         let addr = format!("{host}:{port}");
+        let require_token = require_token.clone();
+        let remote_id52 = remote_id52.clone();
         graceful.spawn(async move {
+            let protocol = kulfi_utils::Protocol::Tcp;
+            if !crate::handle::has_access(
+                &mut send,
+                &mut recv,
+                require_token.as_deref(),
+                &remote_id52,
+                &protocol,
+            )
+            .await
+            {
+                return;
+            }
             if let Err(e) = kulfi_utils::peer_to_tcp(&addr, send, recv).await {
                 tracing::error!("failed to proxy tcp: {e:?}");
             }
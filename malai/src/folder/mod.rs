@@ -29,65 +29,93 @@ use render_folder::render_folder;
 ///
 /// having said all that, the first version of malai browsing will be a simple HTML page, and we
 /// will compile `folder.html` template as part of the build process.
-pub async fn folder(path: String, bridge: String, graceful: kulfi_utils::Graceful) {
-    let path = match validate_path(&path) {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("Failed to validate path: {e}");
-            std::process::exit(1);
-        }
-    };
-
-    let listener = match tokio::net::TcpListener::bind("127.0.0.1:0").await {
-        Ok(l) => l,
-        Err(e) => {
-            eprintln!("Failed to bind to port: {e}");
-            std::process::exit(1);
-        }
-    };
+pub async fn folder(
+    path: String,
+    bridge: String,
+    id52: String,
+    secret_key: kulfi_id52::SecretKey,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    let handle = start_folder(
+        FolderConfig {
+            path: path.clone(),
+            secret_key,
+        },
+        graceful.clone(),
+    )
+    .await?;
+    kulfi_utils::systemd::notify_ready();
+    // start_folder() always binds a local address
+    let port = handle.local_addr().map(|a| a.port()).unwrap_or_default();
 
-    let port = match listener.local_addr() {
-        Ok(addr) => addr.port(),
-        Err(e) => {
-            eprintln!("Failed to get local address: {e}");
-            std::process::exit(1);
-        }
-    };
     println!("Serving {path:?} on http://127.0.0.1:{port}");
+    malai::InfoMode::Startup.print("127.0.0.1", port, &id52, &bridge);
+    handle
+        .wait_showing_info(graceful, || {
+            malai::InfoMode::OnExit.print("127.0.0.1", port, &id52, &bridge);
+        })
+        .await
+}
 
-    let graceful_for_expose_http = graceful.clone();
+/// the folder to expose with [`start_folder`].
+pub struct FolderConfig {
+    pub path: String,
+    /// the identity to expose the folder as, peers and bridges connect to its id52
+    pub secret_key: kulfi_id52::SecretKey,
+}
 
-    graceful.spawn(async move {
-        let (id52, secret_key) = match kulfi_utils::read_or_create_key().await {
-            Ok(v) => v,
-            Err(e) => {
-                malai::identity_read_err_msg(e);
-                std::process::exit(1);
-            }
-        };
-        malai::expose_http(
-            "127.0.0.1".to_string(),
-            port,
-            bridge,
-            id52,
-            secret_key,
-            graceful_for_expose_http,
-        )
-        .await
-    });
+/// serves the folder on a random local port, and exposes it over the kulfi network, without
+/// printing anything. the handle has both the id52 and the local address.
+pub async fn start_folder(
+    config: FolderConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
+    use eyre::WrapErr;
 
-    let mut graceful_mut = graceful.clone();
+    let path = validate_path(&config.path).wrap_err("failed to validate path")?;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .wrap_err("failed to bind to port")?;
+    let local_addr = listener
+        .local_addr()
+        .wrap_err("failed to get local address")?;
+
+    let graceful = graceful.child();
+    let expose = malai::start_expose_http(
+        malai::ExposeHttpConfig {
+            host: "127.0.0.1".to_string(),
+            port: local_addr.port(),
+            secret_key: config.secret_key,
+            require_token: false,
+        },
+        graceful.clone(),
+    )
+    .await?;
+    let id52 = expose.id52().map(ToString::to_string);
+
+    let task = graceful.spawn(serve(listener, path, expose, graceful.clone()));
+    Ok(malai::ServiceHandle::new(
+        id52,
+        Some(local_addr),
+        graceful,
+        task,
+    ))
+}
 
+async fn serve(
+    listener: tokio::net::TcpListener,
+    path: std::path::PathBuf,
+    mut expose: malai::ServiceHandle,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
     loop {
         tokio::select! {
             _ = graceful.cancelled() => {
                 tracing::info!("Stopping control server.");
                 break;
             }
-            _ = graceful_mut.show_info() => {
-                println!("Listening on http://127.0.0.1:{port}");
-                println!("Press ctrl+c again to exit.");
-            }
+            // the folder is not reachable without it
+            r = expose.wait() => return r,
             conn = listener.accept() => {
                 match conn {
                     Ok((stream, _)) => {
@@ -102,6 +130,8 @@ pub async fn folder(path: String, bridge: String, graceful: kulfi_utils::Gracefu
             }
         }
     }
+
+    Ok(())
 }
 
 pub async fn handle_connection(
@@ -0,0 +1,165 @@
+/// A service started by one of the `start_*` functions, e.g., [`crate::start_expose_http`].
+///
+/// the `start_*` functions return once the service is set up, so errors like a busy port are
+/// returned from them, and the service then runs in the background till it is stopped, the handle
+/// is dropped, or the `Graceful` it was started with is cancelled. they print nothing, the id52
+/// and the address the service listens on are available here instead.
+pub struct ServiceHandle {
+    id52: Option<String>,
+    local_addr: Option<std::net::SocketAddr>,
+    graceful: kulfi_utils::Graceful,
+    task: tokio::task::JoinHandle<eyre::Result<()>>,
+}
+
+impl ServiceHandle {
+    /// `graceful` must be the one `task` was spawned on, and only used by this service, it is
+    /// cancelled by `stop()`.
+    pub(crate) fn new(
+        id52: Option<String>,
+        local_addr: Option<std::net::SocketAddr>,
+        graceful: kulfi_utils::Graceful,
+        task: tokio::task::JoinHandle<eyre::Result<()>>,
+    ) -> Self {
+        Self {
+            id52,
+            local_addr,
+            graceful,
+            task,
+        }
+    }
+
+    /// the id52 peers connect to, for services exposed on the kulfi network.
+    pub fn id52(&self) -> Option<&str> {
+        self.id52.as_deref()
+    }
+
+    /// where bridges, proxies and folders listen, with the actual port if 0 was asked for.
+    pub fn local_addr(&self) -> Option<std::net::SocketAddr> {
+        self.local_addr
+    }
+
+    /// asks the service to stop, `wait()` returns once it has.
+    pub fn stop(&self) {
+        self.graceful.cancel();
+    }
+
+    /// waits till the service stops, returns the error if it failed. it can be used in
+    /// `tokio::select!`, but must not be called again once it has returned.
+    pub async fn wait(&mut self) -> eyre::Result<()> {
+        let r = (&mut self.task).await;
+        // a service that failed on its own leaves its reloaders etc. running otherwise
+        self.graceful.cancel();
+        r.map_err(|e| eyre::anyhow!("service task failed: {e}"))?
+    }
+
+    /// what the cli wrappers, e.g., [`crate::expose_tcp`], do after starting a service: wait for
+    /// it, calling `show_info` on the first ctrl+c.
+    pub(crate) async fn wait_showing_info(
+        mut self,
+        graceful: kulfi_utils::Graceful,
+        show_info: impl Fn(),
+    ) -> eyre::Result<()> {
+        let mut graceful = graceful;
+        loop {
+            tokio::select! {
+                _ = graceful.show_info() => show_info(),
+                r = self.wait() => return r,
+            }
+        }
+    }
+}
+
+/// dropping the handle stops the service, like `stop()`, without waiting for it.
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        self.graceful.cancel();
+    }
+}
+
+/// the tcp, udp or tcp+udp service to expose with [`crate::start_expose_tcp`],
+/// [`crate::start_expose_udp`] or [`crate::start_expose_tcp_udp`].
+pub struct ExposeConfig {
+    /// where the service runs, e.g., `127.0.0.1`
+    pub host: String,
+    pub port: u16,
+    /// the identity to expose the service as, peers connect to its id52
+    pub secret_key: kulfi_id52::SecretKey,
+    /// only let in peers with an access token signed by the identity, see
+    /// `kulfi_utils::AccessToken`
+    pub require_token: bool,
+}
+
+/// false, after logging why, if `require_token` is the id52 of the service and `peer` did not
+/// send a valid access token for `protocol` of it. the stream must have been accepted with
+/// `require_token`, so the peer was asked for the token.
+pub(crate) async fn has_access(
+    send: &mut iroh::endpoint::SendStream,
+    recv: &mut iroh::endpoint::RecvStream,
+    require_token: Option<&str>,
+    peer: &str,
+    protocol: &kulfi_utils::Protocol,
+) -> bool {
+    let Some(service) = require_token else {
+        return true;
+    };
+    match kulfi_utils::read_access_token(send, recv, service, peer, protocol).await {
+        Ok(token) => {
+            tracing::info!(peer, expires = token.expires, "access token accepted");
+            true
+        }
+        Err(e) => {
+            tracing::warn!(peer, "no valid access token: {e:?}");
+            false
+        }
+    }
+}
+
+/// the local port to listen on, 0 picks a free one, and the peer to forward to, for
+/// [`crate::start_tcp_bridge`] and [`crate::start_udp_bridge`].
+pub struct BridgeConfig {
+    pub port: u16,
+    /// the id52 of the peer exposing the service
+    pub proxy_target: String,
+    /// the access token to send, for services that require one
+    pub token: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn bridge_on_free_port() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let graceful = kulfi_utils::Graceful::new();
+            let mut handle = malai::start_tcp_bridge(
+                malai::BridgeConfig {
+                    port: 0,
+                    proxy_target: "unused".to_string(),
+                    token: None,
+                },
+                graceful.clone(),
+            )
+            .await
+            .unwrap();
+
+            let local_addr = handle.local_addr().unwrap();
+            assert_ne!(local_addr.port(), 0);
+            assert_eq!(handle.id52(), None);
+
+            // the port is taken till the bridge is stopped
+            let busy = malai::start_tcp_bridge(
+                malai::BridgeConfig {
+                    port: local_addr.port(),
+                    proxy_target: "unused".to_string(),
+                    token: None,
+                },
+                graceful.clone(),
+            )
+            .await;
+            assert!(busy.is_err());
+
+            handle.stop();
+            handle.wait().await.unwrap();
+            assert!(!graceful.is_cancelled());
+        });
+    }
+}
@@ -0,0 +1,287 @@
+//! Short aliases for id52s, so bridge urls are easier to share.
+//!
+//! a full id52 is 52 chars, which fits in a DNS label (max 63) but is a mouthful. the bridge keeps
+//! a registry of known exposers (a file or a url, see `reload.rs`), one per line, either just
+//! `<id52>` or `<name> <id52>`. with it, the first label of the host can be:
+//!
+//! - a prefix of a known id52, at least `min_prefix` chars long, as long as exactly one known id52
+//!   starts with it. ambiguous prefixes are rejected, never guessed.
+//! - a human readable name, e.g., `docs.bridge.example.com`. names that could be mistaken for an
+//!   id52 prefix are dropped when the registry is loaded, so names and prefixes never collide.
+//!   names that are id52s themselves are dropped too, and a 52 char label is never resolved as an
+//!   alias, so a registry entry can not send traffic for one id52 to another.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+pub const DEFAULT_MIN_PREFIX: usize = 8;
+
+#[derive(Debug, Default, Clone)]
+pub struct AliasesConfig {
+    /// a file path or http(s) url with the known exposers, reloaded when it changes
+    pub registry: Option<String>,
+    pub reload_interval: Option<std::time::Duration>,
+    /// shortest prefix accepted, `DEFAULT_MIN_PREFIX` if not set
+    pub min_prefix: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Resolved {
+    Id52(String),
+    /// the prefix matches more than one known id52
+    Ambiguous,
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    names: HashMap<String, String>,
+    /// sorted, so all ids with a given prefix are next to each other
+    ids: Vec<String>,
+}
+
+impl Table {
+    fn with_prefix(&self, prefix: &str) -> &[String] {
+        let start = self.ids.partition_point(|id| id.as_str() < prefix);
+        let len = self.ids[start..]
+            .iter()
+            .take_while(|id| id.starts_with(prefix))
+            .count();
+        &self.ids[start..start + len]
+    }
+
+    /// the shortest prefix of `id52` that no other known id52 shares.
+    fn shortest_alias(&self, id52: &str, min_prefix: usize) -> String {
+        (min_prefix..id52.len())
+            .map(|n| &id52[..n])
+            .find(|prefix| self.with_prefix(prefix).len() == 1)
+            .unwrap_or(id52)
+            .to_string()
+    }
+}
+
+pub struct Aliases {
+    table: RwLock<Table>,
+    min_prefix: usize,
+}
+
+impl Aliases {
+    /// returns `None` if no registry is configured.
+    pub async fn new(
+        config: AliasesConfig,
+        graceful: kulfi_utils::Graceful,
+    ) -> eyre::Result<Option<Arc<Self>>> {
+        let registry = match config.registry {
+            Some(registry) => registry,
+            None => return Ok(None),
+        };
+
+        let aliases = Arc::new(Self {
+            table: RwLock::new(Table::default()),
+            min_prefix: config.min_prefix.unwrap_or(DEFAULT_MIN_PREFIX),
+        });
+
+        let aliases_for_watch = aliases.clone();
+        super::reload::watch(
+            super::reload::Source::parse(&registry),
+            config
+                .reload_interval
+                .unwrap_or(super::reload::DEFAULT_RELOAD_INTERVAL),
+            graceful,
+            move |content| {
+                let table = parse_registry(content, aliases_for_watch.min_prefix);
+                for id in table.ids.iter() {
+                    tracing::info!(
+                        "{id} is reachable as {}",
+                        table.shortest_alias(id, aliases_for_watch.min_prefix)
+                    );
+                }
+                for (name, id) in table.names.iter() {
+                    tracing::info!("{id} is reachable as {name}");
+                }
+                *aliases_for_watch.table.write().unwrap() = table;
+            },
+        )
+        .await?;
+
+        Ok(Some(aliases))
+    }
+
+    /// resolves the first label of the host, `None` if it is not an alias.
+    pub fn resolve(&self, label: &str) -> Option<Resolved> {
+        // a full id52 is used as is, never looked up
+        if label.len() == 52 {
+            return None;
+        }
+
+        let label = label.to_lowercase();
+        let table = self.table.read().unwrap();
+
+        if let Some(id52) = table.names.get(&label) {
+            return Some(Resolved::Id52(id52.clone()));
+        }
+
+        if !could_be_prefix(&label, self.min_prefix) {
+            return None;
+        }
+
+        match table.with_prefix(&label) {
+            [] => None,
+            [id52] => Some(Resolved::Id52(id52.clone())),
+            _ => Some(Resolved::Ambiguous),
+        }
+    }
+}
+
+fn could_be_prefix(label: &str, min_prefix: usize) -> bool {
+    const ALPHABET: &str = "0123456789abcdefghijklmnopqrstuv";
+
+    (min_prefix..52).contains(&label.len()) && label.chars().all(|c| ALPHABET.contains(c))
+}
+
+fn parse_registry(content: &str, min_prefix: usize) -> Table {
+    let mut table = Table::default();
+
+    let lines = content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty());
+
+    for line in lines {
+        let parts: Vec<_> = line.split_whitespace().collect();
+        let (name, id52) = match parts.as_slice() {
+            [id52] => (None, *id52),
+            [name, id52] => (Some(name.to_lowercase()), *id52),
+            _ => {
+                tracing::warn!("ignoring invalid line in alias registry: {line}");
+                continue;
+            }
+        };
+
+        if kulfi_id52::PublicKey::from_str(id52).is_err() {
+            tracing::warn!("ignoring invalid id52 in alias registry: {line}");
+            continue;
+        }
+        table.ids.push(id52.to_string());
+
+        if let Some(name) = name {
+            if name.len() > 63
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            {
+                tracing::warn!("ignoring alias {name}, it is not a valid subdomain");
+                continue;
+            }
+            if kulfi_id52::PublicKey::from_str(&name).is_ok() {
+                tracing::warn!("ignoring alias {name}, it is an id52");
+                continue;
+            }
+            if let Some(other) = table.names.insert(name.clone(), id52.to_string())
+                && other != id52
+            {
+                tracing::warn!("alias {name} is used for both {other} and {id52}, using {id52}");
+            }
+        }
+    }
+
+    table.ids.sort();
+    table.ids.dedup();
+
+    // checked after all ids are known, so the order of the lines does not matter
+    table.names.retain(|name, _| {
+        let collides = could_be_prefix(name, min_prefix) && !table.ids.is_empty() && {
+            let start = table.ids.partition_point(|id| id.as_str() < name.as_str());
+            table
+                .ids
+                .get(start)
+                .is_some_and(|id| id.starts_with(name.as_str()))
+        };
+        if collides {
+            tracing::warn!("ignoring alias {name}, it is also the prefix of a known id52");
+        }
+        !collides
+    });
+
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::Resolved;
+
+    const A: &str = "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60";
+    const B: &str = "i66fo538e7aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9a";
+    const C: &str = "e87aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9as9b7v80";
+
+    fn aliases(registry: &str) -> super::Aliases {
+        super::Aliases {
+            table: std::sync::RwLock::new(super::parse_registry(registry, 8)),
+            min_prefix: 8,
+        }
+    }
+
+    #[test]
+    fn test_prefix_aliases() {
+        // not going through `parse_registry`, as B is made up to share a prefix with A and need
+        // not be a valid public key
+        let mut ids = vec![A.to_string(), B.to_string(), C.to_string()];
+        ids.sort();
+        let aliases = super::Aliases {
+            table: std::sync::RwLock::new(super::Table {
+                names: Default::default(),
+                ids,
+            }),
+            min_prefix: 8,
+        };
+
+        assert_eq!(aliases.resolve("e87aeds2"), Some(Resolved::Id52(C.into())));
+        assert_eq!(aliases.resolve("i66fo538"), Some(Resolved::Ambiguous));
+        assert_eq!(aliases.resolve("i66fo538l"), Some(Resolved::Id52(A.into())));
+        // shorter than min_prefix
+        assert_eq!(aliases.resolve("e87aeds"), None);
+        assert_eq!(aliases.resolve("0000000000"), None);
+
+        let table = aliases.table.read().unwrap();
+        assert_eq!(table.shortest_alias(A, 8), "i66fo538l");
+        assert_eq!(table.shortest_alias(C, 8), "e87aeds2");
+    }
+
+    #[test]
+    fn test_named_aliases() {
+        let aliases = aliases(&format!(
+            "# known exposers\n\
+             docs {A}\n\
+             e87aeds2fa {A}  # would shadow the prefix of the next id\n\
+             {C}\n\
+             Not_Valid {C}\n\
+             {C} {A}  # would send the traffic for C to A\n"
+        ));
+
+        assert_eq!(aliases.resolve("docs"), Some(Resolved::Id52(A.into())));
+        assert_eq!(aliases.resolve("DOCS"), Some(Resolved::Id52(A.into())));
+        assert_eq!(
+            aliases.resolve("e87aeds2fa"),
+            Some(Resolved::Id52(C.into()))
+        );
+        assert_eq!(aliases.resolve("not_valid"), None);
+        assert_eq!(aliases.resolve("blog"), None);
+        assert_eq!(aliases.resolve(C), None);
+        assert!(!aliases.table.read().unwrap().names.contains_key(C));
+    }
+
+    #[test]
+    fn test_full_id52_is_not_an_alias() {
+        // even if a name of that length got into the table
+        let aliases = aliases("");
+        aliases
+            .table
+            .write()
+            .unwrap()
+            .names
+            .insert(C.to_string(), A.to_string());
+
+        assert_eq!(aliases.resolve(C), None);
+        assert_eq!(aliases.resolve(&C.to_uppercase()), None);
+    }
+}
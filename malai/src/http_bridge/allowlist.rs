@@ -0,0 +1,108 @@
+//! The set of id52s a bridge is willing to forward to.
+//!
+//! without an allowlist the bridge forwards to any id52, which makes a public bridge an open proxy
+//! to the whole network. the allowlist is the union of the ids given on the command line and the
+//! ids listed in an optional registry (a file or a url, see `reload.rs`), one id52 per line, with
+//! `#` starting a comment.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Default, Clone)]
+pub struct AllowlistConfig {
+    pub ids: Vec<String>,
+    /// a file path or http(s) url, reloaded when it changes
+    pub registry: Option<String>,
+    pub reload_interval: Option<std::time::Duration>,
+}
+
+impl AllowlistConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.ids.is_empty() || self.registry.is_some()
+    }
+}
+
+pub struct Allowlist {
+    fixed: HashSet<String>,
+    registry: RwLock<HashSet<String>>,
+}
+
+impl Allowlist {
+    /// returns `None` if no allowlist is configured, i.e., everything is allowed.
+    pub async fn new(
+        config: AllowlistConfig,
+        graceful: kulfi_utils::Graceful,
+    ) -> eyre::Result<Option<Arc<Self>>> {
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+
+        let mut fixed = HashSet::new();
+        for id in config.ids.iter() {
+            kulfi_id52::PublicKey::from_str(id).map_err(|e| eyre::anyhow!("{e}"))?;
+            fixed.insert(id.to_string());
+        }
+
+        let allowlist = Arc::new(Self {
+            fixed,
+            registry: RwLock::new(HashSet::new()),
+        });
+
+        if let Some(registry) = config.registry {
+            let allowlist = allowlist.clone();
+            super::reload::watch(
+                super::reload::Source::parse(&registry),
+                config
+                    .reload_interval
+                    .unwrap_or(super::reload::DEFAULT_RELOAD_INTERVAL),
+                graceful,
+                move |content| {
+                    let ids = parse_registry(content);
+                    tracing::info!("allowlist registry has {} ids", ids.len());
+                    *allowlist.registry.write().unwrap() = ids;
+                },
+            )
+            .await?;
+        }
+
+        Ok(Some(allowlist))
+    }
+
+    pub fn is_allowed(&self, id52: &str) -> bool {
+        self.fixed.contains(id52) || self.registry.read().unwrap().contains(id52)
+    }
+}
+
+fn parse_registry(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .filter(|id| match kulfi_id52::PublicKey::from_str(id) {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::warn!("ignoring invalid id52 in allowlist registry: {e}");
+                false
+            }
+        })
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_parse_registry() {
+        let ids = super::parse_registry(
+            "# team services\n\
+             i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60\n\
+             \n\
+             e87aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9as9b7v80  # docs\n\
+             not-an-id52\n",
+        );
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains("i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60"));
+        assert!(ids.contains("e87aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9as9b7v80"));
+    }
+}
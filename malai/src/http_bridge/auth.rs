@@ -0,0 +1,1391 @@
+//! Logging in browser users at the bridge, configured per target id52.
+//!
+//! a bridge forwards to anyone who knows the url. with an auth file it asks for a login before
+//! forwarding to the id52s listed in it, so internal dashboards can be shared with a team without
+//! the app implementing auth itself. the file is toml, a file path or a url, reloaded when it
+//! changes (see `reload.rs`):
+//!
+//! ```toml
+//! # signs the login cookies, without it logins do not survive a restart of the bridge
+//! cookie_secret = "a long random string"
+//!
+//! [target.<id52>]
+//! method = "basic"
+//! users = { alice = "<from malai hash-password>" }
+//!
+//! # every id52 not listed
+//! [target."*"]
+//! method = "oidc"
+//! issuer = "http://127.0.0.1:5556/dex"
+//! client_id = "bridge"
+//! client_secret = "..."
+//! ```
+//!
+//! the methods are:
+//!
+//! - basic: HTTP basic auth, the browser asks for the user and password on every site.
+//! - login: a login page, the user gets a signed cookie that lasts for `session` seconds.
+//! - oidc: the openid connect authorization code flow against `issuer`, with the same cookie.
+//!   `allowed_users` (verified emails or `sub`s) and `allowed_domains` (of verified emails) limit
+//!   who gets in, without them anyone the provider knows does. `preferred_username` is never
+//!   used, users can often change it to anything.
+//! - none: no login, for id52s that should not get the `*` one.
+//!
+//! the pages of the bridge are under `/.kulfi/auth/` of each site. the `Authorization` headers the
+//! bridge accepted and the cookies of the bridge are not forwarded to any id52, path routed sites
+//! share the host of the bridge, so browsers send them to all of them. the user is sent in
+//! `X-Forwarded-User`.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+pub const AUTH_PATH: &str = "/.kulfi/auth";
+pub const USER_HEADER: &str = "x-forwarded-user";
+const COOKIE_PREFIX: &str = "kulfi-auth-";
+const STATE_COOKIE_PREFIX: &str = "kulfi-auth-state-";
+const DEFAULT_SESSION: u64 = 12 * 60 * 60;
+/// how long the user has to log in at the provider
+const STATE_TTL: u64 = 10 * 60;
+/// hashing the password on every request is slow, so accepted ones are remembered for a while
+const BASIC_TTL: Duration = Duration::from_secs(5 * 60);
+const MAX_FORM_SIZE: usize = 16 * 1024;
+
+type Response = kulfi_utils::http::ProxyResponse<eyre::Error>;
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+#[derive(Debug, Default, Clone)]
+pub struct AuthConfig {
+    /// a file path or http(s) url with the auth file, reloaded when it changes
+    pub file: Option<String>,
+    pub reload_interval: Option<Duration>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuthFile {
+    cookie_secret: Option<String>,
+    #[serde(default)]
+    target: HashMap<String, Method>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase", deny_unknown_fields)]
+enum Method {
+    #[serde(rename = "none")]
+    Open,
+    Basic {
+        #[serde(default = "default_realm")]
+        realm: String,
+        /// user -> argon2 hash of the password
+        users: HashMap<String, String>,
+    },
+    Login {
+        users: HashMap<String, String>,
+        #[serde(default = "default_session")]
+        session: u64,
+    },
+    Oidc(Box<Oidc>),
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Oidc {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    /// by default `http(s)://<host>/.kulfi/auth/callback` of the site, it has to be registered
+    /// with the provider
+    redirect_url: Option<String>,
+    #[serde(default = "default_scopes")]
+    scopes: Vec<String>,
+    #[serde(default)]
+    allowed_users: Vec<String>,
+    #[serde(default)]
+    allowed_domains: Vec<String>,
+    #[serde(default = "default_session")]
+    session: u64,
+    /// discovered on the first login
+    #[serde(skip)]
+    provider: tokio::sync::OnceCell<Provider>,
+}
+
+fn default_realm() -> String {
+    "malai".to_string()
+}
+
+fn default_session() -> u64 {
+    DEFAULT_SESSION
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+/// the parts of `/.well-known/openid-configuration` we use.
+#[derive(Debug, serde::Deserialize)]
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+/// the login cookie.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Session {
+    target: String,
+    user: String,
+    expires: u64,
+}
+
+/// the `state` of an oidc login, the nonce is also in a cookie, so the login is finished by the
+/// browser that started it.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct State {
+    target: String,
+    next: String,
+    nonce: String,
+    expires: u64,
+}
+
+/// the claims of an id token we check or use.
+#[derive(Debug, serde::Deserialize)]
+struct IdToken {
+    iss: String,
+    /// a string or a list of them
+    aud: serde_json::Value,
+    exp: u64,
+    nonce: Option<String>,
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+}
+
+fn parse_file(content: &str) -> eyre::Result<AuthFile> {
+    let file: AuthFile = toml::from_str(content)?;
+    for (target, method) in file.target.iter() {
+        if target != "*" {
+            kulfi_id52::PublicKey::from_str(target)
+                .map_err(|e| eyre::anyhow!("target.{target}: {e}"))?;
+        }
+        if let Method::Basic { users, .. } | Method::Login { users, .. } = method {
+            for (user, hash) in users.iter() {
+                if user.contains(':') {
+                    return Err(eyre::anyhow!(
+                        "target.{target}: user {user} can not have a `:` in it"
+                    ));
+                }
+                argon2::PasswordHash::new(hash).map_err(|e| {
+                    eyre::anyhow!(
+                        "target.{target}: the password hash of {user} is not valid, see `malai \
+                         hash-password`: {e}"
+                    )
+                })?;
+            }
+        }
+    }
+    Ok(file)
+}
+
+/// errors if the auth file at `path` is not valid, for `malai validate`.
+pub fn check_file(path: &str) -> eyre::Result<()> {
+    use eyre::WrapErr;
+
+    let content = std::fs::read_to_string(path).wrap_err_with(|| format!("can not read {path}"))?;
+    parse_file(&content).map(|_| ())
+}
+
+/// asks for a password, or reads it from stdin, and prints its hash for the `users` of an auth
+/// file.
+pub fn hash_password() -> eyre::Result<()> {
+    use eyre::WrapErr;
+    use std::io::IsTerminal;
+
+    let password = if std::io::stdin().is_terminal() {
+        let password =
+            rpassword::prompt_password("Password: ").wrap_err("failed to read password")?;
+        let again =
+            rpassword::prompt_password("Password (again): ").wrap_err("failed to read password")?;
+        if again != password {
+            return Err(eyre::anyhow!("the passwords do not match"));
+        }
+        password
+    } else {
+        let mut password = String::new();
+        std::io::stdin()
+            .read_line(&mut password)
+            .wrap_err("failed to read stdin")?;
+        password.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        return Err(eyre::anyhow!("the password can not be empty"));
+    }
+
+    println!("{}", hash(&password)?);
+    Ok(())
+}
+
+fn hash(password: &str) -> eyre::Result<String> {
+    use argon2::PasswordHasher;
+
+    let salt = argon2::password_hash::SaltString::generate(&mut rand::rngs::OsRng);
+    argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| eyre::anyhow!("failed to hash the password: {e}"))
+}
+
+async fn verify_password(password: String, hash: String) -> bool {
+    use argon2::PasswordVerifier;
+
+    tokio::task::spawn_blocking(move || {
+        argon2::PasswordHash::new(&hash).is_ok_and(|hash| {
+            argon2::Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false)
+}
+
+pub struct Auth {
+    file: RwLock<Arc<AuthFile>>,
+    /// signs the cookies if the file has no `cookie_secret`
+    random_secret: [u8; 32],
+    /// mac of accepted `Authorization` headers -> (id52, user, when)
+    verified: Mutex<HashMap<Vec<u8>, (String, String, Instant)>>,
+    client: reqwest::Client,
+}
+
+impl Auth {
+    /// returns `None` if no auth file is configured, i.e., no id52 needs a login.
+    pub async fn new(
+        config: AuthConfig,
+        graceful: kulfi_utils::Graceful,
+    ) -> eyre::Result<Option<Arc<Self>>> {
+        use eyre::WrapErr;
+
+        let file = match config.file {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        // mistakes in the file are fatal at start, later ones are logged and the old file is kept
+        let source = super::reload::Source::parse(&file);
+        let initial = parse_file(&source.load().await?)
+            .wrap_err_with(|| format!("{source} is not a valid auth file"))?;
+        if initial.cookie_secret.is_none() {
+            tracing::warn!("the auth file has no cookie_secret, logins end when the bridge stops");
+        }
+
+        let auth = Arc::new(Self {
+            file: RwLock::new(Arc::new(initial)),
+            random_secret: rand::random(),
+            verified: Mutex::new(HashMap::new()),
+            client: reqwest::Client::new(),
+        });
+
+        let auth_for_watch = auth.clone();
+        super::reload::watch(
+            source,
+            config
+                .reload_interval
+                .unwrap_or(super::reload::DEFAULT_RELOAD_INTERVAL),
+            graceful,
+            move |content| match parse_file(content) {
+                Ok(file) => {
+                    tracing::info!("auth file has {} targets", file.target.len());
+                    *auth_for_watch.file.write().unwrap() = Arc::new(file);
+                    auth_for_watch.verified.lock().unwrap().clear();
+                }
+                Err(e) => tracing::error!("ignoring invalid auth file: {e:#}"),
+            },
+        )
+        .await?;
+
+        Ok(Some(auth))
+    }
+
+    /// lets `r` through, with the user in `X-Forwarded-User`, if `peer_id` needs no login or the
+    /// user has logged in, otherwise returns the response asking for a login, or a page of the
+    /// bridge. `base` is the `/~<label>` prefix of path routed requests, empty otherwise.
+    pub async fn check<B>(
+        &self,
+        mut r: hyper::Request<B>,
+        peer_id: &str,
+        base: &str,
+    ) -> Result<hyper::Request<B>, Response>
+    where
+        B: hyper::body::Body,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let file = self.file.read().unwrap().clone();
+        let secret = file
+            .cookie_secret
+            .as_deref()
+            .map_or(self.random_secret.as_slice(), str::as_bytes);
+
+        // only the bridge gets to say who the user is
+        r.headers_mut().remove(USER_HEADER);
+
+        let mut r = match file.target.get(peer_id).or_else(|| file.target.get("*")) {
+            Some(method) => self.authenticate(r, method, peer_id, base, secret).await?,
+            None => r,
+        };
+
+        // path routed sites share the host of the bridge, so browsers send the login cookies, and
+        // basic auth, of one site along to the others, no service gets to see them
+        strip_cookies(r.headers_mut());
+        if let Some(header) = r.headers().get(hyper::header::AUTHORIZATION)
+            && self.is_verified(header, secret)
+        {
+            r.headers_mut().remove(hyper::header::AUTHORIZATION);
+        }
+        Ok(r)
+    }
+
+    async fn authenticate<B>(
+        &self,
+        mut r: hyper::Request<B>,
+        method: &Method,
+        peer_id: &str,
+        base: &str,
+        secret: &[u8],
+    ) -> Result<hyper::Request<B>, Response>
+    where
+        B: hyper::body::Body,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let user = match method {
+            Method::Open => return Ok(r),
+            Method::Basic { realm, users } => {
+                match self.basic_user(r.headers(), users, peer_id, secret).await {
+                    Some(user) => {
+                        r.headers_mut().remove(hyper::header::AUTHORIZATION);
+                        user
+                    }
+                    None => return Err(ask_for_password(realm)),
+                }
+            }
+            Method::Login { .. } | Method::Oidc(_) => {
+                if let Some(page) = r.uri().path().strip_prefix(AUTH_PATH) {
+                    let page = page.to_string();
+                    return Err(self.page(r, &page, method, peer_id, base, secret).await);
+                }
+                match session_user(r.headers(), peer_id, secret, now()) {
+                    Some(user) => user,
+                    None => {
+                        return Err(self.ask_for_login(&r, method, peer_id, base, secret).await);
+                    }
+                }
+            }
+        };
+
+        tracing::debug!(peer_id, user, "logged in user");
+        match user.parse() {
+            Ok(value) => {
+                r.headers_mut().insert(USER_HEADER, value);
+            }
+            Err(_) => tracing::warn!(user, "can not send the user in a header"),
+        }
+        Ok(r)
+    }
+
+    async fn basic_user(
+        &self,
+        headers: &hyper::HeaderMap,
+        users: &HashMap<String, String>,
+        peer_id: &str,
+        secret: &[u8],
+    ) -> Option<String> {
+        let header = headers.get(hyper::header::AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, encoded) = header.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+
+        let key = basic_key(header.as_bytes(), secret);
+        if let Some((target, user, at)) = self.verified.lock().unwrap().get(&key)
+            && target == peer_id
+            && at.elapsed() < BASIC_TTL
+        {
+            return Some(user.clone());
+        }
+
+        let decoded = data_encoding::BASE64
+            .decode(encoded.trim().as_bytes())
+            .ok()?;
+        let (user, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+        let hash = users.get(user)?;
+        if !verify_password(password.to_string(), hash.clone()).await {
+            tracing::info!(peer_id, user, "wrong password");
+            return None;
+        }
+
+        let mut verified = self.verified.lock().unwrap();
+        verified.retain(|_, (_, _, at)| at.elapsed() < BASIC_TTL);
+        verified.insert(key, (peer_id.to_string(), user.to_string(), Instant::now()));
+        Some(user.to_string())
+    }
+
+    /// true if `header` is basic auth the bridge has recently accepted, for any id52.
+    fn is_verified(&self, header: &hyper::header::HeaderValue, secret: &[u8]) -> bool {
+        self.verified
+            .lock()
+            .unwrap()
+            .contains_key(&basic_key(header.as_bytes(), secret))
+    }
+
+    /// the pages under `/.kulfi/auth/`.
+    async fn page<B>(
+        &self,
+        r: hyper::Request<B>,
+        page: &str,
+        method: &Method,
+        peer_id: &str,
+        base: &str,
+        secret: &[u8],
+    ) -> Response
+    where
+        B: hyper::body::Body,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let secure = is_https(r.headers());
+        let action = format!("{base}{AUTH_PATH}/login");
+        let is_post = r.method() == hyper::Method::POST;
+
+        match (page, method, is_post) {
+            ("/login", Method::Login { .. }, false) => {
+                let next = safe_next(query_param(r.uri(), "next").as_deref());
+                login_page(&action, &next, "", hyper::StatusCode::OK)
+            }
+            ("/login", Method::Login { users, session }, true) => {
+                let form = match read_form(r).await {
+                    Ok(form) => form,
+                    Err(e) => {
+                        tracing::info!(peer_id, "invalid login form: {e:#}");
+                        return text(hyper::StatusCode::BAD_REQUEST, "invalid login form");
+                    }
+                };
+                let next = safe_next(form.get("next").map(String::as_str));
+                let user = form.get("username").cloned().unwrap_or_default();
+                let password = form.get("password").cloned().unwrap_or_default();
+                let correct = match users.get(&user) {
+                    Some(hash) => verify_password(password, hash.clone()).await,
+                    None => false,
+                };
+                if !correct {
+                    tracing::info!(peer_id, user, "wrong password");
+                    return login_page(
+                        &action,
+                        &next,
+                        "Wrong user or password.",
+                        hyper::StatusCode::UNAUTHORIZED,
+                    );
+                }
+                logged_in(peer_id, &user, *session, base, &next, secure, secret)
+            }
+            ("/callback", Method::Oidc(oidc), false) => {
+                match self.callback(&r, oidc, peer_id, base, secret).await {
+                    Ok((user, next)) => {
+                        let mut resp =
+                            logged_in(peer_id, &user, oidc.session, base, &next, secure, secret);
+                        set_cookie(&mut resp, &state_cookie(peer_id), "", 0, base, secure);
+                        resp
+                    }
+                    Err(e) => {
+                        tracing::info!(peer_id, "oidc login failed: {e:#}");
+                        text(
+                            hyper::StatusCode::FORBIDDEN,
+                            &format!("failed to log in: {e}"),
+                        )
+                    }
+                }
+            }
+            ("/logout", _, _) => {
+                let mut resp = text(hyper::StatusCode::OK, "logged out");
+                set_cookie(&mut resp, &session_cookie(peer_id), "", 0, base, secure);
+                resp
+            }
+            _ => text(hyper::StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    async fn ask_for_login<B>(
+        &self,
+        r: &hyper::Request<B>,
+        method: &Method,
+        peer_id: &str,
+        base: &str,
+        secret: &[u8],
+    ) -> Response {
+        // only pages can be logged in to, a login page is no use to a script posting a form
+        if r.method() != hyper::Method::GET && r.method() != hyper::Method::HEAD {
+            return text(
+                hyper::StatusCode::UNAUTHORIZED,
+                &format!("log in at {base}{AUTH_PATH}/login first"),
+            );
+        }
+        let next = r.uri().path_and_query().map_or("/", |p| p.as_str());
+
+        match method {
+            Method::Oidc(oidc) => {
+                match self
+                    .start_oidc(r.headers(), oidc, peer_id, base, next, secret)
+                    .await
+                {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        tracing::error!(peer_id, "failed to start oidc login: {e:#}");
+                        text(
+                            hyper::StatusCode::BAD_GATEWAY,
+                            "the login provider is not reachable",
+                        )
+                    }
+                }
+            }
+            _ => redirect(&format!(
+                "{base}{AUTH_PATH}/login?{}",
+                form_urlencoded::Serializer::new(String::new())
+                    .append_pair("next", next)
+                    .finish()
+            )),
+        }
+    }
+
+    /// sends the user to the provider, which sends them back to `/.kulfi/auth/callback`.
+    async fn start_oidc(
+        &self,
+        headers: &hyper::HeaderMap,
+        oidc: &Oidc,
+        peer_id: &str,
+        base: &str,
+        next: &str,
+        secret: &[u8],
+    ) -> eyre::Result<Response> {
+        let provider = oidc.provider(&self.client).await?;
+        let nonce = data_encoding::BASE64URL_NOPAD.encode(&rand::random::<[u8; 16]>());
+        let state = seal(
+            secret,
+            "state",
+            &State {
+                target: peer_id.to_string(),
+                next: next.to_string(),
+                nonce: nonce.clone(),
+                expires: now() + STATE_TTL,
+            },
+        );
+
+        let separator = if provider.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let location = format!(
+            "{}{separator}{}",
+            provider.authorization_endpoint,
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &oidc.client_id)
+                .append_pair("redirect_uri", &oidc.redirect_url(headers, base)?)
+                .append_pair("scope", &oidc.scopes.join(" "))
+                .append_pair("state", &state)
+                .append_pair("nonce", &nonce)
+                .finish()
+        );
+
+        let mut resp = redirect(&location);
+        set_cookie(
+            &mut resp,
+            &state_cookie(peer_id),
+            &nonce,
+            STATE_TTL,
+            base,
+            is_https(headers),
+        );
+        Ok(resp)
+    }
+
+    /// finishes an oidc login, returns the user and the path they started at.
+    async fn callback<B>(
+        &self,
+        r: &hyper::Request<B>,
+        oidc: &Oidc,
+        peer_id: &str,
+        base: &str,
+        secret: &[u8],
+    ) -> eyre::Result<(String, String)> {
+        use eyre::WrapErr;
+
+        if let Some(error) = query_param(r.uri(), "error") {
+            return Err(eyre::anyhow!("the provider says {error}"));
+        }
+        let state: State = query_param(r.uri(), "state")
+            .and_then(|state| open(secret, "state", &state))
+            .ok_or_else(|| eyre::anyhow!("the state is not valid"))?;
+        if state.target != peer_id || state.expires <= now() {
+            return Err(eyre::anyhow!("the login has expired, try again"));
+        }
+        if cookie(r.headers(), &state_cookie(peer_id)) != Some(state.nonce.as_str()) {
+            return Err(eyre::anyhow!("the login was started in another browser"));
+        }
+        let code = query_param(r.uri(), "code")
+            .ok_or_else(|| eyre::anyhow!("the provider did not send a code"))?;
+
+        let provider = oidc.provider(&self.client).await?;
+        let redirect_url = oidc.redirect_url(r.headers(), base)?;
+        let mut request = self.client.post(&provider.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", redirect_url.as_str()),
+            ("client_id", oidc.client_id.as_str()),
+        ]);
+        if let Some(client_secret) = &oidc.client_secret {
+            request = request.basic_auth(&oidc.client_id, Some(client_secret));
+        }
+        let body = request
+            .send()
+            .await
+            .wrap_err("failed to reach the token endpoint")?
+            .error_for_status()
+            .wrap_err("the token endpoint did not accept the code")?
+            .text()
+            .await
+            .wrap_err("failed to read the tokens")?;
+
+        #[derive(serde::Deserialize)]
+        struct Tokens {
+            id_token: String,
+        }
+        let tokens: Tokens =
+            serde_json::from_str(&body).wrap_err("the provider did not send an id token")?;
+        let claims = id_token_claims(&tokens.id_token)?;
+        let user = claims.check(provider, oidc, &state.nonce, now())?;
+        Ok((user, state.next))
+    }
+}
+
+impl Oidc {
+    async fn provider(&self, client: &reqwest::Client) -> eyre::Result<&Provider> {
+        use eyre::WrapErr;
+
+        self.provider
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.issuer.trim_end_matches('/')
+                );
+                let body = client
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .wrap_err_with(|| format!("failed to fetch {url}"))?
+                    .text()
+                    .await
+                    .wrap_err_with(|| format!("failed to read body of {url}"))?;
+                let provider: Provider = serde_json::from_str(&body)
+                    .wrap_err_with(|| format!("{url} is not an openid configuration"))?;
+                if provider.issuer.trim_end_matches('/') != self.issuer.trim_end_matches('/') {
+                    return Err(eyre::anyhow!(
+                        "{url} is for the issuer {}, not {}",
+                        provider.issuer,
+                        self.issuer
+                    ));
+                }
+                Ok(provider)
+            })
+            .await
+    }
+
+    fn redirect_url(&self, headers: &hyper::HeaderMap, base: &str) -> eyre::Result<String> {
+        if let Some(url) = &self.redirect_url {
+            return Ok(url.clone());
+        }
+        let host = headers
+            .get(hyper::header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .ok_or_else(|| eyre::anyhow!("the request has no Host header"))?;
+        let scheme = if is_https(headers) { "https" } else { "http" };
+        Ok(format!("{scheme}://{host}{base}{AUTH_PATH}/callback"))
+    }
+}
+
+/// the claims of `id_token`. it comes straight from the token endpoint of the provider, so, as
+/// openid connect allows, its signature is not checked, only its claims are.
+fn id_token_claims(id_token: &str) -> eyre::Result<IdToken> {
+    use eyre::WrapErr;
+
+    let claims = id_token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| eyre::anyhow!("the id token is not a jwt"))?;
+    let claims = data_encoding::BASE64URL_NOPAD
+        .decode(claims.trim_end_matches('=').as_bytes())
+        .wrap_err("the claims of the id token are not base64url")?;
+    serde_json::from_slice(&claims).wrap_err("the claims of the id token are not valid")
+}
+
+impl IdToken {
+    /// the user, if the token is for this login and they are allowed in.
+    fn check(
+        &self,
+        provider: &Provider,
+        oidc: &Oidc,
+        nonce: &str,
+        now: u64,
+    ) -> eyre::Result<String> {
+        if self.iss != provider.issuer {
+            return Err(eyre::anyhow!("the id token is from {}", self.iss));
+        }
+        let audience = match &self.aud {
+            serde_json::Value::String(aud) => aud == &oidc.client_id,
+            serde_json::Value::Array(aud) => aud.iter().any(|a| a == oidc.client_id.as_str()),
+            _ => false,
+        };
+        if !audience {
+            return Err(eyre::anyhow!("the id token is for another client"));
+        }
+        if self.exp <= now {
+            return Err(eyre::anyhow!("the id token has expired"));
+        }
+        if self.nonce.as_deref() != Some(nonce) {
+            return Err(eyre::anyhow!("the id token is for another login"));
+        }
+
+        // providers that do not say the email is verified may let users set any email
+        let email = self
+            .email
+            .as_deref()
+            .filter(|_| self.email_verified == Some(true));
+        let user = email.unwrap_or(&self.sub).to_string();
+
+        if oidc.allowed_users.is_empty() && oidc.allowed_domains.is_empty() {
+            return Ok(user);
+        }
+        let listed = oidc.allowed_users.iter().any(|u| match email {
+            Some(email) => u.eq_ignore_ascii_case(email) || u == &self.sub,
+            None => u == &self.sub,
+        });
+        let in_domain = email
+            .and_then(|e| e.rsplit_once('@'))
+            .is_some_and(|(_, domain)| {
+                oidc.allowed_domains
+                    .iter()
+                    .any(|d| d.eq_ignore_ascii_case(domain))
+            });
+        if !listed && !in_domain {
+            return Err(eyre::anyhow!("{user} is not allowed in"));
+        }
+        Ok(user)
+    }
+}
+
+/// `verified` is keyed by a mac of the header, so the passwords are not kept in memory.
+fn basic_key(header: &[u8], secret: &[u8]) -> Vec<u8> {
+    use hmac::Mac;
+
+    mac(secret, "basic", &String::from_utf8_lossy(header))
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+fn mac(secret: &[u8], purpose: &str, payload: &str) -> HmacSha256 {
+    use hmac::Mac;
+
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac takes keys of any size");
+    mac.update(purpose.as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// `value` as `<json>.<mac>`, both base64url, the mac also covers `purpose` so a value sealed
+/// for one thing can not be used for another.
+fn seal(secret: &[u8], purpose: &str, value: &impl serde::Serialize) -> String {
+    use hmac::Mac;
+
+    let payload =
+        data_encoding::BASE64URL_NOPAD.encode(&serde_json::to_vec(value).unwrap_or_default());
+    let tag = mac(secret, purpose, &payload).finalize().into_bytes();
+    format!("{payload}.{}", data_encoding::BASE64URL_NOPAD.encode(&tag))
+}
+
+fn open<T: serde::de::DeserializeOwned>(secret: &[u8], purpose: &str, sealed: &str) -> Option<T> {
+    use hmac::Mac;
+
+    let (payload, tag) = sealed.split_once('.')?;
+    let tag = data_encoding::BASE64URL_NOPAD.decode(tag.as_bytes()).ok()?;
+    mac(secret, purpose, payload).verify_slice(&tag).ok()?;
+    let payload = data_encoding::BASE64URL_NOPAD
+        .decode(payload.as_bytes())
+        .ok()?;
+    serde_json::from_slice(&payload).ok()
+}
+
+/// cookies are per id52, as path routed sites share the host of the bridge.
+fn session_cookie(peer_id: &str) -> String {
+    format!("{COOKIE_PREFIX}{peer_id}")
+}
+
+fn state_cookie(peer_id: &str) -> String {
+    format!("{STATE_COOKIE_PREFIX}{peer_id}")
+}
+
+fn cookie<'a>(headers: &'a hyper::HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| value)
+}
+
+fn session_user(
+    headers: &hyper::HeaderMap,
+    peer_id: &str,
+    secret: &[u8],
+    now: u64,
+) -> Option<String> {
+    let session: Session = open(
+        secret,
+        "session",
+        cookie(headers, &session_cookie(peer_id))?,
+    )?;
+    (session.target == peer_id && session.expires > now).then_some(session.user)
+}
+
+/// removes the cookies of the bridge, for every id52, no service has any use for them.
+fn strip_cookies(headers: &mut hyper::HeaderMap) {
+    let rest = headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .filter(|c| !c.starts_with(COOKIE_PREFIX))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    headers.remove(hyper::header::COOKIE);
+    if !rest.is_empty()
+        && let Ok(value) = rest.parse()
+    {
+        headers.insert(hyper::header::COOKIE, value);
+    }
+}
+
+/// sets the login cookie and sends the user back to `next`, under `base`.
+fn logged_in(
+    peer_id: &str,
+    user: &str,
+    session: u64,
+    base: &str,
+    next: &str,
+    secure: bool,
+    secret: &[u8],
+) -> Response {
+    tracing::info!(peer_id, user, "logged in");
+    let value = seal(
+        secret,
+        "session",
+        &Session {
+            target: peer_id.to_string(),
+            user: user.to_string(),
+            expires: now() + session,
+        },
+    );
+    let mut resp = redirect(&format!("{base}{next}"));
+    set_cookie(
+        &mut resp,
+        &session_cookie(peer_id),
+        &value,
+        session,
+        base,
+        secure,
+    );
+    resp
+}
+
+/// the cookie is only sent to the site it is for, `base` scopes it to a path routed one.
+fn set_cookie(
+    resp: &mut Response,
+    name: &str,
+    value: &str,
+    max_age: u64,
+    base: &str,
+    secure: bool,
+) {
+    let secure = if secure { "; Secure" } else { "" };
+    resp.headers_mut().append(
+        hyper::header::SET_COOKIE,
+        format!("{name}={value}; Path={base}/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}")
+            .parse()
+            .unwrap(),
+    );
+}
+
+/// the bridge itself only speaks http, behind a reverse proxy doing tls the cookies should be
+/// `Secure` and the redirect url https.
+fn is_https(headers: &hyper::HeaderMap) -> bool {
+    headers
+        .get("x-forwarded-proto")
+        .is_some_and(|proto| proto == "https")
+}
+
+/// `next` if it is a path on this site, so the login can not send the user elsewhere.
+fn safe_next(next: Option<&str>) -> String {
+    match next {
+        Some(next)
+            if next.starts_with('/')
+                && !next.starts_with("//")
+                && !next.starts_with("/\\")
+                && hyper::header::HeaderValue::from_str(next).is_ok() =>
+        {
+            next.to_string()
+        }
+        _ => "/".to_string(),
+    }
+}
+
+fn query_param(uri: &hyper::Uri, name: &str) -> Option<String> {
+    form_urlencoded::parse(uri.query()?.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+async fn read_form<B>(r: hyper::Request<B>) -> eyre::Result<HashMap<String, String>>
+where
+    B: hyper::body::Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    use http_body_util::BodyExt;
+
+    let body = http_body_util::Limited::new(r.into_body(), MAX_FORM_SIZE)
+        .collect()
+        .await
+        .map_err(|e| eyre::anyhow!("failed to read the form: {e}"))?
+        .to_bytes();
+    Ok(form_urlencoded::parse(&body).into_owned().collect())
+}
+
+fn ask_for_password(realm: &str) -> Response {
+    let mut resp = text(hyper::StatusCode::UNAUTHORIZED, "log in to see this site");
+    if let Ok(value) = format!("Basic realm=\"{realm}\", charset=\"UTF-8\"").parse() {
+        resp.headers_mut()
+            .insert(hyper::header::WWW_AUTHENTICATE, value);
+    }
+    resp
+}
+
+fn login_page(action: &str, next: &str, error: &str, status: hyper::StatusCode) -> Response {
+    let mut resp = kulfi_utils::http::bytes_to_resp(
+        format!(
+            include_str!("login.html"),
+            action = escape(action),
+            next = escape(next),
+            error = escape(error),
+        )
+        .into_bytes(),
+        status,
+    );
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    resp
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn redirect(location: &str) -> Response {
+    let mut resp = kulfi_utils::http::bytes_to_resp(vec![], hyper::StatusCode::SEE_OTHER);
+    match location.parse() {
+        Ok(location) => {
+            resp.headers_mut().insert(hyper::header::LOCATION, location);
+            resp
+        }
+        Err(_) => text(hyper::StatusCode::INTERNAL_SERVER_ERROR, "invalid redirect"),
+    }
+}
+
+fn text(status: hyper::StatusCode, message: &str) -> Response {
+    kulfi_utils::http::bytes_to_resp(format!("{message}\n").into_bytes(), status)
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    const ID52: &str = "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60";
+    const OTHER: &str = "e87aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9as9b7v80";
+
+    #[tokio::test]
+    async fn passwords() {
+        let hash = super::hash("hunter2").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(super::verify_password("hunter2".to_string(), hash.clone()).await);
+        assert!(!super::verify_password("hunter3".to_string(), hash.clone()).await);
+
+        let file = super::parse_file(&format!(
+            r#"cookie_secret = "s3cret"
+
+[target.{ID52}]
+method = "basic"
+users = {{ alice = "{hash}" }}
+
+[target."*"]
+method = "oidc"
+issuer = "http://127.0.0.1:5556/dex"
+client_id = "bridge"
+allowed_domains = ["example.com"]
+"#
+        ))
+        .unwrap();
+        assert!(matches!(file.target[ID52], super::Method::Basic { .. }));
+        assert!(matches!(file.target["*"], super::Method::Oidc(_)));
+
+        let err = |content: &str| super::parse_file(content).unwrap_err().to_string();
+        assert!(err("[target.not-an-id52]\nmethod = \"none\"\n").starts_with("target.not-an-id52"));
+        assert!(
+            err(&format!(
+                "[target.{ID52}]\nmethod = \"login\"\nusers = {{ alice = \"hunter2\" }}\n"
+            ))
+            .contains("the password hash of alice is not valid")
+        );
+        assert!(super::parse_file(&format!("[target.{ID52}]\nmethod = \"magic\"\n")).is_err());
+        assert!(
+            super::parse_file(&format!(
+                "[target.{ID52}]\nmethod = \"login\"\nusers = {{}}\nsesion = 60\n"
+            ))
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn path_routed_sites() {
+        use hyper::header::{AUTHORIZATION, COOKIE, LOCATION, SET_COOKIE};
+
+        let hash = super::hash("hunter2").unwrap();
+        let basic = kulfi_id52::SecretKey::generate().id52();
+        let auth = super::Auth {
+            file: std::sync::RwLock::new(std::sync::Arc::new(
+                super::parse_file(&format!(
+                    "cookie_secret = \"s3cret\"\n\
+                     [target.{ID52}]\nmethod = \"login\"\nusers = {{ alice = \"{hash}\" }}\n\
+                     [target.{basic}]\nmethod = \"basic\"\nusers = {{ alice = \"{hash}\" }}\n\
+                     [target.{OTHER}]\nmethod = \"none\"\n"
+                ))
+                .unwrap(),
+            )),
+            random_secret: [0; 32],
+            verified: Default::default(),
+            client: reqwest::Client::new(),
+        };
+        let request = |method, path: &str, headers: &[(hyper::header::HeaderName, &str)], body| {
+            let mut r = hyper::Request::builder().method(method).uri(path);
+            for (name, value) in headers {
+                r = r.header(name, *value);
+            }
+            r.body(http_body_util::Full::new(hyper::body::Bytes::from_static(
+                body,
+            )))
+            .unwrap()
+        };
+        let base = format!("/~{ID52}");
+
+        // logging in to the protected site scopes the cookie to its prefix
+        let resp = auth
+            .check(
+                request(
+                    hyper::Method::POST,
+                    "/.kulfi/auth/login",
+                    &[],
+                    b"username=alice&password=hunter2&next=%2Fdash",
+                ),
+                ID52,
+                &base,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(resp.status(), hyper::StatusCode::SEE_OTHER);
+        assert_eq!(resp.headers()[LOCATION], format!("{base}/dash"));
+        let set_cookie = resp.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains(&format!("; Path={base}/;")));
+        let session = set_cookie.split(';').next().unwrap();
+        let cookies = format!("theme=dark; {session}");
+
+        // the protected site gets the user, not the cookie
+        let r = auth
+            .check(
+                request(hyper::Method::GET, "/dash", &[(COOKIE, &cookies)], b""),
+                ID52,
+                &base,
+            )
+            .await
+            .unwrap();
+        assert_eq!(r.headers()[super::USER_HEADER], "alice");
+        assert_eq!(r.headers()[COOKIE], "theme=dark");
+
+        let credentials = format!("Basic {}", data_encoding::BASE64.encode(b"alice:hunter2"));
+        let r = auth
+            .check(
+                request(
+                    hyper::Method::GET,
+                    "/",
+                    &[(AUTHORIZATION, &credentials)],
+                    b"",
+                ),
+                &basic,
+                &format!("/~{basic}"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(r.headers()[super::USER_HEADER], "alice");
+        assert!(!r.headers().contains_key(AUTHORIZATION));
+
+        // browsers send both along to the other sites on the bridge, which get neither, nor a
+        // user they made up
+        for peer_id in [OTHER, "not-in-the-auth-file"] {
+            let r = auth
+                .check(
+                    request(
+                        hyper::Method::GET,
+                        "/",
+                        &[
+                            (COOKIE, &cookies),
+                            (AUTHORIZATION, &credentials),
+                            (super::USER_HEADER.parse().unwrap(), "alice"),
+                        ],
+                        b"",
+                    ),
+                    peer_id,
+                    &format!("/~{peer_id}"),
+                )
+                .await
+                .unwrap();
+            assert_eq!(r.headers()[COOKIE], "theme=dark");
+            assert!(!r.headers().contains_key(AUTHORIZATION));
+            assert!(!r.headers().contains_key(super::USER_HEADER));
+        }
+
+        // their own credentials are theirs
+        let r = auth
+            .check(
+                request(
+                    hyper::Method::GET,
+                    "/",
+                    &[(AUTHORIZATION, "Bearer their-own")],
+                    b"",
+                ),
+                OTHER,
+                &format!("/~{OTHER}"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(r.headers()[AUTHORIZATION], "Bearer their-own");
+    }
+
+    #[test]
+    fn cookies() {
+        let session = super::Session {
+            target: ID52.to_string(),
+            user: "alice".to_string(),
+            expires: 100,
+        };
+        let sealed = super::seal(b"secret", "session", &session);
+        assert_eq!(
+            super::open::<super::Session>(b"secret", "session", &sealed),
+            Some(session)
+        );
+        // other secrets, other purposes and changed values are rejected
+        assert_eq!(
+            super::open::<super::Session>(b"other", "session", &sealed),
+            None
+        );
+        assert_eq!(
+            super::open::<super::Session>(b"secret", "state", &sealed),
+            None
+        );
+        let (_, tag) = sealed.split_once('.').unwrap();
+        let forged = super::seal(
+            b"other",
+            "session",
+            &super::Session {
+                target: ID52.to_string(),
+                user: "mallory".to_string(),
+                expires: 100,
+            },
+        );
+        let (payload, _) = forged.split_once('.').unwrap();
+        assert_eq!(
+            super::open::<super::Session>(b"secret", "session", &format!("{payload}.{tag}")),
+            None
+        );
+
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(
+            hyper::header::COOKIE,
+            format!("theme=dark; kulfi-auth-{ID52}={sealed}; kulfi-auth-state-{ID52}=n")
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            super::session_user(&headers, ID52, b"secret", 99).as_deref(),
+            Some("alice")
+        );
+        // expired, or for another id52
+        assert_eq!(super::session_user(&headers, ID52, b"secret", 100), None);
+        assert_eq!(super::session_user(&headers, OTHER, b"secret", 99), None);
+
+        super::strip_cookies(&mut headers);
+        assert_eq!(headers[hyper::header::COOKIE], "theme=dark");
+
+        assert_eq!(super::safe_next(Some("/a?b=c")), "/a?b=c");
+        assert_eq!(super::safe_next(Some("//evil.example.com")), "/");
+        assert_eq!(super::safe_next(Some("https://evil.example.com")), "/");
+        assert_eq!(super::safe_next(None), "/");
+    }
+
+    #[test]
+    fn id_tokens() {
+        let provider = super::Provider {
+            issuer: "http://127.0.0.1:5556/dex".to_string(),
+            authorization_endpoint: "http://127.0.0.1:5556/dex/auth".to_string(),
+            token_endpoint: "http://127.0.0.1:5556/dex/token".to_string(),
+        };
+        let oidc = |allowed_users: &[&str], allowed_domains: &[&str]| super::Oidc {
+            issuer: provider.issuer.clone(),
+            client_id: "bridge".to_string(),
+            client_secret: None,
+            redirect_url: None,
+            scopes: super::default_scopes(),
+            allowed_users: allowed_users.iter().map(ToString::to_string).collect(),
+            allowed_domains: allowed_domains.iter().map(ToString::to_string).collect(),
+            session: super::DEFAULT_SESSION,
+            provider: Default::default(),
+        };
+        let token = |claims: serde_json::Value| {
+            let claims = data_encoding::BASE64URL_NOPAD.encode(claims.to_string().as_bytes());
+            super::id_token_claims(&format!("eyJhbGciOiJSUzI1NiJ9.{claims}.c2ln")).unwrap()
+        };
+        let alice = token(serde_json::json!({
+            "iss": "http://127.0.0.1:5556/dex",
+            "aud": ["bridge"],
+            "exp": 100,
+            "nonce": "n",
+            "sub": "CgVhbGljZQ",
+            "email": "alice@example.com",
+            "email_verified": true,
+        }));
+
+        let check = |oidc: &super::Oidc, nonce, now| alice.check(&provider, oidc, nonce, now);
+        assert_eq!(
+            check(&oidc(&[], &[]), "n", 99).unwrap(),
+            "alice@example.com"
+        );
+        assert!(check(&oidc(&["ALICE@example.com"], &[]), "n", 99).is_ok());
+        assert!(check(&oidc(&[], &["example.com"]), "n", 99).is_ok());
+        assert!(check(&oidc(&["bob@example.com"], &["example.org"]), "n", 99).is_err());
+        assert!(check(&oidc(&[], &[]), "other", 99).is_err());
+        assert!(check(&oidc(&[], &[]), "n", 100).is_err());
+
+        let unverified = token(serde_json::json!({
+            "iss": "http://127.0.0.1:5556/dex",
+            "aud": "bridge",
+            "exp": 100,
+            "nonce": "n",
+            "sub": "CgVhbGljZQ",
+            "email": "alice@example.com",
+            "email_verified": false,
+        }));
+        assert_eq!(
+            unverified
+                .check(&provider, &oidc(&[], &[]), "n", 99)
+                .unwrap(),
+            "CgVhbGljZQ"
+        );
+        assert!(
+            unverified
+                .check(&provider, &oidc(&[], &["example.com"]), "n", 99)
+                .is_err()
+        );
+        // without `email_verified` the email can be anything
+        let unsaid = token(serde_json::json!({
+            "iss": "http://127.0.0.1:5556/dex",
+            "aud": "bridge",
+            "exp": 100,
+            "nonce": "n",
+            "sub": "CgVhbGljZQ",
+            "email": "alice@example.com",
+        }));
+        assert!(
+            unsaid
+                .check(
+                    &provider,
+                    &oidc(&["alice@example.com"], &["example.com"]),
+                    "n",
+                    99
+                )
+                .is_err()
+        );
+        assert!(
+            unsaid
+                .check(&provider, &oidc(&["CgVhbGljZQ"], &[]), "n", 99)
+                .is_ok()
+        );
+
+        let spoofed = token(serde_json::json!({
+            "iss": "http://127.0.0.1:5556/dex",
+            "aud": "bridge",
+            "exp": 100,
+            "nonce": "n",
+            "sub": "CgNtYWxsb3J5",
+            "email": "mallory@example.org",
+            "email_verified": true,
+            "preferred_username": "alice@example.com",
+        }));
+        assert!(
+            spoofed
+                .check(&provider, &oidc(&["alice@example.com"], &[]), "n", 99)
+                .is_err()
+        );
+        assert_eq!(
+            spoofed.check(&provider, &oidc(&[], &[]), "n", 99).unwrap(),
+            "mallory@example.org"
+        );
+
+        let other_client = token(serde_json::json!({
+            "iss": "http://127.0.0.1:5556/dex",
+            "aud": "someone-else",
+            "exp": 100,
+            "nonce": "n",
+            "sub": "CgVhbGljZQ",
+        }));
+        assert!(
+            other_client
+                .check(&provider, &oidc(&[], &[]), "n", 99)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,1101 @@
+//! HTTP response cache for `malai http-bridge`.
+//!
+//! every request that reaches the bridge is otherwise a full p2p round-trip to the exposer, which
+//! is wasteful for immutable assets, e.g., a static site published with `malai folder`. this cache
+//! sits in front of `http_to_peer` and behaves like a shared cache (RFC 9111), with a few
+//! simplifications:
+//!
+//! - only `GET` (and `HEAD`, served from the `GET` entry) requests are cached.
+//! - only `200 OK` responses with a known `Content-Length` below `max_entry_size` are stored, other
+//!   responses are streamed through untouched.
+//! - freshness comes from `s-maxage` or `max-age`. responses without either are stored only if they
+//!   have a validator (`ETag` or `Last-Modified`), and are revalidated on every use.
+//! - `no-store`, `private`, `Vary: *` and `Set-Cookie` responses, and requests carrying
+//!   `Authorization` or of users logged in at the bridge (see `auth.rs`), are never cached, the
+//!   bridge can not know if the response is the same for everyone. requests carrying `Cookie` only
+//!   get, and store, responses that are explicitly `public`.
+//!
+//! entries are partitioned by the id52 of the exposer, so one site can never be served another
+//! site's response, and a purge only affects one id52. the partition + request path is the
+//! primary key, and the values of the request headers named in `Vary` select among the variants.
+//!
+//! purging is done by sending a `PURGE` request to the bridge, with the same Host as the site. a
+//! path ending in `*` purges every entry with that prefix, so `PURGE /*` clears the whole site.
+//! purge is only enabled when a purge token is configured, and the token must be sent as
+//! `Authorization: Bearer <token>`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use http_body_util::BodyExt;
+
+pub const DEFAULT_MAX_SIZE: u64 = 256 * 1024 * 1024;
+pub const DEFAULT_MAX_ENTRY_SIZE: u64 = 8 * 1024 * 1024;
+
+const X_CACHE: &str = "x-cache";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// keep cached bodies in memory, lost on restart
+    Memory,
+    /// keep cached bodies in `dir`, survives restarts
+    Disk,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub backend: CacheBackend,
+    /// required for the disk backend
+    pub dir: Option<PathBuf>,
+    /// the total size of all cached bodies, in bytes
+    pub max_size: u64,
+    /// responses bigger than this are never cached, in bytes
+    pub max_entry_size: u64,
+    /// purge requests are rejected unless this is set
+    pub purge_token: Option<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::Memory,
+            dir: None,
+            max_size: DEFAULT_MAX_SIZE,
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            purge_token: None,
+        }
+    }
+}
+
+/// the parsed value of a `Cache-Control` header, only the directives we care about.
+#[derive(Debug, Default, PartialEq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &hyper::HeaderMap) -> Self {
+        let mut cc = CacheControl::default();
+        for value in headers.get_all(hyper::header::CACHE_CONTROL) {
+            let value = match value.to_str() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for directive in value.split(',') {
+                let (name, arg) = match directive.split_once('=') {
+                    Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                    None => (directive.trim(), None),
+                };
+                match name.to_ascii_lowercase().as_str() {
+                    "no-store" => cc.no_store = true,
+                    "no-cache" => cc.no_cache = true,
+                    "private" => cc.private = true,
+                    "public" => cc.public = true,
+                    "max-age" => cc.max_age = arg.and_then(|v| v.parse().ok()),
+                    "s-maxage" => cc.s_maxage = arg.and_then(|v| v.parse().ok()),
+                    _ => {}
+                }
+            }
+        }
+        cc
+    }
+
+    /// how long a response is fresh for, `None` if the response must not be stored
+    fn freshness(&self, has_validator: bool) -> Option<Duration> {
+        if self.no_store || self.private {
+            return None;
+        }
+        if self.no_cache {
+            return has_validator.then_some(Duration::ZERO);
+        }
+        match self.s_maxage.or(self.max_age) {
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None if has_validator => Some(Duration::ZERO),
+            None => None,
+        }
+    }
+}
+
+/// everything we store about a cached response, except the body.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct EntryMeta {
+    id52: String,
+    key: String,
+    /// `(header name, request header value)` for every header listed in `Vary`
+    vary: Vec<(String, Option<String>)>,
+    response: kulfi_utils::http::Response,
+    stored_at: SystemTime,
+    fresh_for: Duration,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// the response was `Cache-Control: public`, so it can be served to requests with `Cookie`
+    #[serde(default)]
+    public: bool,
+}
+
+impl EntryMeta {
+    fn is_fresh(&self, now: SystemTime) -> bool {
+        now.duration_since(self.stored_at).unwrap_or_default() < self.fresh_for
+    }
+
+    fn age(&self, now: SystemTime) -> u64 {
+        now.duration_since(self.stored_at)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn matches(&self, headers: &hyper::HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| header_string(headers, name) == *value)
+    }
+
+    fn has_validator(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Body {
+    Memory(hyper::body::Bytes),
+    Disk(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    meta: EntryMeta,
+    body: Body,
+    size: u64,
+    last_used: SystemTime,
+}
+
+/// the in-memory index of all cached entries, the bodies live in memory or on disk depending on
+/// the backend.
+#[derive(Debug, Default)]
+struct Store {
+    /// id52 -> key -> variants
+    partitions: HashMap<String, HashMap<String, Vec<Entry>>>,
+    size: u64,
+}
+
+impl Store {
+    fn lookup(&mut self, id52: &str, key: &str, headers: &hyper::HeaderMap) -> Option<Entry> {
+        let entry = self
+            .partitions
+            .get_mut(id52)?
+            .get_mut(key)?
+            .iter_mut()
+            .find(|e| e.meta.matches(headers))?;
+        entry.last_used = SystemTime::now();
+        Some(entry.clone())
+    }
+
+    /// inserts the entry, replacing any variant with the same `Vary` values, and returns the
+    /// entries that were dropped to make space (their bodies need to be cleaned up).
+    fn insert(&mut self, entry: Entry, max_size: u64) -> Vec<Entry> {
+        let mut removed = vec![];
+        let variants = self
+            .partitions
+            .entry(entry.meta.id52.clone())
+            .or_default()
+            .entry(entry.meta.key.clone())
+            .or_default();
+        if let Some(i) = variants.iter().position(|e| e.meta.vary == entry.meta.vary) {
+            let old = variants.remove(i);
+            self.size -= old.size;
+            removed.push(old);
+        }
+        self.size += entry.size;
+        variants.push(entry);
+
+        while self.size > max_size {
+            match self.evict_least_recently_used() {
+                Some(e) => removed.push(e),
+                None => break,
+            }
+        }
+        removed
+    }
+
+    fn evict_least_recently_used(&mut self) -> Option<Entry> {
+        let (id52, key, index) = self
+            .partitions
+            .iter()
+            .flat_map(|(id52, keys)| {
+                keys.iter().flat_map(move |(key, variants)| {
+                    variants
+                        .iter()
+                        .enumerate()
+                        .map(move |(i, e)| (e.last_used, id52, key, i))
+                })
+            })
+            .min_by_key(|(last_used, ..)| *last_used)
+            .map(|(_, id52, key, i)| (id52.clone(), key.clone(), i))?;
+        self.remove(&id52, |k| k == key, Some(index)).pop()
+    }
+
+    /// removes the entries of `id52` whose key matches; all variants unless `index` is given.
+    fn remove(
+        &mut self,
+        id52: &str,
+        key_matches: impl Fn(&str) -> bool,
+        index: Option<usize>,
+    ) -> Vec<Entry> {
+        let mut removed = vec![];
+        let keys = match self.partitions.get_mut(id52) {
+            Some(keys) => keys,
+            None => return removed,
+        };
+        keys.retain(|key, variants| {
+            if !key_matches(key) {
+                return true;
+            }
+            match index {
+                Some(i) if i < variants.len() => removed.push(variants.remove(i)),
+                Some(_) => {}
+                None => removed.append(variants),
+            }
+            !variants.is_empty()
+        });
+        if keys.is_empty() {
+            self.partitions.remove(id52);
+        }
+        for e in removed.iter() {
+            self.size -= e.size;
+        }
+        removed
+    }
+
+    fn refresh(&mut self, meta: &EntryMeta) {
+        if let Some(e) = self
+            .partitions
+            .get_mut(&meta.id52)
+            .and_then(|keys| keys.get_mut(&meta.key))
+            .and_then(|variants| variants.iter_mut().find(|e| e.meta.vary == meta.vary))
+        {
+            e.meta = meta.clone();
+        }
+    }
+}
+
+pub struct HttpCache {
+    config: CacheConfig,
+    store: std::sync::Mutex<Store>,
+}
+
+impl HttpCache {
+    pub async fn new(config: CacheConfig) -> eyre::Result<std::sync::Arc<Self>> {
+        use eyre::WrapErr;
+
+        let mut store = Store::default();
+        if config.backend == CacheBackend::Disk {
+            let dir = config
+                .dir
+                .as_ref()
+                .ok_or_else(|| eyre::anyhow!("disk cache needs a cache directory"))?;
+            tokio::fs::create_dir_all(dir)
+                .await
+                .wrap_err_with(|| format!("failed to create cache directory: {dir:?}"))?;
+            load_disk_entries(dir, &mut store, config.max_size).await?;
+            tracing::info!(?dir, size = store.size, "loaded disk cache");
+        }
+
+        Ok(std::sync::Arc::new(Self {
+            config,
+            store: std::sync::Mutex::new(store),
+        }))
+    }
+
+    /// serves `r` from the cache if possible, otherwise calls `forward` and stores the response
+    /// if it is cacheable.
+    pub async fn handle<B, F, Fut>(
+        &self,
+        mut r: hyper::Request<B>,
+        id52: &str,
+        forward: F,
+    ) -> kulfi_utils::http::ProxyResult<eyre::Error>
+    where
+        F: FnOnce(hyper::Request<B>) -> Fut,
+        Fut: Future<Output = kulfi_utils::http::ProxyResult<eyre::Error>>,
+    {
+        if r.method().as_str() == "PURGE" {
+            return Ok(self.purge(&r, id52).await);
+        }
+
+        let is_head = r.method() == hyper::Method::HEAD;
+        if (r.method() != hyper::Method::GET && !is_head)
+            || r.headers().contains_key(hyper::header::AUTHORIZATION)
+            || r.headers().contains_key(super::auth::USER_HEADER)
+            || CacheControl::parse(r.headers()).no_store
+        {
+            return forward(r).await;
+        }
+
+        let key = cache_key(r.uri());
+        let request_headers = r.headers().clone();
+        let has_cookie = request_headers.contains_key(hyper::header::COOKIE);
+        let cached = self
+            .store
+            .lock()
+            .unwrap()
+            .lookup(id52, &key, &request_headers)
+            // the response may depend on the cookie, unless the site said otherwise
+            .filter(|e| !has_cookie || e.meta.public);
+
+        let now = SystemTime::now();
+        if let Some(entry) = &cached
+            && entry.meta.is_fresh(now)
+            && !CacheControl::parse(&request_headers).no_cache
+        {
+            tracing::debug!(id52, key, "cache hit");
+            return self.respond(entry, &request_headers, is_head, "HIT").await;
+        }
+
+        if is_head {
+            // we do not store HEAD responses, so no point in revalidating for them
+            return forward(r).await;
+        }
+
+        if let Some(entry) = &cached
+            && entry.meta.has_validator()
+        {
+            let headers = r.headers_mut();
+            headers.remove(hyper::header::IF_NONE_MATCH);
+            headers.remove(hyper::header::IF_MODIFIED_SINCE);
+            if let Some(etag) = entry.meta.etag.as_ref().and_then(|v| v.parse().ok()) {
+                headers.insert(hyper::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(lm) = entry
+                .meta
+                .last_modified
+                .as_ref()
+                .and_then(|v| v.parse().ok())
+            {
+                headers.insert(hyper::header::IF_MODIFIED_SINCE, lm);
+            }
+        }
+
+        let resp = forward(r).await?;
+
+        if resp.status() == hyper::StatusCode::NOT_MODIFIED
+            && let Some(entry) = cached
+        {
+            tracing::debug!(id52, key, "cache revalidated");
+            let mut entry = entry;
+            let cc = CacheControl::parse(resp.headers());
+            match cc.freshness(true) {
+                Some(fresh_for) => {
+                    entry.meta.stored_at = SystemTime::now();
+                    entry.meta.fresh_for = fresh_for;
+                    self.store.lock().unwrap().refresh(&entry.meta);
+                }
+                None => {
+                    let removed = self.store.lock().unwrap().remove(id52, |k| k == key, None);
+                    remove_bodies(removed).await;
+                }
+            }
+            return self
+                .respond(&entry, &request_headers, false, "REVALIDATED")
+                .await;
+        }
+
+        self.store_response(resp, id52, key, &request_headers).await
+    }
+
+    async fn store_response(
+        &self,
+        resp: kulfi_utils::http::ProxyResponse<eyre::Error>,
+        id52: &str,
+        key: String,
+        request_headers: &hyper::HeaderMap,
+    ) -> kulfi_utils::http::ProxyResult<eyre::Error> {
+        let meta = match self.storable(&resp, id52, &key, request_headers) {
+            Some(meta) => meta,
+            None => {
+                let mut resp = resp;
+                resp.headers_mut()
+                    .insert(X_CACHE, hyper::header::HeaderValue::from_static("MISS"));
+                return Ok(resp);
+            }
+        };
+
+        let (parts, body) = resp.into_parts();
+        let bytes = body.collect().await?.to_bytes();
+        let size = bytes.len() as u64;
+
+        let body = match self.config.backend {
+            CacheBackend::Memory => Body::Memory(bytes.clone()),
+            CacheBackend::Disk => match self.write_to_disk(&meta, &bytes).await {
+                Ok(path) => Body::Disk(path),
+                Err(e) => {
+                    tracing::error!(id52, key, "failed to write cache entry: {e:?}");
+                    let mut resp = hyper::Response::from_parts(parts, full(bytes));
+                    resp.headers_mut()
+                        .insert(X_CACHE, hyper::header::HeaderValue::from_static("MISS"));
+                    return Ok(resp);
+                }
+            },
+        };
+
+        tracing::debug!(id52, key, size, "storing response in cache");
+        let new_body = body.clone();
+        let mut removed = self.store.lock().unwrap().insert(
+            Entry {
+                meta,
+                body,
+                size,
+                last_used: SystemTime::now(),
+            },
+            self.config.max_size,
+        );
+        // replacing a variant on disk reuses its file names, so do not delete what we just wrote
+        removed
+            .retain(|e| !matches!((&e.body, &new_body), (Body::Disk(a), Body::Disk(b)) if a == b));
+        remove_bodies(removed).await;
+
+        let mut resp = hyper::Response::from_parts(parts, full(bytes));
+        resp.headers_mut()
+            .insert(X_CACHE, hyper::header::HeaderValue::from_static("MISS"));
+        Ok(resp)
+    }
+
+    fn storable(
+        &self,
+        resp: &kulfi_utils::http::ProxyResponse<eyre::Error>,
+        id52: &str,
+        key: &str,
+        request_headers: &hyper::HeaderMap,
+    ) -> Option<EntryMeta> {
+        if resp.status() != hyper::StatusCode::OK
+            || resp.headers().contains_key(hyper::header::SET_COOKIE)
+        {
+            return None;
+        }
+
+        let length: u64 = header_string(resp.headers(), hyper::header::CONTENT_LENGTH.as_str())?
+            .parse()
+            .ok()?;
+        if length > self.config.max_entry_size {
+            return None;
+        }
+
+        let etag = header_string(resp.headers(), hyper::header::ETAG.as_str());
+        let last_modified = header_string(resp.headers(), hyper::header::LAST_MODIFIED.as_str());
+        let cc = CacheControl::parse(resp.headers());
+        if request_headers.contains_key(hyper::header::COOKIE) && !cc.public {
+            return None;
+        }
+        let fresh_for = cc.freshness(etag.is_some() || last_modified.is_some())?;
+
+        let mut vary = vec![];
+        for value in resp.headers().get_all(hyper::header::VARY) {
+            for name in value.to_str().ok()?.split(',') {
+                let name = name.trim().to_ascii_lowercase();
+                if name == "*" {
+                    return None;
+                }
+                if !name.is_empty() {
+                    let value = header_string(request_headers, &name);
+                    vary.push((name, value));
+                }
+            }
+        }
+
+        Some(EntryMeta {
+            id52: id52.to_string(),
+            key: key.to_string(),
+            vary,
+            response: kulfi_utils::http::Response {
+                status: resp.status().as_u16(),
+                headers: resp
+                    .headers()
+                    .iter()
+                    .filter(|(k, _)| *k != X_CACHE)
+                    .map(|(k, v)| (k.to_string(), v.as_bytes().to_vec()))
+                    .collect(),
+            },
+            stored_at: SystemTime::now(),
+            fresh_for,
+            etag,
+            last_modified,
+            public: cc.public,
+        })
+    }
+
+    async fn respond(
+        &self,
+        entry: &Entry,
+        request_headers: &hyper::HeaderMap,
+        is_head: bool,
+        status: &'static str,
+    ) -> kulfi_utils::http::ProxyResult<eyre::Error> {
+        let client_etag = header_string(request_headers, hyper::header::IF_NONE_MATCH.as_str());
+        let not_modified = client_etag.is_some() && client_etag == entry.meta.etag;
+
+        let body = if is_head || not_modified {
+            vec![]
+        } else {
+            match &entry.body {
+                Body::Memory(bytes) => bytes.to_vec(),
+                Body::Disk(path) => tokio::fs::read(path).await?,
+            }
+        };
+
+        let mut resp = kulfi_utils::http::bytes_to_resp(
+            body,
+            hyper::StatusCode::from_u16(entry.meta.response.status)?,
+        );
+        for (k, v) in entry.meta.response.headers.iter() {
+            resp.headers_mut().append(
+                hyper::header::HeaderName::from_bytes(k.as_bytes())?,
+                hyper::header::HeaderValue::from_bytes(v)?,
+            );
+        }
+        if not_modified {
+            *resp.status_mut() = hyper::StatusCode::NOT_MODIFIED;
+            resp.headers_mut().remove(hyper::header::CONTENT_LENGTH);
+        }
+        resp.headers_mut()
+            .insert(hyper::header::AGE, entry.meta.age(SystemTime::now()).into());
+        resp.headers_mut()
+            .insert(X_CACHE, hyper::header::HeaderValue::from_static(status));
+        Ok(resp)
+    }
+
+    async fn purge<B>(
+        &self,
+        r: &hyper::Request<B>,
+        id52: &str,
+    ) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+        let token = match &self.config.purge_token {
+            Some(token) => token,
+            None => {
+                return kulfi_utils::http::bytes_to_resp(
+                    b"cache purge is disabled on this bridge".to_vec(),
+                    hyper::StatusCode::METHOD_NOT_ALLOWED,
+                );
+            }
+        };
+
+        let given = header_string(r.headers(), hyper::header::AUTHORIZATION.as_str());
+        let authorized = given
+            .as_deref()
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|given| kulfi_utils::constant_time_eq(given.as_bytes(), token.as_bytes()));
+        if !authorized {
+            tracing::warn!(id52, "cache purge with invalid token");
+            return kulfi_utils::http::bytes_to_resp(
+                b"invalid purge token".to_vec(),
+                hyper::StatusCode::UNAUTHORIZED,
+            );
+        }
+
+        let key = cache_key(r.uri());
+        let removed = {
+            let mut store = self.store.lock().unwrap();
+            match key.strip_suffix('*') {
+                Some(prefix) => store.remove(id52, |k| k.starts_with(prefix), None),
+                None => store.remove(id52, |k| k == key, None),
+            }
+        };
+        let count = removed.len();
+        remove_bodies(removed).await;
+        tracing::info!(id52, key, count, "purged cache entries");
+
+        kulfi_utils::http::bytes_to_resp(
+            format!("purged {count} entries\n").into_bytes(),
+            hyper::StatusCode::OK,
+        )
+    }
+
+    async fn write_to_disk(
+        &self,
+        meta: &EntryMeta,
+        bytes: &hyper::body::Bytes,
+    ) -> eyre::Result<PathBuf> {
+        use std::hash::{Hash, Hasher};
+
+        let dir = self
+            .config
+            .dir
+            .as_ref()
+            .expect("disk backend always has a dir")
+            .join(&meta.id52);
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let mut hasher = std::hash::DefaultHasher::new();
+        (&meta.key, &meta.vary).hash(&mut hasher);
+        let name = format!("{:016x}", hasher.finish());
+
+        let body_path = dir.join(format!("{name}.body"));
+        tokio::fs::write(&body_path, bytes).await?;
+        tokio::fs::write(dir.join(format!("{name}.json")), serde_json::to_vec(meta)?).await?;
+        Ok(body_path)
+    }
+}
+
+/// scans `<dir>/<id52>/*.json` and adds the entries found to the store.
+async fn load_disk_entries(
+    dir: &std::path::Path,
+    store: &mut Store,
+    max_size: u64,
+) -> eyre::Result<()> {
+    let mut partitions = tokio::fs::read_dir(dir).await?;
+    while let Some(partition) = partitions.next_entry().await? {
+        if !partition.file_type().await?.is_dir() {
+            continue;
+        }
+        let mut files = tokio::fs::read_dir(partition.path()).await?;
+        while let Some(file) = files.next_entry().await? {
+            let path = file.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let body_path = path.with_extension("body");
+            let meta: EntryMeta = match tokio::fs::read(&path)
+                .await
+                .map_err(eyre::Report::from)
+                .and_then(|v| serde_json::from_slice(&v).map_err(Into::into))
+            {
+                Ok(meta) => meta,
+                Err(e) => {
+                    tracing::warn!(?path, "ignoring unreadable cache entry: {e:?}");
+                    continue;
+                }
+            };
+            let size = match tokio::fs::metadata(&body_path).await {
+                Ok(m) => m.len(),
+                Err(_) => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    continue;
+                }
+            };
+            let removed = store.insert(
+                Entry {
+                    meta,
+                    body: Body::Disk(body_path),
+                    size,
+                    last_used: SystemTime::now(),
+                },
+                max_size,
+            );
+            remove_bodies(removed).await;
+        }
+    }
+    Ok(())
+}
+
+async fn remove_bodies(entries: Vec<Entry>) {
+    for entry in entries {
+        if let Body::Disk(path) = entry.body {
+            let _ = tokio::fs::remove_file(path.with_extension("json")).await;
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+}
+
+fn cache_key(uri: &hyper::Uri) -> String {
+    uri.path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string())
+}
+
+fn header_string(headers: &hyper::HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+fn full(
+    bytes: hyper::body::Bytes,
+) -> http_body_util::combinators::BoxBody<hyper::body::Bytes, eyre::Error> {
+    http_body_util::Full::new(bytes)
+        .map_err(|e| match e {})
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> hyper::HeaderMap {
+        let mut h = hyper::HeaderMap::new();
+        for (k, v) in pairs {
+            h.append(*k, hyper::header::HeaderValue::from_static(v));
+        }
+        h
+    }
+
+    fn entry(id52: &str, key: &str, vary: Vec<(String, Option<String>)>, size: u64) -> Entry {
+        Entry {
+            meta: EntryMeta {
+                id52: id52.to_string(),
+                key: key.to_string(),
+                vary,
+                response: kulfi_utils::http::Response {
+                    status: 200,
+                    headers: vec![],
+                },
+                stored_at: SystemTime::now(),
+                fresh_for: Duration::from_secs(60),
+                etag: None,
+                last_modified: None,
+                public: false,
+            },
+            body: Body::Memory(vec![0; size as usize].into()),
+            size,
+            last_used: SystemTime::now(),
+        }
+    }
+
+    fn request(
+        method: &str,
+        path: &str,
+        pairs: &[(&'static str, &'static str)],
+    ) -> hyper::Request<()> {
+        let mut r = hyper::Request::builder().method(method).uri(path);
+        for (k, v) in pairs {
+            r = r.header(*k, *v);
+        }
+        r.body(()).unwrap()
+    }
+
+    /// a `200` with `body`, and `Content-Length` so it can be stored.
+    fn response(
+        pairs: &[(&'static str, &'static str)],
+        body: &str,
+    ) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+        let mut resp =
+            kulfi_utils::http::bytes_to_resp(body.as_bytes().to_vec(), hyper::StatusCode::OK);
+        for (k, v) in pairs {
+            resp.headers_mut()
+                .append(*k, hyper::header::HeaderValue::from_static(v));
+        }
+        resp.headers_mut()
+            .insert(hyper::header::CONTENT_LENGTH, body.len().into());
+        resp
+    }
+
+    async fn text(resp: kulfi_utils::http::ProxyResponse<eyre::Error>) -> String {
+        String::from_utf8(
+            resp.into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_logged_in_users() {
+        let cache = HttpCache::new(CacheConfig::default()).await.unwrap();
+        for user in ["alice", "bob"] {
+            let resp = cache
+                .handle(
+                    request(
+                        "GET",
+                        "/me",
+                        &[(crate::http_bridge::auth::USER_HEADER, user)],
+                    ),
+                    "a",
+                    |r: hyper::Request<()>| async move {
+                        let user = r.headers()[crate::http_bridge::auth::USER_HEADER]
+                            .to_str()
+                            .unwrap()
+                            .to_string();
+                        Ok(response(&[("cache-control", "public, max-age=60")], &user))
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(text(resp).await, user);
+        }
+        assert_eq!(cache.store.lock().unwrap().size, 0);
+    }
+
+    fn x_cache(resp: &kulfi_utils::http::ProxyResponse<eyre::Error>) -> Option<&str> {
+        resp.headers().get(X_CACHE).map(|v| v.to_str().unwrap())
+    }
+
+    async fn not_forwarded(_: hyper::Request<()>) -> kulfi_utils::http::ProxyResult<eyre::Error> {
+        panic!("request should have been served from the cache");
+    }
+
+    #[tokio::test]
+    async fn test_handle_revalidation() {
+        let cache = HttpCache::new(CacheConfig::default()).await.unwrap();
+        let resp = cache
+            .handle(request("GET", "/x", &[]), "a", |_| async {
+                Ok(response(&[("etag", "\"v1\"")], "hello"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(x_cache(&resp), Some("MISS"));
+        assert_eq!(text(resp).await, "hello");
+
+        // no max-age, so every use goes to the site with the validator
+        let resp = cache
+            .handle(request("GET", "/x", &[]), "a", |r| async move {
+                assert_eq!(r.headers()[hyper::header::IF_NONE_MATCH], "\"v1\"");
+                Ok(kulfi_utils::http::bytes_to_resp(
+                    vec![],
+                    hyper::StatusCode::NOT_MODIFIED,
+                ))
+            })
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        assert_eq!(x_cache(&resp), Some("REVALIDATED"));
+        assert_eq!(text(resp).await, "hello");
+
+        // the client has it too, so it gets a 304 of its own
+        let resp = cache
+            .handle(
+                request("GET", "/x", &[("if-none-match", "\"v1\"")]),
+                "a",
+                |_| async {
+                    Ok(kulfi_utils::http::bytes_to_resp(
+                        vec![],
+                        hyper::StatusCode::NOT_MODIFIED,
+                    ))
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::NOT_MODIFIED);
+        assert_eq!(text(resp).await, "");
+
+        // a changed response replaces the entry
+        let resp = cache
+            .handle(request("GET", "/x", &[]), "a", |_| async {
+                Ok(response(&[("etag", "\"v2\"")], "world"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(x_cache(&resp), Some("MISS"));
+        assert_eq!(text(resp).await, "world");
+        let entry = cache
+            .store
+            .lock()
+            .unwrap()
+            .lookup("a", "/x", &headers(&[]))
+            .unwrap();
+        assert_eq!(entry.meta.etag.as_deref(), Some("\"v2\""));
+    }
+
+    #[tokio::test]
+    async fn test_handle_head() {
+        let cache = HttpCache::new(CacheConfig::default()).await.unwrap();
+        // nothing cached yet, HEAD goes to the site and is not stored
+        let resp = cache
+            .handle(request("HEAD", "/h", &[]), "a", |r| async move {
+                assert_eq!(r.method(), hyper::Method::HEAD);
+                Ok(response(&[("cache-control", "max-age=60")], ""))
+            })
+            .await
+            .unwrap();
+        assert_eq!(x_cache(&resp), None);
+        assert_eq!(cache.store.lock().unwrap().size, 0);
+
+        cache
+            .handle(request("GET", "/h", &[]), "a", |_| async {
+                Ok(response(&[("cache-control", "max-age=60")], "hello"))
+            })
+            .await
+            .unwrap();
+        let resp = cache
+            .handle(request("HEAD", "/h", &[]), "a", not_forwarded)
+            .await
+            .unwrap();
+        assert_eq!(x_cache(&resp), Some("HIT"));
+        assert_eq!(resp.headers()[hyper::header::CONTENT_LENGTH], "5");
+        assert_eq!(text(resp).await, "");
+    }
+
+    #[tokio::test]
+    async fn test_handle_purge() {
+        let cache = HttpCache::new(CacheConfig::default()).await.unwrap();
+        let resp = cache
+            .handle(request("PURGE", "/*", &[]), "a", not_forwarded)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
+
+        let cache = HttpCache::new(CacheConfig {
+            purge_token: Some("secret".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        for path in ["/a", "/b/c", "/b/d"] {
+            cache
+                .handle(request("GET", path, &[]), "a", |_| async {
+                    Ok(response(&[("cache-control", "max-age=60")], "hello"))
+                })
+                .await
+                .unwrap();
+        }
+
+        for auth in [&[][..], &[("authorization", "Bearer wrong")][..]] {
+            let resp = cache
+                .handle(request("PURGE", "/*", auth), "a", not_forwarded)
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), hyper::StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(cache.store.lock().unwrap().size, 15);
+
+        let auth = [("authorization", "Bearer secret")];
+        let resp = cache
+            .handle(request("PURGE", "/a", &auth), "a", not_forwarded)
+            .await
+            .unwrap();
+        assert_eq!(text(resp).await, "purged 1 entries\n");
+        // another site's purge does not touch ours
+        let resp = cache
+            .handle(request("PURGE", "/*", &auth), "b", not_forwarded)
+            .await
+            .unwrap();
+        assert_eq!(text(resp).await, "purged 0 entries\n");
+        let resp = cache
+            .handle(request("PURGE", "/b/*", &auth), "a", not_forwarded)
+            .await
+            .unwrap();
+        assert_eq!(text(resp).await, "purged 2 entries\n");
+        assert_eq!(cache.store.lock().unwrap().size, 0);
+    }
+
+    #[tokio::test]
+    async fn test_handle_cookies() {
+        let cache = HttpCache::new(CacheConfig::default()).await.unwrap();
+        cache
+            .handle(request("GET", "/login", &[]), "a", |_| async {
+                Ok(response(
+                    &[("cache-control", "max-age=60"), ("set-cookie", "s=1")],
+                    "hello",
+                ))
+            })
+            .await
+            .unwrap();
+        assert_eq!(cache.store.lock().unwrap().size, 0);
+
+        // a response to a request with a cookie is only stored when public
+        for (path, cc) in [
+            ("/private", "max-age=60"),
+            ("/public", "public, max-age=60"),
+        ] {
+            cache
+                .handle(request("GET", path, &[("cookie", "s=1")]), "a", |_| async {
+                    Ok(response(&[("cache-control", cc)], "hello"))
+                })
+                .await
+                .unwrap();
+        }
+        {
+            let mut store = cache.store.lock().unwrap();
+            assert!(store.lookup("a", "/private", &headers(&[])).is_none());
+            assert!(store.lookup("a", "/public", &headers(&[])).is_some());
+        }
+
+        // and only public responses are served to requests with a cookie
+        cache
+            .handle(request("GET", "/x", &[]), "a", |_| async {
+                Ok(response(&[("cache-control", "max-age=60")], "anonymous"))
+            })
+            .await
+            .unwrap();
+        let resp = cache
+            .handle(request("GET", "/x", &[("cookie", "s=1")]), "a", |_| async {
+                Ok(response(&[("cache-control", "max-age=60")], "alice"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(text(resp).await, "alice");
+        let resp = cache
+            .handle(request("GET", "/x", &[]), "a", not_forwarded)
+            .await
+            .unwrap();
+        assert_eq!(text(resp).await, "anonymous");
+    }
+
+    #[test]
+    fn test_cache_control_freshness() {
+        let cc = CacheControl::parse(&headers(&[("cache-control", "public, max-age=60")]));
+        assert_eq!(cc.freshness(false), Some(Duration::from_secs(60)));
+
+        let cc = CacheControl::parse(&headers(&[("cache-control", "max-age=60, s-maxage=10")]));
+        assert_eq!(cc.freshness(false), Some(Duration::from_secs(10)));
+
+        let cc = CacheControl::parse(&headers(&[("cache-control", "private, max-age=60")]));
+        assert_eq!(cc.freshness(true), None);
+
+        let cc = CacheControl::parse(&headers(&[("cache-control", "no-cache")]));
+        assert_eq!(cc.freshness(false), None);
+        assert_eq!(cc.freshness(true), Some(Duration::ZERO));
+
+        let cc = CacheControl::parse(&headers(&[]));
+        assert_eq!(cc.freshness(false), None);
+        assert_eq!(cc.freshness(true), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_store_vary_and_partitions() {
+        let mut store = Store::default();
+        let gzip = vec![("accept-encoding".to_string(), Some("gzip".to_string()))];
+        store.insert(entry("a", "/x", gzip.clone(), 10), 1000);
+        store.insert(
+            entry("a", "/x", vec![("accept-encoding".to_string(), None)], 10),
+            1000,
+        );
+
+        let hit = store.lookup("a", "/x", &headers(&[("accept-encoding", "gzip")]));
+        assert_eq!(hit.map(|e| e.meta.vary), Some(gzip));
+        assert!(
+            store
+                .lookup("a", "/x", &headers(&[("accept-encoding", "br")]))
+                .is_none()
+        );
+        assert!(store.lookup("a", "/x", &headers(&[])).is_some());
+        assert!(store.lookup("b", "/x", &headers(&[])).is_none());
+        assert_eq!(store.size, 20);
+    }
+
+    #[test]
+    fn test_store_evicts_least_recently_used() {
+        let mut store = Store::default();
+        store.insert(entry("a", "/old", vec![], 40), 100);
+        store.insert(entry("a", "/new", vec![], 40), 100);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(store.lookup("a", "/old", &headers(&[])).is_some());
+
+        let removed = store.insert(entry("b", "/big", vec![], 40), 100);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].meta.key, "/new");
+        assert_eq!(store.size, 80);
+    }
+
+    #[test]
+    fn test_store_purge_prefix() {
+        let mut store = Store::default();
+        store.insert(entry("a", "/assets/app.js", vec![], 1), 100);
+        store.insert(entry("a", "/assets/app.css", vec![], 1), 100);
+        store.insert(entry("a", "/index.html", vec![], 1), 100);
+        store.insert(entry("b", "/assets/app.js", vec![], 1), 100);
+
+        let removed = store.remove("a", |k| k.starts_with("/assets/"), None);
+        assert_eq!(removed.len(), 2);
+        assert!(store.lookup("a", "/index.html", &headers(&[])).is_some());
+        assert!(store.lookup("b", "/assets/app.js", &headers(&[])).is_some());
+        assert_eq!(store.size, 2);
+    }
+}
@@ -0,0 +1,310 @@
+//! Custom domains, e.g., `docs.example.com`, mapped to id52s.
+//!
+//! the mapping table is a file or a url (see `reload.rs`) with one `<domain> <id52>` pair per
+//! line, `#` starts a comment. the domain has to point to the bridge in DNS.
+//!
+//! since anyone running a bridge can put any name in front of any id52, the bridge can be asked to
+//! only serve domains the owner of the id52 has agreed to. the owner signs the domain with their
+//! secret key (`malai domain-token <domain>`) and publishes the token either:
+//!
+//! - over kulfi: the exposed service serves it at `/.well-known/kulfi-domain-verification`, one
+//!   token per line if the service has more than one domain, or
+//! - in DNS: a TXT record on `_kulfi.<domain>`.
+//!
+//! the bridge checks the token on the first request for a domain, and caches the result. failed
+//! checks are retried after `RETRY_FAILED_AFTER`.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+pub const WELL_KNOWN_PATH: &str = "/.well-known/kulfi-domain-verification";
+const TOKEN_PREFIX: &str = "kulfi-domain-verification=";
+const RETRY_FAILED_AFTER: Duration = Duration::from_secs(60);
+const REVERIFY_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Default, Clone)]
+pub struct DomainsConfig {
+    /// a file path or http(s) url with the mapping table, reloaded when it changes
+    pub table: Option<String>,
+    pub reload_interval: Option<Duration>,
+    /// only serve domains with a valid ownership token
+    pub verify: bool,
+}
+
+/// the token for `domain`, signed by the identity the domain should point to.
+pub fn domain_token(secret_key: &kulfi_id52::SecretKey, domain: &str) -> String {
+    let signature = secret_key.sign(message(&normalize(domain)).as_bytes());
+    format!(
+        "{TOKEN_PREFIX}{}",
+        data_encoding::HEXLOWER.encode(&signature.to_bytes())
+    )
+}
+
+fn message(domain: &str) -> String {
+    format!("kulfi-domain:{domain}")
+}
+
+fn normalize(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_lowercase()
+}
+
+/// true if any of the tokens in `content` (one per line) is valid for `domain` and `id52`.
+fn verify_tokens(content: &str, domain: &str, id52: &str) -> bool {
+    let public_key = match kulfi_id52::PublicKey::from_str(id52) {
+        Ok(k) => k,
+        Err(_) => return false,
+    };
+    let message = message(domain);
+
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(TOKEN_PREFIX))
+        .filter_map(|hex| {
+            data_encoding::HEXLOWER_PERMISSIVE
+                .decode(hex.as_bytes())
+                .ok()
+        })
+        .filter_map(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        .filter_map(|bytes| kulfi_id52::Signature::from_bytes(&bytes).ok())
+        .any(|signature| public_key.verify(message.as_bytes(), &signature).is_ok())
+}
+
+enum Status {
+    Verified(Instant),
+    Failed(Instant),
+}
+
+pub struct Domains {
+    table: RwLock<HashMap<String, String>>,
+    verify: bool,
+    /// keyed by (domain, id52), so a changed mapping is verified again
+    status: Mutex<HashMap<(String, String), Status>>,
+}
+
+impl Domains {
+    /// returns `None` if no mapping table is configured.
+    pub async fn new(
+        config: DomainsConfig,
+        graceful: kulfi_utils::Graceful,
+    ) -> eyre::Result<Option<Arc<Self>>> {
+        let table = match config.table {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+
+        let domains = Arc::new(Self {
+            table: RwLock::new(HashMap::new()),
+            verify: config.verify,
+            status: Mutex::new(HashMap::new()),
+        });
+
+        let domains_for_watch = domains.clone();
+        super::reload::watch(
+            super::reload::Source::parse(&table),
+            config
+                .reload_interval
+                .unwrap_or(super::reload::DEFAULT_RELOAD_INTERVAL),
+            graceful,
+            move |content| {
+                let table = parse_table(content);
+                tracing::info!("domain table has {} domains", table.len());
+                *domains_for_watch.table.write().unwrap() = table;
+            },
+        )
+        .await?;
+
+        Ok(Some(domains))
+    }
+
+    /// the id52 `host` is mapped to, `host` may include a port.
+    pub fn lookup(&self, host: &str) -> Option<(String, String)> {
+        let domain = normalize(host.rsplit_once(':').map_or(host, |(h, _)| h));
+        let id52 = self.table.read().unwrap().get(&domain)?.clone();
+        Some((domain, id52))
+    }
+
+    /// checks the ownership token of `domain`, if verification is on. the result is cached.
+    pub async fn verify(
+        &self,
+        domain: &str,
+        id52: &str,
+        self_endpoint: iroh::Endpoint,
+        peer_connections: kulfi_utils::PeerStreamSenders,
+        graceful: kulfi_utils::Graceful,
+    ) -> bool {
+        if !self.verify {
+            return true;
+        }
+
+        let key = (domain.to_string(), id52.to_string());
+        match self.status.lock().unwrap().get(&key) {
+            Some(Status::Verified(at)) if at.elapsed() < REVERIFY_AFTER => return true,
+            Some(Status::Failed(at)) if at.elapsed() < RETRY_FAILED_AFTER => return false,
+            _ => {}
+        }
+
+        let verified =
+            match fetch_well_known(domain, id52, self_endpoint, peer_connections, graceful).await {
+                Ok(content) if verify_tokens(&content, domain, id52) => true,
+                Ok(_) => {
+                    tracing::info!(domain, id52, "no valid token at {WELL_KNOWN_PATH}");
+                    false
+                }
+                Err(e) => {
+                    tracing::info!(domain, id52, "failed to fetch {WELL_KNOWN_PATH}: {e:?}");
+                    false
+                }
+            };
+
+        let verified = verified
+            || match fetch_txt(domain).await {
+                Ok(content) if verify_tokens(&content, domain, id52) => true,
+                Ok(_) => {
+                    tracing::info!(domain, id52, "no valid token in TXT of _kulfi.{domain}");
+                    false
+                }
+                Err(e) => {
+                    tracing::info!(
+                        domain,
+                        id52,
+                        "failed to look up TXT of _kulfi.{domain}: {e:?}"
+                    );
+                    false
+                }
+            };
+
+        if verified {
+            tracing::info!(domain, id52, "domain verified");
+        } else {
+            tracing::warn!(domain, id52, "domain not verified");
+        }
+
+        let now = Instant::now();
+        self.status.lock().unwrap().insert(
+            key,
+            if verified {
+                Status::Verified(now)
+            } else {
+                Status::Failed(now)
+            },
+        );
+        verified
+    }
+}
+
+async fn fetch_well_known(
+    domain: &str,
+    id52: &str,
+    self_endpoint: iroh::Endpoint,
+    peer_connections: kulfi_utils::PeerStreamSenders,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<String> {
+    use http_body_util::BodyExt;
+
+    let r = hyper::Request::builder()
+        .uri(WELL_KNOWN_PATH)
+        .header(hyper::header::HOST, domain)
+        .body(hyper::body::Bytes::new())?;
+    let resp = kulfi_utils::http_to_peer_non_streaming(
+        kulfi_utils::Protocol::Http.into(),
+        r,
+        self_endpoint,
+        id52,
+        peer_connections,
+        graceful,
+    )
+    .await?;
+
+    if !resp.status().is_success() {
+        return Err(eyre::anyhow!("got {}", resp.status()));
+    }
+
+    let body = resp.into_body().collect().await?.to_bytes();
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+async fn fetch_txt(domain: &str) -> eyre::Result<String> {
+    let resolver = hickory_resolver::Resolver::builder_tokio()?.build();
+    let lookup = resolver.txt_lookup(format!("_kulfi.{domain}.")).await?;
+    Ok(lookup
+        .iter()
+        .map(|txt| {
+            txt.iter()
+                .map(|s| String::from_utf8_lossy(s).into_owned())
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn parse_table(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(domain), Some(id52), None)
+                    if kulfi_id52::PublicKey::from_str(id52).is_ok() =>
+                {
+                    Some((normalize(domain), id52.to_string()))
+                }
+                _ => {
+                    tracing::warn!("ignoring invalid line in domain table: {line}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_parse_table() {
+        let table = super::parse_table(
+            "# name   id52\n\
+             Docs.Example.com. i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60\n\
+             blog.example.com e87aeds2fajaeu10tjdio5ppcdha410n6tu4665u7el9as9b7v80 # team blog\n\
+             broken.example.com\n\
+             other.example.com not-an-id52\n",
+        );
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table.get("docs.example.com").unwrap(),
+            "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60"
+        );
+    }
+
+    #[test]
+    fn test_domain_token() {
+        let key = kulfi_id52::SecretKey::generate();
+        let other = kulfi_id52::SecretKey::generate();
+        let token = super::domain_token(&key, "Docs.Example.com");
+
+        let content = format!(
+            "# tokens\n{}\n{token}\n",
+            super::domain_token(&other, "x.com")
+        );
+        assert!(super::verify_tokens(
+            &content,
+            "docs.example.com",
+            &key.id52()
+        ));
+        // signed for another domain
+        assert!(!super::verify_tokens(
+            &token,
+            "blog.example.com",
+            &key.id52()
+        ));
+        // signed by another identity
+        assert!(!super::verify_tokens(
+            &token,
+            "docs.example.com",
+            &other.id52()
+        ));
+    }
+}
@@ -0,0 +1,372 @@
+//! Abuse controls for public bridges: request rate limits, connection caps and bandwidth limits.
+//!
+//! all limits are off by default. requests over a rate limit, and connections over a cap, get a
+//! `429 Too Many Requests` with a `Retry-After` header. bandwidth limits do not reject anything,
+//! they slow down the response bodies.
+//!
+//! rate and bandwidth limits are token buckets that hold one second worth of tokens, but at least
+//! one, so a client can burst up to the configured rate and is then throttled to it. with rates
+//! below one per second, e.g. `0.5`, a client gets one request and then one every two seconds.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// buckets that have been full for this long are forgotten, to keep memory bounded.
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Default, Clone)]
+pub struct LimitsConfig {
+    /// requests per second from one client ip
+    pub ip_rate: Option<f64>,
+    /// requests per second to one id52
+    pub target_rate: Option<f64>,
+    /// concurrent connections to the bridge
+    pub max_connections: Option<usize>,
+    /// concurrent connections from one client ip
+    pub max_connections_per_ip: Option<usize>,
+    /// response bytes per second from one id52
+    pub bandwidth: Option<u64>,
+    /// use the last address in `X-Forwarded-For` as the client ip, only enable this if the bridge
+    /// is behind a reverse proxy that sets it.
+    pub trust_forwarded_for: bool,
+}
+
+impl LimitsConfig {
+    /// errors on rates that can not be limited to, e.g. `0`, `NaN` or negative ones.
+    pub fn validate(&self) -> eyre::Result<()> {
+        for (name, rate) in [("ip rate", self.ip_rate), ("target rate", self.target_rate)] {
+            if let Some(rate) = rate
+                && !(rate.is_finite() && rate > 0.0)
+            {
+                return Err(eyre::anyhow!(
+                    "the {name} limit must be a positive number of requests per second, not {rate}"
+                ));
+            }
+        }
+        if self.bandwidth == Some(0) {
+            return Err(eyre::anyhow!(
+                "the bandwidth limit must be a positive number of bytes per second"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// a set of token buckets, one per key, all with the same rate.
+pub struct RateLimiter<K> {
+    rate: f64,
+    /// how many tokens a full bucket has, a request costs one so it is never less than that
+    capacity: f64,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Hash + Eq + Clone> RateLimiter<K> {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            capacity: rate.max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// takes `n` tokens from the bucket of `key`, letting it go into debt, and returns how long
+    /// the caller has to wait for the debt to be paid off.
+    fn reserve(&self, key: &K, n: f64, now: Instant) -> Duration {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > 10_000 {
+            let (rate, capacity) = (self.rate, self.capacity);
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < capacity
+                    || now.duration_since(b.updated) < IDLE_BUCKET_TTL
+            });
+        }
+
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.updated = now;
+        bucket.tokens -= n;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        }
+    }
+
+    /// takes one token if available, otherwise returns how long until one is.
+    pub fn check(&self, key: &K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &K, now: Instant) -> Result<(), Duration> {
+        let wait = self.reserve(key, 1.0, now);
+        if wait.is_zero() {
+            return Ok(());
+        }
+        // a rejected request should not count against the client
+        if let Some(b) = self.buckets.lock().unwrap().get_mut(key) {
+            b.tokens += 1.0;
+        }
+        Err(wait)
+    }
+
+    /// waits until `n` tokens have been paid for.
+    pub async fn consume(&self, key: &K, n: usize) {
+        let wait = self.reserve(key, n as f64, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// counts open connections, in total and per client ip.
+#[derive(Default)]
+pub struct ConnectionLimiter {
+    max_total: Option<usize>,
+    max_per_ip: Option<usize>,
+    open: Mutex<(usize, HashMap<IpAddr, usize>)>,
+}
+
+/// decrements the connection count when the connection is done
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut open = self.limiter.open.lock().unwrap();
+        open.0 -= 1;
+        if let Some(count) = open.1.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.1.remove(&self.ip);
+            }
+        }
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_total: Option<usize>, max_per_ip: Option<usize>) -> Self {
+        Self {
+            max_total,
+            max_per_ip,
+            open: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    /// returns `None` if the connection would go over a cap.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut open = self.open.lock().unwrap();
+        let for_ip = open.1.get(&ip).copied().unwrap_or_default();
+        if self.max_total.is_some_and(|max| open.0 >= max)
+            || self.max_per_ip.is_some_and(|max| for_ip >= max)
+        {
+            return None;
+        }
+        open.0 += 1;
+        *open.1.entry(ip).or_default() += 1;
+        Some(ConnectionGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+}
+
+pub struct Limits {
+    pub config: LimitsConfig,
+    ip_rate: Option<RateLimiter<IpAddr>>,
+    target_rate: Option<RateLimiter<String>>,
+    bandwidth: Option<Arc<RateLimiter<String>>>,
+    pub connections: Arc<ConnectionLimiter>,
+}
+
+impl Limits {
+    pub fn new(config: LimitsConfig) -> eyre::Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            ip_rate: config.ip_rate.map(RateLimiter::new),
+            target_rate: config.target_rate.map(RateLimiter::new),
+            bandwidth: config
+                .bandwidth
+                .map(|b| Arc::new(RateLimiter::new(b as f64))),
+            connections: Arc::new(ConnectionLimiter::new(
+                config.max_connections,
+                config.max_connections_per_ip,
+            )),
+            config,
+        })
+    }
+
+    /// the ip to rate limit on, see `LimitsConfig::trust_forwarded_for`
+    pub fn client_ip(&self, headers: &hyper::HeaderMap, peer: IpAddr) -> IpAddr {
+        if !self.config.trust_forwarded_for {
+            return peer;
+        }
+        headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(peer)
+    }
+
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), Duration> {
+        match &self.ip_rate {
+            Some(limiter) => limiter.check(&ip),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_target(&self, id52: &str) -> Result<(), Duration> {
+        match &self.target_rate {
+            Some(limiter) => limiter.check(&id52.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// slows down the body of `resp` to the bandwidth limit of `id52`, if any.
+    pub fn throttle(
+        &self,
+        id52: &str,
+        resp: kulfi_utils::http::ProxyResponse<eyre::Error>,
+    ) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+        use http_body_util::BodyExt;
+
+        let limiter = match &self.bandwidth {
+            Some(limiter) => limiter.clone(),
+            None => return resp,
+        };
+
+        let id52 = id52.to_string();
+        let (parts, body) = resp.into_parts();
+        let frames = futures_util::stream::unfold(body, move |mut body| {
+            let limiter = limiter.clone();
+            let id52 = id52.clone();
+            async move {
+                let frame = body.frame().await?;
+                if let Ok(frame) = &frame
+                    && let Some(data) = frame.data_ref()
+                {
+                    limiter.consume(&id52, data.len()).await;
+                }
+                Some((frame, body))
+            }
+        });
+
+        hyper::Response::from_parts(parts, http_body_util::StreamBody::new(frames).boxed())
+    }
+}
+
+pub fn too_many_requests(retry_after: Duration) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+    let mut resp = kulfi_utils::http::bytes_to_resp(
+        b"too many requests, slow down\n".to_vec(),
+        hyper::StatusCode::TOO_MANY_REQUESTS,
+    );
+    resp.headers_mut().insert(
+        hyper::header::RETRY_AFTER,
+        retry_after
+            .as_secs_f64()
+            .ceil()
+            .max(1.0)
+            .to_string()
+            .parse()
+            .unwrap(),
+    );
+    resp
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_burst_and_refill() {
+        let limiter = RateLimiter::new(2.0);
+        let now = Instant::now();
+        assert!(limiter.check_at(&"a", now).is_ok());
+        assert!(limiter.check_at(&"a", now).is_ok());
+        let wait = limiter.check_at(&"a", now).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // other keys have their own bucket
+        assert!(limiter.check_at(&"b", now).is_ok());
+        // rejected requests do not dig the hole deeper
+        assert!(limiter.check_at(&"a", now).is_err());
+        assert!(
+            limiter
+                .check_at(&"a", now + Duration::from_millis(500))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_below_one_per_second() {
+        let limiter = RateLimiter::new(0.5);
+        let now = Instant::now();
+        assert!(limiter.check_at(&"a", now).is_ok());
+        let wait = limiter.check_at(&"a", now).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(2));
+        assert!(
+            limiter
+                .check_at(&"a", now + Duration::from_secs(1))
+                .is_err()
+        );
+        assert!(limiter.check_at(&"a", now + Duration::from_secs(2)).is_ok());
+        // the bucket never holds more than one request
+        assert!(
+            limiter
+                .check_at(&"a", now + Duration::from_secs(60))
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .check_at(&"a", now + Duration::from_secs(60))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let limits = |ip_rate, target_rate, bandwidth| LimitsConfig {
+            ip_rate,
+            target_rate,
+            bandwidth,
+            ..Default::default()
+        };
+        assert!(Limits::new(limits(Some(0.5), Some(100.0), Some(1))).is_ok());
+        assert!(Limits::new(limits(Some(0.0), None, None)).is_err());
+        assert!(Limits::new(limits(Some(-1.0), None, None)).is_err());
+        assert!(Limits::new(limits(None, Some(f64::NAN), None)).is_err());
+        assert!(Limits::new(limits(None, Some(f64::INFINITY), None)).is_err());
+        assert!(Limits::new(limits(None, None, Some(0))).is_err());
+    }
+
+    #[test]
+    fn test_connection_limiter() {
+        let limiter = Arc::new(ConnectionLimiter::new(Some(3), Some(2)));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let a1 = limiter.acquire(a).unwrap();
+        let _a2 = limiter.acquire(a).unwrap();
+        assert!(limiter.acquire(a).is_none(), "per ip cap");
+        let _b1 = limiter.acquire(b).unwrap();
+        assert!(limiter.acquire(b).is_none(), "total cap");
+
+        drop(a1);
+        assert!(limiter.acquire(b).is_some());
+    }
+}
@@ -0,0 +1,769 @@
+mod aliases;
+mod allowlist;
+mod auth;
+mod cache;
+mod domains;
+mod limits;
+mod path_routing;
+mod reload;
+
+use aliases::DEFAULT_MIN_PREFIX;
+pub use aliases::{AliasesConfig, DEFAULT_MIN_PREFIX as DEFAULT_ALIAS_MIN_PREFIX};
+pub use allowlist::AllowlistConfig;
+pub(crate) use auth::check_file as check_auth_file;
+pub use auth::{AuthConfig, hash_password};
+pub use cache::{CacheBackend, CacheConfig};
+pub use domains::{DomainsConfig, domain_token};
+pub use limits::LimitsConfig;
+pub use path_routing::PathRoutingConfig;
+
+/// Optional features of the http bridge, everything is off by default.
+#[derive(Debug, Default, Clone)]
+pub struct HttpBridgeConfig {
+    pub cache: Option<CacheConfig>,
+    pub allowlist: AllowlistConfig,
+    pub limits: LimitsConfig,
+    pub domains: DomainsConfig,
+    pub aliases: AliasesConfig,
+    pub path_routing: PathRoutingConfig,
+    pub auth: AuthConfig,
+    /// access tokens to present to the services that require one, see `malai token create`.
+    /// they must all be for one identity, which must be on this machine.
+    pub tokens: Vec<String>,
+}
+
+/// The options of `malai http-bridge`, also accepted in `[http_bridge.<name>]` sections of
+/// `malai.toml`, with `_` instead of `-`.
+#[derive(Debug, Clone, PartialEq, clap::Args, serde::Deserialize)]
+#[serde(default)]
+pub struct HttpBridgeOptions {
+    #[arg(
+        long,
+        help = "Cache cacheable responses (per Cache-Control, ETag/Last-Modified and Vary) in memory or on disk. Disabled by default."
+    )]
+    pub cache: Option<CacheBackend>,
+    #[arg(
+        long,
+        help = "Directory for the disk cache.",
+        required_if_eq("cache", "disk")
+    )]
+    pub cache_dir: Option<String>,
+    #[arg(
+        long,
+        help = "Maximum total size of cached responses, in bytes.",
+        default_value_t = CacheConfig::default().max_size
+    )]
+    pub cache_max_size: u64,
+    #[arg(
+        long,
+        help = "Responses larger than this many bytes are not cached.",
+        default_value_t = CacheConfig::default().max_entry_size
+    )]
+    pub cache_max_entry_size: u64,
+    #[arg(
+        long,
+        help = "Enables `PURGE <path>` requests (a path ending in `*` purges by prefix), which must send `Authorization: Bearer <token>`. Can be set via MALAI_CACHE_PURGE_TOKEN env var.",
+        env = "MALAI_CACHE_PURGE_TOKEN"
+    )]
+    pub cache_purge_token: Option<String>,
+    #[arg(
+        long,
+        help = "Only forward to this id52, can be passed multiple times. By default the bridge forwards to every id52."
+    )]
+    pub allow: Vec<String>,
+    #[arg(
+        long,
+        help = "A file or http(s) url listing the id52s to forward to, one per line. Reloaded when it changes."
+    )]
+    pub allow_registry: Option<String>,
+    #[arg(
+        long,
+        help = "How often to check the allow registry for changes, in seconds. Defaults to 30."
+    )]
+    pub allow_reload_interval: Option<u64>,
+    #[arg(long, help = "Maximum requests per second from one client IP.")]
+    pub ip_rate_limit: Option<f64>,
+    #[arg(long, help = "Maximum requests per second forwarded to one id52.")]
+    pub target_rate_limit: Option<f64>,
+    #[arg(long, help = "Maximum concurrent connections to the bridge.")]
+    pub max_connections: Option<usize>,
+    #[arg(long, help = "Maximum concurrent connections from one client IP.")]
+    pub max_connections_per_ip: Option<usize>,
+    #[arg(
+        long,
+        help = "Maximum response bytes per second from one id52. Responses are slowed down, not rejected."
+    )]
+    pub bandwidth_limit: Option<u64>,
+    #[arg(
+        long,
+        help = "Use the X-Forwarded-For header as the client IP for rate limits. Only use this behind a reverse proxy that sets it."
+    )]
+    pub trust_forwarded_for: bool,
+    #[arg(
+        long,
+        help = "A file or http(s) url mapping custom domains to id52s, one `<domain> <id52>` pair per line. Reloaded when it changes."
+    )]
+    pub domains: Option<String>,
+    #[arg(
+        long,
+        help = "How often to check the domain table for changes, in seconds. Defaults to 30."
+    )]
+    pub domains_reload_interval: Option<u64>,
+    #[arg(
+        long,
+        requires = "domains",
+        help = "Only serve custom domains whose id52 has published a token from `malai domain-token`."
+    )]
+    pub verify_domains: bool,
+    #[arg(
+        long,
+        help = "A file or http(s) url listing known id52s, one per line, optionally preceded by a name. Lets clients use `<name>.<bridge>` or a unique id52 prefix instead of the full id52. Reloaded when it changes."
+    )]
+    pub aliases: Option<String>,
+    #[arg(
+        long,
+        help = "How often to check the alias registry for changes, in seconds. Defaults to 30."
+    )]
+    pub aliases_reload_interval: Option<u64>,
+    #[arg(
+        long,
+        help = "The shortest id52 prefix accepted as an alias.",
+        default_value_t = DEFAULT_MIN_PREFIX
+    )]
+    pub alias_min_prefix: usize,
+    #[arg(
+        long,
+        help = "Also route `/~<id52>/path` to `/path` on <id52>, for when wildcard DNS is not available."
+    )]
+    pub path_routing: bool,
+    #[arg(
+        long,
+        requires = "path_routing",
+        help = "Add the `/~<id52>` prefix to absolute links in html and Location headers of path routed responses."
+    )]
+    pub rewrite_links: bool,
+    #[arg(
+        long,
+        requires = "path_routing",
+        help = "Remember the id52 of the last path routed request in a cookie, and route requests without the prefix to it."
+    )]
+    pub sticky: bool,
+    #[arg(
+        long,
+        help = "A toml file or http(s) url saying which id52s need a login, and how: HTTP basic auth, a login page or OpenID Connect. Reloaded when it changes."
+    )]
+    pub auth: Option<String>,
+    #[arg(
+        long,
+        help = "How often to check the auth file for changes, in seconds. Defaults to 30."
+    )]
+    pub auth_reload_interval: Option<u64>,
+    #[arg(
+        long,
+        help = "An access token to present to the service it is for, for services started with --require-token. Can be repeated."
+    )]
+    pub token: Vec<String>,
+}
+
+impl Default for HttpBridgeOptions {
+    fn default() -> Self {
+        Self {
+            cache: None,
+            cache_dir: None,
+            cache_max_size: CacheConfig::default().max_size,
+            cache_max_entry_size: CacheConfig::default().max_entry_size,
+            cache_purge_token: None,
+            allow: vec![],
+            allow_registry: None,
+            allow_reload_interval: None,
+            ip_rate_limit: None,
+            target_rate_limit: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            bandwidth_limit: None,
+            trust_forwarded_for: false,
+            domains: None,
+            domains_reload_interval: None,
+            verify_domains: false,
+            aliases: None,
+            aliases_reload_interval: None,
+            alias_min_prefix: DEFAULT_MIN_PREFIX,
+            path_routing: false,
+            rewrite_links: false,
+            sticky: false,
+            auth: None,
+            auth_reload_interval: None,
+            token: vec![],
+        }
+    }
+}
+
+impl HttpBridgeOptions {
+    pub fn config(self) -> HttpBridgeConfig {
+        HttpBridgeConfig {
+            cache: self.cache.map(|backend| CacheConfig {
+                backend,
+                dir: self.cache_dir.map(Into::into),
+                max_size: self.cache_max_size,
+                max_entry_size: self.cache_max_entry_size,
+                purge_token: self.cache_purge_token,
+            }),
+            allowlist: AllowlistConfig {
+                ids: self.allow,
+                registry: self.allow_registry,
+                reload_interval: self
+                    .allow_reload_interval
+                    .map(std::time::Duration::from_secs),
+            },
+            limits: LimitsConfig {
+                ip_rate: self.ip_rate_limit,
+                target_rate: self.target_rate_limit,
+                max_connections: self.max_connections,
+                max_connections_per_ip: self.max_connections_per_ip,
+                bandwidth: self.bandwidth_limit,
+                trust_forwarded_for: self.trust_forwarded_for,
+            },
+            domains: DomainsConfig {
+                table: self.domains,
+                reload_interval: self
+                    .domains_reload_interval
+                    .map(std::time::Duration::from_secs),
+                verify: self.verify_domains,
+            },
+            aliases: AliasesConfig {
+                registry: self.aliases,
+                reload_interval: self
+                    .aliases_reload_interval
+                    .map(std::time::Duration::from_secs),
+                min_prefix: Some(self.alias_min_prefix),
+            },
+            path_routing: PathRoutingConfig {
+                enabled: self.path_routing,
+                rewrite: self.rewrite_links,
+                sticky: self.sticky,
+            },
+            auth: AuthConfig {
+                file: self.auth,
+                reload_interval: self
+                    .auth_reload_interval
+                    .map(std::time::Duration::from_secs),
+            },
+            tokens: self.token,
+        }
+    }
+}
+
+/// per bridge state shared by all connections.
+struct Bridge {
+    proxy_target: Option<String>,
+    cache: Option<std::sync::Arc<cache::HttpCache>>,
+    allowlist: Option<std::sync::Arc<allowlist::Allowlist>>,
+    limits: limits::Limits,
+    domains: Option<std::sync::Arc<domains::Domains>>,
+    aliases: Option<std::sync::Arc<aliases::Aliases>>,
+    path_routing: PathRoutingConfig,
+    auth: Option<std::sync::Arc<auth::Auth>>,
+    tokens: Option<crate::token::Tokens>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn http_bridge(
+    port: u16,
+    proxy_target: Option<String>,
+    config: HttpBridgeConfig,
+    graceful: kulfi_utils::Graceful,
+    post_start: impl FnOnce(u16) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    let handle = start_http_bridge(port, proxy_target, config, graceful.clone()).await?;
+    kulfi_utils::systemd::notify_ready();
+    // start_http_bridge() always binds a local address
+    let port = handle.local_addr().map(|a| a.port()).unwrap_or_default();
+
+    match post_start(port) {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Failed to run post start function: {e:?}");
+        }
+    }
+
+    println!("Listening on http://127.0.0.1:{port}");
+    handle
+        .wait_showing_info(graceful, || {
+            println!("Listening on http://127.0.0.1:{port}");
+            println!("Press ctrl+c again to exit.");
+        })
+        .await
+}
+
+/// starts the bridge on the local port, 0 picks a free one, without printing anything.
+pub async fn start_http_bridge(
+    port: u16,
+    proxy_target: Option<String>,
+    config: HttpBridgeConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
+    use eyre::WrapErr;
+
+    // bound first, so nothing is left reloading in the background if the port is busy
+    let listener = kulfi_utils::systemd::listen_tcp(port)
+        .await
+        .wrap_err_with(|| {
+            format!("can not listen on port {port}, is it busy, or you do not have root access?")
+        })?;
+
+    // because the caller can pass the port as 0 if they want to bind to a random port
+    let local_addr = listener
+        .local_addr()
+        .wrap_err("failed to get local address")?;
+
+    // the reloaders started by new_bridge stop with the bridge, or right away if it fails
+    let graceful = graceful.child();
+    let bridge = new_bridge(proxy_target, config, graceful.clone())
+        .await
+        .inspect_err(|_| graceful.cancel())?;
+
+    let task = graceful.spawn(serve(
+        listener,
+        std::sync::Arc::new(bridge),
+        graceful.clone(),
+    ));
+    Ok(malai::ServiceHandle::new(
+        None,
+        Some(local_addr),
+        graceful,
+        task,
+    ))
+}
+
+async fn new_bridge(
+    proxy_target: Option<String>,
+    config: HttpBridgeConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<Bridge> {
+    use eyre::WrapErr;
+
+    let limits = limits::Limits::new(config.limits).wrap_err("invalid limits")?;
+
+    let cache = match config.cache {
+        Some(c) => Some(
+            cache::HttpCache::new(c)
+                .await
+                .wrap_err("failed to set up http cache")?,
+        ),
+        None => None,
+    };
+
+    let allowlist = allowlist::Allowlist::new(config.allowlist, graceful.clone())
+        .await
+        .wrap_err("failed to load allowlist")?;
+
+    let domains = domains::Domains::new(config.domains, graceful.clone())
+        .await
+        .wrap_err("failed to load domain table")?;
+
+    let aliases = aliases::Aliases::new(config.aliases, graceful.clone())
+        .await
+        .wrap_err("failed to load alias registry")?;
+
+    let auth = auth::Auth::new(config.auth, graceful.clone())
+        .await
+        .wrap_err("failed to load auth file")?;
+
+    let tokens = crate::token::Tokens::new(
+        &config.tokens,
+        kulfi_utils::Protocol::Http,
+        proxy_target.as_deref(),
+    )
+    .await
+    .wrap_err("failed to load access tokens")?;
+
+    Ok(Bridge {
+        proxy_target,
+        cache,
+        allowlist,
+        limits,
+        domains,
+        aliases,
+        path_routing: config.path_routing,
+        auth,
+        tokens,
+    })
+}
+
+async fn serve(
+    listener: tokio::net::TcpListener,
+    bridge: std::sync::Arc<Bridge>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    use eyre::WrapErr;
+
+    let peer_connections = kulfi_utils::PeerStreamSenders::default();
+
+    loop {
+        tokio::select! {
+            () = graceful.cancelled() => {
+                tracing::info!("Stopping control server.");
+                break;
+            }
+            r = listener.accept() => {
+                match r {
+                    Ok((stream, addr)) => {
+                        tracing::info!("got connection");
+                        let graceful_for_handle_connection = graceful.clone();
+                        let peer_connections = peer_connections.clone();
+                        let bridge = bridge.clone();
+                        graceful.spawn(async move {
+                            let self_endpoint =
+                                crate::token::self_endpoint(bridge.tokens.as_ref()).await;
+                            handle_connection(
+                                self_endpoint,
+                                stream,
+                                addr,
+                                graceful_for_handle_connection,
+                                peer_connections,
+                                bridge,
+                            )
+                            .await
+                        });
+                    }
+                    Err(e) => {
+                        tracing::error!("failed to accept: {e:?}");
+                        return Err(e).wrap_err("failed to accept");
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn handle_connection(
+    self_endpoint: iroh::Endpoint,
+    stream: tokio::net::TcpStream,
+    addr: std::net::SocketAddr,
+    graceful: kulfi_utils::Graceful,
+    peer_connections: kulfi_utils::PeerStreamSenders,
+    bridge: std::sync::Arc<Bridge>,
+) {
+    // held till the connection is closed
+    let _guard = match bridge.limits.connections.acquire(addr.ip()) {
+        Some(guard) => guard,
+        None => {
+            tracing::info!(%addr, "too many connections");
+            reject_connection(stream).await;
+            return;
+        }
+    };
+
+    let io = hyper_util::rt::TokioIo::new(stream);
+
+    let builder =
+        hyper_util::server::conn::auto::Builder::new(hyper_util::rt::tokio::TokioExecutor::new());
+    // the following builder runs only http2 service, whereas the hyper_util auto Builder runs an
+    // http1.1 server that upgrades to http2 if the client requests.
+    // let builder = hyper::server::conn::http2::Builder::new(hyper_util::rt::tokio::TokioExecutor::new());
+    tokio::pin! {
+        let conn = builder
+            .serve_connection(
+                io,
+                hyper::service::service_fn(|r| handle_request(r, addr, self_endpoint.clone(), peer_connections.clone(), bridge.clone(), graceful.clone())),
+            );
+    }
+
+    if let Err(e) = tokio::select! {
+        _ = graceful.cancelled() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+        r = &mut conn => r,
+    } {
+        tracing::error!("connection error2: {e:?}");
+    }
+}
+
+/// serves a single 429 on a connection we are not going to keep.
+async fn reject_connection(stream: tokio::net::TcpStream) {
+    let io = hyper_util::rt::TokioIo::new(stream);
+    let service = hyper::service::service_fn(|_r| async {
+        let mut resp = limits::too_many_requests(std::time::Duration::from_secs(1));
+        resp.headers_mut().insert(
+            hyper::header::CONNECTION,
+            hyper::header::HeaderValue::from_static("close"),
+        );
+        Ok::<_, std::convert::Infallible>(resp)
+    });
+    if let Err(e) = hyper::server::conn::http1::Builder::new()
+        .keep_alive(false)
+        .serve_connection(io, service)
+        .await
+    {
+        tracing::debug!("failed to reject connection: {e:?}");
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn handle_request(
+    r: hyper::Request<hyper::body::Incoming>,
+    addr: std::net::SocketAddr,
+    self_endpoint: iroh::Endpoint,
+    peer_connections: kulfi_utils::PeerStreamSenders,
+    bridge: std::sync::Arc<Bridge>,
+    graceful: kulfi_utils::Graceful,
+) -> kulfi_utils::http::ProxyResult<eyre::Error> {
+    let client_ip = bridge.limits.client_ip(r.headers(), addr.ip());
+    if let Err(retry_after) = bridge.limits.check_ip(client_ip) {
+        tracing::info!(%client_ip, "rate limited");
+        return Ok(limits::too_many_requests(retry_after));
+    }
+
+    let mut r = r;
+
+    // `/~<id52>/...` takes precedence over the host, and without it the sticky cookie does
+    let mut prefix_label = None;
+    let routed = if bridge.path_routing.enabled {
+        let path_and_query = r.uri().path_and_query().map_or("/", |p| p.as_str());
+        match path_routing::split_prefix(path_and_query) {
+            Some((label, _)) if r.uri().path() == format!("/~{label}") => {
+                return Ok(path_routing::redirect_to_root(&label));
+            }
+            Some((label, rest)) => {
+                let peer_id = match bridge.aliases.as_ref().and_then(|a| a.resolve(&label)) {
+                    Some(aliases::Resolved::Id52(peer_id)) => peer_id,
+                    Some(aliases::Resolved::Ambiguous) => {
+                        return Ok(kulfi_utils::bad_request!(
+                            "this prefix matches more than one id52, use a longer one"
+                        ));
+                    }
+                    None if label.len() == 52 => label.clone(),
+                    None => {
+                        tracing::error!(label, "request received for invalid peer id");
+                        return Ok(kulfi_utils::bad_request!(
+                            "failed to get peer id from request"
+                        ));
+                    }
+                };
+                if let Err(e) = path_routing::strip_prefix(&mut r, &rest) {
+                    tracing::error!("failed to strip path prefix: {e:?}");
+                    return Ok(kulfi_utils::bad_request!("invalid path"));
+                }
+                prefix_label = Some(label);
+                Some(peer_id)
+            }
+            None if bridge.path_routing.sticky => path_routing::sticky_target(r.headers()),
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    let peer_id = match routed {
+        Some(peer_id)
+            if bridge
+                .proxy_target
+                .as_ref()
+                .is_some_and(|target| *target != peer_id) =>
+        {
+            tracing::error!(peer_id, "request for peer_id is not allowed");
+            return Ok(kulfi_utils::bad_request!(
+                "failed to get peer id from request"
+            ));
+        }
+        Some(peer_id) => peer_id,
+        None => match peer_id_from_host(
+            r.headers().get("Host").and_then(|h| h.to_str().ok()),
+            &bridge,
+            &self_endpoint,
+            &peer_connections,
+            &graceful,
+        )
+        .await
+        {
+            Ok(peer_id) => peer_id,
+            Err(resp) => return Ok(resp),
+        },
+    };
+
+    tracing::debug!("got request for {peer_id}");
+
+    if let Some(allowlist) = &bridge.allowlist
+        && !allowlist.is_allowed(&peer_id)
+    {
+        tracing::info!(%peer_id, "not in allowlist");
+        return Ok(kulfi_utils::http::bytes_to_resp(
+            b"this bridge does not serve this id52\n".to_vec(),
+            hyper::StatusCode::FORBIDDEN,
+        ));
+    }
+
+    // before the cache, so cached responses are only served to users who logged in
+    if let Some(auth) = &bridge.auth {
+        let base = prefix_label
+            .as_ref()
+            .map(|label| format!("/~{label}"))
+            .unwrap_or_default();
+        r = match auth.check(r, &peer_id, &base).await {
+            Ok(r) => r,
+            Err(resp) => return Ok(resp),
+        };
+    }
+
+    // cache hits are served locally, so only the requests that reach the peer count against its
+    // rate and bandwidth limits
+    let forward = |r| {
+        let peer_id = peer_id.clone();
+        let bridge = bridge.clone();
+        async move {
+            if let Err(retry_after) = bridge.limits.check_target(&peer_id) {
+                tracing::info!(%peer_id, "rate limited");
+                return Ok(limits::too_many_requests(retry_after));
+            }
+            let resp = kulfi_utils::http_to_peer(
+                crate::token::header(
+                    bridge.tokens.as_ref(),
+                    kulfi_utils::Protocol::Http,
+                    &peer_id,
+                ),
+                r,
+                self_endpoint,
+                &peer_id,
+                peer_connections,
+                graceful,
+            )
+            .await?;
+            Ok(bridge.limits.throttle(&peer_id, resp))
+        }
+    };
+
+    let resp = match &bridge.cache {
+        Some(cache) => cache.handle(r, &peer_id, forward).await?,
+        None => forward(r).await?,
+    };
+
+    match prefix_label {
+        Some(label) => {
+            Ok(path_routing::fix_response(&bridge.path_routing, &label, &peer_id, resp).await)
+        }
+        None => Ok(resp),
+    }
+}
+
+/// the id52 a request is for, from its Host header: a custom domain, an alias, or the id52 itself.
+async fn peer_id_from_host(
+    host: Option<&str>,
+    bridge: &Bridge,
+    self_endpoint: &iroh::Endpoint,
+    peer_connections: &kulfi_utils::PeerStreamSenders,
+    graceful: &kulfi_utils::Graceful,
+) -> Result<String, kulfi_utils::http::ProxyResponse<eyre::Error>> {
+    let mapped = bridge
+        .domains
+        .as_ref()
+        .zip(host)
+        .and_then(|(domains, host)| Some((domains, domains.lookup(host)?)));
+    // with a proxy target, domains and aliases can only point to it
+    let not_proxy_target = |peer_id: &str| {
+        bridge
+            .proxy_target
+            .as_ref()
+            .is_some_and(|target| target != peer_id)
+    };
+    let peer_id = match mapped {
+        Some((domains, (domain, peer_id))) => {
+            if not_proxy_target(&peer_id) {
+                tracing::error!(domain, peer_id, "request for peer_id is not allowed");
+                return Err(kulfi_utils::bad_request!(
+                    "failed to get peer id from request"
+                ));
+            }
+            if !domains
+                .verify(
+                    &domain,
+                    &peer_id,
+                    self_endpoint.clone(),
+                    peer_connections.clone(),
+                    graceful.clone(),
+                )
+                .await
+            {
+                return Err(kulfi_utils::http::bytes_to_resp(
+                    format!(
+                        "the owner of this service has not verified {domain}, see {}\n",
+                        domains::WELL_KNOWN_PATH
+                    )
+                    .into_bytes(),
+                    hyper::StatusCode::MISDIRECTED_REQUEST,
+                ));
+            }
+            peer_id
+        }
+        None => {
+            let alias = bridge
+                .aliases
+                .as_ref()
+                .zip(host.and_then(|h| h.split_once('.')))
+                .and_then(|(aliases, (label, _))| aliases.resolve(label));
+            match alias {
+                Some(aliases::Resolved::Id52(peer_id)) => {
+                    if not_proxy_target(&peer_id) {
+                        tracing::error!(peer_id, "request for peer_id is not allowed");
+                        return Err(kulfi_utils::bad_request!(
+                            "failed to get peer id from request"
+                        ));
+                    }
+                    peer_id
+                }
+                Some(aliases::Resolved::Ambiguous) => {
+                    return Err(kulfi_utils::bad_request!(
+                        "this prefix matches more than one id52, use a longer one"
+                    ));
+                }
+                None => match get_peer_id52_from_host(host, bridge.proxy_target.clone()) {
+                    Ok(peer_id) => peer_id,
+                    Err(e) => {
+                        tracing::error!("failed to get peer id from request: {e:?}");
+                        return Err(kulfi_utils::bad_request!(
+                            "failed to get peer id from request"
+                        ));
+                    }
+                },
+            }
+        }
+    };
+
+    Ok(peer_id)
+}
+
+fn get_peer_id52_from_host(
+    host: Option<&str>,
+    proxy_target: Option<String>,
+) -> eyre::Result<String> {
+    let first = match host.and_then(|h| h.split_once('.')) {
+        Some((first, _)) => first,
+        None => {
+            tracing::error!("got http request without Host header");
+            return Err(eyre::anyhow!("got http request without Host header"));
+        }
+    };
+
+    if first == "127"
+        && let Some(target) = proxy_target
+    {
+        return Ok(target);
+    }
+
+    if first.len() != 52 && proxy_target.is_none() {
+        tracing::error!(peer_id = %first, "request received for invalid peer id");
+        return Err(eyre::anyhow!("got http request with invalid peer id"));
+    }
+
+    if let Some(target) = proxy_target
+        && first != target
+    {
+        tracing::error!(peer_id = %first, proxy_target = %target, "request for peer_id is not allowed");
+        return Err(eyre::anyhow!("got http request with invalid peer id"));
+    }
+
+    Ok(first.to_string())
+}
@@ -0,0 +1,350 @@
+//! Path based addressing, for networks without wildcard DNS.
+//!
+//! `https://bridge.example.com/~<id52>/some/path` is forwarded to `<id52>` as `/some/path`. the
+//! first segment can also be an alias (see `aliases.rs`).
+//!
+//! sites usually link to their own pages with absolute paths, like `/style.css`, which would miss
+//! the `/~<id52>` prefix. two optional fixes:
+//!
+//! - rewrite: `Location` headers, and `href`, `src` and `action` attributes in html responses that
+//!   start with `/`, get the prefix added.
+//! - sticky: the bridge sets a cookie with the id52, and requests without the prefix are forwarded
+//!   to the id52 in the cookie. this also covers urls built by javascript, but only works for one
+//!   site per browser at a time.
+
+pub const COOKIE_NAME: &str = "kulfi-bridge-target";
+
+/// html bodies larger than this are passed through without rewriting
+const MAX_REWRITE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Default, Clone)]
+pub struct PathRoutingConfig {
+    pub enabled: bool,
+    pub rewrite: bool,
+    pub sticky: bool,
+}
+
+/// splits `/~<label>/rest?query` into the label and `/rest?query`.
+pub fn split_prefix(path_and_query: &str) -> Option<(String, String)> {
+    let after = path_and_query.strip_prefix("/~")?;
+    let end = after.find(['/', '?']).unwrap_or(after.len());
+    let (label, rest) = after.split_at(end);
+    if label.is_empty() {
+        return None;
+    }
+    let rest = if rest.starts_with('/') {
+        rest.to_string()
+    } else {
+        format!("/{rest}")
+    };
+    Some((label.to_string(), rest))
+}
+
+/// the id52 from the sticky cookie, if any.
+pub fn sticky_target(headers: &hyper::HeaderMap) -> Option<String> {
+    headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, value)| value.to_string())
+        .filter(|id52| id52.len() == 52)
+}
+
+/// replaces the path of `r` with `rest`, which has already been stripped of the prefix.
+pub fn strip_prefix<B>(r: &mut hyper::Request<B>, rest: &str) -> eyre::Result<()> {
+    let mut parts = r.uri().clone().into_parts();
+    parts.path_and_query = Some(rest.parse()?);
+    *r.uri_mut() = hyper::Uri::from_parts(parts)?;
+    Ok(())
+}
+
+/// a `301` to `/~<label>/`, so relative links in the index page resolve under the prefix.
+pub fn redirect_to_root(label: &str) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+    let mut resp = kulfi_utils::http::bytes_to_resp(vec![], hyper::StatusCode::MOVED_PERMANENTLY);
+    resp.headers_mut().insert(
+        hyper::header::LOCATION,
+        format!("/~{label}/").parse().unwrap(),
+    );
+    resp
+}
+
+/// fixes up the response of a request that came in with the `/~<label>` prefix.
+pub async fn fix_response(
+    config: &PathRoutingConfig,
+    label: &str,
+    peer_id: &str,
+    mut resp: kulfi_utils::http::ProxyResponse<eyre::Error>,
+) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+    if config.sticky {
+        resp.headers_mut().append(
+            hyper::header::SET_COOKIE,
+            format!("{COOKIE_NAME}={peer_id}; Path=/; HttpOnly; SameSite=Lax")
+                .parse()
+                .unwrap(),
+        );
+    }
+
+    if !config.rewrite {
+        return resp;
+    }
+
+    let prefix = format!("/~{label}");
+    if let Some(location) = resp
+        .headers()
+        .get(hyper::header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|l| rewrite_location(l, &prefix, peer_id))
+        .and_then(|l| l.parse().ok())
+    {
+        resp.headers_mut().insert(hyper::header::LOCATION, location);
+    }
+
+    rewrite_html(resp, &prefix).await
+}
+
+/// `/path` and `http(s)://<id52>.<anything>/path` become `<prefix>/path`.
+fn rewrite_location(location: &str, prefix: &str, peer_id: &str) -> Option<String> {
+    if location.starts_with('/') && !location.starts_with("//") {
+        return Some(format!("{prefix}{location}"));
+    }
+
+    let after_scheme = location
+        .strip_prefix("https://")
+        .or_else(|| location.strip_prefix("http://"))?;
+    let (host, path) = match after_scheme.find('/') {
+        Some(i) => after_scheme.split_at(i),
+        None => (after_scheme, "/"),
+    };
+    let first = host.split('.').next()?;
+    if first != peer_id {
+        return None;
+    }
+    Some(format!("{prefix}{path}"))
+}
+
+async fn rewrite_html(
+    resp: kulfi_utils::http::ProxyResponse<eyre::Error>,
+    prefix: &str,
+) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+    use http_body_util::BodyExt;
+
+    let content_type = resp
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    // other charsets would be mangled by rewriting them as utf-8
+    let is_html = content_type.is_some_and(|v| v.starts_with("text/html") && is_utf8_charset(v));
+    // we do not decompress, compressed html is passed through as is
+    let is_encoded = resp.headers().contains_key(hyper::header::CONTENT_ENCODING);
+    let too_big = resp
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .is_none_or(|len| len > MAX_REWRITE_SIZE);
+    if !is_html || is_encoded || too_big {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            tracing::error!("failed to read html body for rewriting: {e:?}");
+            return kulfi_utils::server_error!("failed to read response from peer");
+        }
+    };
+
+    // no charset can also mean a `<meta charset>` that is not utf-8
+    let html = match std::str::from_utf8(&body) {
+        Ok(html) => rewrite_links(html, prefix),
+        Err(_) => {
+            return hyper::Response::from_parts(
+                parts,
+                http_body_util::Full::new(body)
+                    .map_err(|e| match e {})
+                    .boxed(),
+            );
+        }
+    };
+    parts.headers.insert(
+        hyper::header::CONTENT_LENGTH,
+        html.len().to_string().parse().unwrap(),
+    );
+    hyper::Response::from_parts(
+        parts,
+        http_body_util::Full::new(hyper::body::Bytes::from(html))
+            .map_err(|e| match e {})
+            .boxed(),
+    )
+}
+
+/// `true` if the content type has no charset, or says it is utf-8.
+fn is_utf8_charset(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .is_none_or(|(_, value)| {
+            let value = value.trim().trim_matches('"');
+            value.eq_ignore_ascii_case("utf-8") || value.eq_ignore_ascii_case("utf8")
+        })
+}
+
+/// prefixes `href="/..."`, `src="/..."` and `action="/..."`, with either quote style. protocol
+/// relative urls (`//host/...`) are left alone.
+fn rewrite_links(html: &str, prefix: &str) -> String {
+    const NEEDLES: [&str; 6] = [
+        "href=\"/",
+        "href='/",
+        "src=\"/",
+        "src='/",
+        "action=\"/",
+        "action='/",
+    ];
+
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    'outer: while let Some(c) = rest.chars().next() {
+        for needle in NEEDLES {
+            if let Some(head) = rest.get(..needle.len())
+                && head.eq_ignore_ascii_case(needle)
+                && !rest[needle.len()..].starts_with('/')
+            {
+                out.push_str(&head[..needle.len() - 1]);
+                out.push_str(prefix);
+                out.push('/');
+                rest = &rest[needle.len()..];
+                continue 'outer;
+            }
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    const ID: &str = "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60";
+
+    #[test]
+    fn test_split_prefix() {
+        assert_eq!(
+            super::split_prefix(&format!("/~{ID}/a/b?c=d")),
+            Some((ID.to_string(), "/a/b?c=d".to_string()))
+        );
+        assert_eq!(
+            super::split_prefix("/~docs?x=1"),
+            Some(("docs".to_string(), "/?x=1".to_string()))
+        );
+        assert_eq!(
+            super::split_prefix("/~docs"),
+            Some(("docs".to_string(), "/".to_string()))
+        );
+        assert_eq!(super::split_prefix("/~/a"), None);
+        assert_eq!(super::split_prefix("/a/~b"), None);
+    }
+
+    #[test]
+    fn test_sticky_target() {
+        let mut headers = hyper::HeaderMap::new();
+        assert_eq!(super::sticky_target(&headers), None);
+        headers.insert(
+            hyper::header::COOKIE,
+            format!("a=b; kulfi-bridge-target={ID}; c=d")
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(super::sticky_target(&headers), Some(ID.to_string()));
+    }
+
+    #[test]
+    fn test_rewrite_location() {
+        let prefix = format!("/~{ID}");
+        assert_eq!(
+            super::rewrite_location("/login?next=/", &prefix, ID),
+            Some(format!("{prefix}/login?next=/"))
+        );
+        assert_eq!(
+            super::rewrite_location(&format!("https://{ID}.bridge.example.com/a"), &prefix, ID),
+            Some(format!("{prefix}/a"))
+        );
+        assert_eq!(
+            super::rewrite_location(&format!("http://{ID}.localhost:8080"), &prefix, ID),
+            Some(format!("{prefix}/"))
+        );
+        assert_eq!(
+            super::rewrite_location("https://example.com/a", &prefix, ID),
+            None
+        );
+        assert_eq!(
+            super::rewrite_location("//cdn.example.com/a", &prefix, ID),
+            None
+        );
+        assert_eq!(super::rewrite_location("relative", &prefix, ID), None);
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        assert_eq!(
+            super::rewrite_links(
+                r#"<a href="/about">à</a><img SRC='/logo.png'><script src="//cdn/x.js"></script><form action="/post"><a href="https://x">"#,
+                "/~docs"
+            ),
+            r#"<a href="/~docs/about">à</a><img SRC='/~docs/logo.png'><script src="//cdn/x.js"></script><form action="/~docs/post"><a href="https://x">"#
+        );
+    }
+
+    fn html(content_type: &str, body: &[u8]) -> kulfi_utils::http::ProxyResponse<eyre::Error> {
+        use http_body_util::BodyExt;
+
+        hyper::Response::builder()
+            .header(hyper::header::CONTENT_TYPE, content_type)
+            .header(hyper::header::CONTENT_LENGTH, body.len())
+            .body(
+                http_body_util::Full::new(hyper::body::Bytes::copy_from_slice(body))
+                    .map_err(|e| match e {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+
+    async fn rewritten(resp: kulfi_utils::http::ProxyResponse<eyre::Error>) -> Vec<u8> {
+        use http_body_util::BodyExt;
+
+        let resp = super::rewrite_html(resp, "/~docs").await;
+        resp.into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_html_only_utf8() {
+        assert_eq!(
+            rewritten(html("text/html", "<a href=\"/à\">".as_bytes())).await,
+            "<a href=\"/~docs/à\">".as_bytes()
+        );
+        assert_eq!(
+            rewritten(html("text/html; Charset=\"UTF-8\"", b"<a href=\"/\">")).await,
+            b"<a href=\"/~docs/\">"
+        );
+
+        // `à` in latin-1, passed through as is instead of turning into U+FFFD
+        let latin1 = b"<a href=\"/\">\xe0</a>";
+        assert_eq!(
+            rewritten(html("text/html; charset=iso-8859-1", latin1)).await,
+            latin1
+        );
+        assert_eq!(rewritten(html("text/html", latin1)).await, latin1);
+    }
+}
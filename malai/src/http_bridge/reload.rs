@@ -0,0 +1,104 @@
+//! Config that the bridge keeps up to date while running, e.g., the allowlist of id52s.
+//!
+//! a source is either a local file or an http(s) url. we do not use filesystem notifications, we
+//! poll: files are re-read when their modification time changes, urls are re-fetched every
+//! interval. the callback is called with the new content only when the content actually changed.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    File(PathBuf),
+    Url(String),
+}
+
+impl Source {
+    pub fn parse(s: &str) -> Self {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Source::Url(s.to_string())
+        } else {
+            Source::File(PathBuf::from(s))
+        }
+    }
+
+    pub async fn load(&self) -> eyre::Result<String> {
+        use eyre::WrapErr;
+
+        match self {
+            Source::File(path) => tokio::fs::read_to_string(path)
+                .await
+                .wrap_err_with(|| format!("failed to read {path:?}")),
+            Source::Url(url) => {
+                let resp = reqwest::get(url)
+                    .await
+                    .wrap_err_with(|| format!("failed to fetch {url}"))?
+                    .error_for_status()
+                    .wrap_err_with(|| format!("failed to fetch {url}"))?;
+                resp.text()
+                    .await
+                    .wrap_err_with(|| format!("failed to read body of {url}"))
+            }
+        }
+    }
+
+    async fn modified(&self) -> Option<std::time::SystemTime> {
+        match self {
+            Source::File(path) => tokio::fs::metadata(path).await.ok()?.modified().ok(),
+            Source::Url(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// loads `source` once, calls `on_change`, and spawns a task that keeps calling it whenever the
+/// content changes. the initial load failing is an error, later failures are only logged so a
+/// flaky registry does not take the bridge down.
+pub async fn watch(
+    source: Source,
+    interval: Duration,
+    graceful: kulfi_utils::Graceful,
+    on_change: impl Fn(&str) + Send + 'static,
+) -> eyre::Result<()> {
+    let mut content = source.load().await?;
+    let mut modified = source.modified().await;
+    on_change(&content);
+
+    let graceful_for_watch = graceful.clone();
+    graceful.spawn(async move {
+        loop {
+            tokio::select! {
+                _ = graceful_for_watch.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+
+            let new_modified = source.modified().await;
+            if new_modified.is_some() && new_modified == modified {
+                continue;
+            }
+
+            match source.load().await {
+                Ok(new_content) if new_content != content => {
+                    tracing::info!(%source, "reloading");
+                    on_change(&new_content);
+                    content = new_content;
+                    modified = new_modified;
+                }
+                Ok(_) => modified = new_modified,
+                Err(e) => tracing::error!(%source, "failed to reload: {e:?}"),
+            }
+        }
+    });
+
+    Ok(())
+}
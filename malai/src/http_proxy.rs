@@ -3,55 +3,76 @@ pub async fn http_proxy(
     remote: String,
     graceful: kulfi_utils::Graceful,
     post_start: impl FnOnce(u16) -> eyre::Result<()>,
-) {
+) -> eyre::Result<()> {
+    let handle = start_http_proxy(HttpProxyConfig { port, remote }, graceful.clone()).await?;
+    kulfi_utils::systemd::notify_ready();
+    // start_http_proxy() always binds a local address
+    let port = handle.local_addr().map(|a| a.port()).unwrap_or_default();
+
+    if let Err(e) = post_start(port) {
+        eprintln!("Failed to run post start function: {e:?}");
+    }
+
+    println!("Listening on http://127.0.0.1:{port}");
+    handle
+        .wait_showing_info(graceful, || {
+            println!("Listening on http://127.0.0.1:{port}");
+            println!("Press ctrl+c again to exit.");
+        })
+        .await
+}
+
+/// the http proxy to start with [`start_http_proxy`].
+pub struct HttpProxyConfig {
+    /// the local port to listen on, 0 picks a free one
+    pub port: u16,
+    /// the id52 of the peer running `malai http-proxy-remote`
+    pub remote: String,
+}
+
+/// listens on the local port, and proxies the requests through the remote peer, without printing
+/// anything.
+pub async fn start_http_proxy(
+    config: HttpProxyConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
     use eyre::WrapErr;
 
-    let listener = match tokio::net::TcpListener::bind(format!("127.0.0.1:{port}"))
+    let port = config.port;
+    let listener = tokio::net::TcpListener::bind(format!("127.0.0.1:{port}"))
         .await
         .wrap_err_with(|| {
             format!("can not listen on port {port}, is it busy, or you do not have root access?")
-        }) {
-        Ok(l) => l,
-        Err(e) => {
-            eprintln!("Failed to bind to port {port}: {e:?}");
-            std::process::exit(1);
-        }
-    };
+        })?;
+    let local_addr = listener
+        .local_addr()
+        .wrap_err("failed to get local address")?;
 
-    let port = match listener.local_addr() {
-        Ok(addr) => addr.port(),
-        Err(e) => {
-            eprintln!("Failed to get local address: {e:?}");
-            std::process::exit(1);
-        }
-    };
-
-    if let Err(e) = post_start(port) {
-        eprintln!("Failed to run post start function: {e:?}");
-    }
+    let graceful = graceful.child();
+    let task = graceful.spawn(serve(listener, config.remote, graceful.clone()));
+    Ok(malai::ServiceHandle::new(
+        None,
+        Some(local_addr),
+        graceful,
+        task,
+    ))
+}
 
-    println!("Listening on http://127.0.0.1:{port}");
+async fn serve(
+    listener: tokio::net::TcpListener,
+    remote: String,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    use eyre::WrapErr;
 
     let peer_connections = kulfi_utils::PeerStreamSenders::default();
 
-    let mut graceful_mut = graceful.clone();
     loop {
         tokio::select! {
             () = graceful.cancelled() => {
                 tracing::info!("Stopping control server.");
                 break;
             }
-            r = graceful_mut.show_info() => {
-                match r {
-                    Ok(_) => {
-                        println!("Listening on http://127.0.0.1:{port}");
-                        println!("Press ctrl+c again to exit.");
-                    }
-                    Err(e) => {
-                        tracing::error!("failed to show info: {e:?}");
-                    }
-                }
-            }
             r = listener.accept() => {
                 match r {
                     Ok((stream, _addr)) => {
@@ -73,12 +94,14 @@ pub async fn http_proxy(
                     }
                     Err(e) => {
                         tracing::error!("failed to accept: {e:?}");
-                        break;
+                        return Err(e).wrap_err("failed to accept");
                     }
                 }
             }
         }
     }
+
+    Ok(())
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -185,6 +208,7 @@ async fn handle_request(
                 extra: Some(serde_json::to_string(&ProxyData::Http {
                     addr: host.to_string(),
                 })?),
+                token: None,
             },
             r,
             self_endpoint,
@@ -227,6 +251,7 @@ async fn handle_upgrade(
             extra: Some(serde_json::to_string(&ProxyData::Connect {
                 addr: host.to_string(),
             })?),
+            token: None,
         },
         remote.to_string(),
         peer_connections.clone(),
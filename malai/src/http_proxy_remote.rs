@@ -1,29 +1,47 @@
-pub async fn http_proxy_remote(graceful: kulfi_utils::Graceful) {
-    let (id52, secret_key) = match kulfi_utils::read_or_create_key().await {
-        Ok(v) => v,
-        Err(e) => {
-            malai::identity_read_err_msg(e);
-            std::process::exit(1);
-        }
-    };
-    let ep = match kulfi_utils::get_endpoint(secret_key).await {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Failed to bind to iroh network: {e:?}");
-            std::process::exit(1);
-        }
-    };
+pub async fn http_proxy_remote(
+    id52: String,
+    secret_key: kulfi_id52::SecretKey,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    let handle =
+        start_http_proxy_remote(HttpProxyRemoteConfig { secret_key }, graceful.clone()).await?;
+    kulfi_utils::systemd::notify_ready();
 
-    let http_connection_pools = kulfi_utils::HttpConnectionPools::default();
     InfoMode::Startup.print(&id52);
+    handle
+        .wait_showing_info(graceful, || InfoMode::OnExit.print(&id52))
+        .await
+}
 
-    let mut graceful_mut = graceful.clone();
+/// the http proxy remote to start with [`start_http_proxy_remote`].
+pub struct HttpProxyRemoteConfig {
+    /// the identity to run as, `malai http-proxy` connects to its id52
+    pub secret_key: kulfi_id52::SecretKey,
+}
+
+/// lets peers use this machine as an http proxy over the kulfi network, without printing
+/// anything.
+pub async fn start_http_proxy_remote(
+    config: HttpProxyRemoteConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
+    use eyre::WrapErr;
+
+    let id52 = config.secret_key.id52();
+    let ep = kulfi_utils::get_endpoint(config.secret_key)
+        .await
+        .wrap_err("failed to bind to iroh network")?;
+
+    let graceful = graceful.child();
+    let task = graceful.spawn(serve(ep, graceful.clone()));
+    Ok(malai::ServiceHandle::new(Some(id52), None, graceful, task))
+}
+
+async fn serve(ep: iroh::Endpoint, graceful: kulfi_utils::Graceful) -> eyre::Result<()> {
+    let http_connection_pools = kulfi_utils::HttpConnectionPools::default();
 
     loop {
         tokio::select! {
-            _ = graceful_mut.show_info() => {
-                InfoMode::OnExit.print(&id52);
-            }
             _ = graceful.cancelled() => {
                 tracing::info!("Stopping http-proxy server.");
                 break;
@@ -39,15 +57,25 @@ pub async fn http_proxy_remote(graceful: kulfi_utils::Graceful) {
 
                 let graceful_for_handle_connection = graceful.clone();
                 let http_connection_pools = http_connection_pools.clone();
+                let ep = ep.clone();
                 graceful.spawn(async move {
                     let start = std::time::Instant::now();
                     let conn = match conn.await {
                         Ok(c) => c,
                         Err(e) => {
                             tracing::error!("failed to convert incoming to connection: {e:?}");
+                            kulfi_utils::metrics::inc(
+                                kulfi_utils::metrics::CONNECTION_ERRORS,
+                                &[("stage", "accept")],
+                            );
                             return;
                         }
                     };
+                    let _conn_type_watcher = kulfi_utils::watch_conn_type(
+                        &ep,
+                        conn.remote_id(),
+                        kulfi_utils::get_remote_id52(&conn),
+                    );
                     if let Err(e) = handle_connection(conn, http_connection_pools, graceful_for_handle_connection).await {
                         tracing::error!("connection error3: {e:?}");
                     }
@@ -58,6 +86,7 @@ pub async fn http_proxy_remote(graceful: kulfi_utils::Graceful) {
     }
 
     ep.close().await;
+    Ok(())
 }
 
 async fn handle_connection(
@@ -66,6 +95,10 @@ async fn handle_connection(
     graceful: kulfi_utils::Graceful,
 ) -> eyre::Result<()> {
     let remote_id52 = kulfi_utils::get_remote_id52(&conn);
+    let _connection_gauge = kulfi_utils::metrics::track(
+        kulfi_utils::metrics::PEER_CONNECTIONS,
+        &[("peer", &remote_id52), ("direction", "incoming")],
+    );
 
     tracing::info!("new client: {remote_id52}, waiting for bidirectional stream");
     loop {
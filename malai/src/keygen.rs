@@ -1,44 +1,148 @@
-pub fn keygen(filename: Option<String>) {
-    use std::io::Write;
+pub fn keygen(
+    filename: Option<String>,
+    encrypt: bool,
+    prefix: Option<kulfi_id52::VanityPrefix>,
+) -> eyre::Result<()> {
+    let passphrase = if encrypt {
+        Some(kulfi_utils::secret::passphrase(true)?)
+    } else {
+        None
+    };
+
+    let (id52, secret_key) = match prefix {
+        Some(prefix) => {
+            let secret_key = search_prefix(&prefix);
+            (secret_key.id52(), secret_key)
+        }
+        None => kulfi_utils::generate_secret_key()
+            .map_err(|e| eyre::anyhow!("failed to generate secret key: {e}"))?,
+    };
 
-    let (id52, secret_key) = match kulfi_utils::generate_secret_key() {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Failed to generate secret key: {e}");
-            std::process::exit(1);
+    let filename = match filename {
+        Some(filename) => filename,
+        None => {
+            eprintln!("Generated Public Key (ID52): {id52}");
+            match passphrase {
+                Some(passphrase) => println!("{}", secret_key.encrypt(&passphrase)),
+                // Use Display implementation which outputs hex
+                None => println!("{secret_key}"),
+            }
+            return Ok(());
         }
     };
 
+    save_key_file(
+        std::path::Path::new(&filename),
+        &secret_key,
+        passphrase.as_deref(),
+    )?;
+    crate::identity::register_key_file(&id52, std::path::Path::new(&filename));
     eprintln!("Generated Public Key (ID52): {id52}");
+    eprintln!("Private key saved to `{filename}`.");
+    Ok(())
+}
 
-    match filename {
-        Some(ref filename) => {
-            if std::path::Path::new(filename).exists() {
-                eprintln!("File `{filename}` already exists. Please choose a different file name.");
-                std::process::exit(1);
-            }
-            let mut file = match std::fs::File::create(filename) {
-                Ok(f) => f,
-                Err(e) => {
-                    eprintln!("Failed to create file `{filename}`: {e}");
-                    std::process::exit(1);
-                }
-            };
-
-            // Use Display implementation which outputs hex
-            match writeln!(file, "{}", secret_key) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Failed to write secret key to file `{filename}`: {e}");
-                    std::process::exit(1);
-                }
-            }
+/// `SecretKey::generate_with_prefix()`, showing how far along the search is on stderr.
+fn search_prefix(prefix: &kulfi_id52::VanityPrefix) -> kulfi_id52::SecretKey {
+    use std::io::IsTerminal;
 
-            eprintln!("Private key saved to `{filename}`.");
-        }
-        None => {
-            // Use Display implementation which outputs hex
-            println!("{}", secret_key);
+    let expected = prefix.expected_attempts();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    eprintln!(
+        "Searching for an ID52 starting with `{prefix}` on {threads} threads, it takes {expected:.0} attempts on average."
+    );
+
+    let started = std::time::Instant::now();
+    let interactive = std::io::stderr().is_terminal();
+    let secret_key = kulfi_id52::SecretKey::generate_with_prefix(prefix, |attempts| {
+        let elapsed = started.elapsed().as_secs_f64();
+        let rate = attempts as f64 / elapsed;
+        let eta = (expected - attempts as f64).max(0.0) / rate;
+        let status = format!(
+            "tried {attempts} keys ({:.0}% of the average) at {rate:.0}/s, ~{eta:.0}s to go on average",
+            100.0 * attempts as f64 / expected
+        );
+        if interactive {
+            eprint!("\r\x1b[2K{status}");
+        } else {
+            tracing::info!("{status}");
         }
+    });
+    if interactive && started.elapsed() >= std::time::Duration::from_secs(1) {
+        eprintln!();
+    }
+    tracing::info!("found after {:?}", started.elapsed());
+    secret_key
+}
+
+/// generates a new identity and saves its secret key to `path`, which must not exist yet, in the
+/// format `malai keygen --file` uses, encrypted if a passphrase is given.
+pub fn write_key_file(
+    path: &std::path::Path,
+    passphrase: Option<&str>,
+) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
+    let (id52, secret_key) = kulfi_utils::generate_secret_key()
+        .map_err(|e| eyre::anyhow!("failed to generate secret key: {e}"))?;
+    save_key_file(path, &secret_key, passphrase)?;
+    Ok((id52, secret_key))
+}
+
+/// saves an existing key, e.g., an imported one, like `write_key_file()` does.
+pub(crate) fn save_key_file(
+    path: &std::path::Path,
+    secret_key: &kulfi_id52::SecretKey,
+    passphrase: Option<&str>,
+) -> eyre::Result<()> {
+    use eyre::WrapErr;
+    use std::io::Write;
+
+    // create_new, so an existing key is never overwritten, even if it shows up in the meantime
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .wrap_err_with(|| {
+            format!(
+                "failed to create `{}`, if it already exists, please choose a different file name",
+                path.display()
+            )
+        })?;
+
+    let content = match passphrase {
+        Some(passphrase) => secret_key.encrypt(passphrase),
+        // Use Display implementation which outputs hex
+        None => secret_key.to_string(),
+    };
+    writeln!(file, "{content}")
+        .wrap_err_with(|| format!("failed to write secret key to `{}`", path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn write_key_file() {
+        let path = std::env::temp_dir().join(format!("malai-keygen-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let (id52, _) = super::write_key_file(&path, None).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let (read_id52, _) = kulfi_utils::secret::handle_secret(content.trim()).unwrap();
+        assert_eq!(read_id52, id52);
+
+        // never overwrites an existing key
+        assert!(super::write_key_file(&path, None).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+
+        std::fs::remove_file(&path).unwrap();
+
+        let (id52, _) = super::write_key_file(&path, Some("hunter2")).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(kulfi_id52::SecretKey::is_encrypted(&content));
+        let secret_key = kulfi_id52::SecretKey::decrypt(&content, "hunter2").unwrap();
+        assert_eq!(secret_key.id52(), id52);
+
+        std::fs::remove_file(&path).unwrap();
     }
 }
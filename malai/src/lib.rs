@@ -4,16 +4,17 @@
 
 extern crate self as malai;
 
-use clap as _;
 use clap_verbosity_flag as _;
 use tracing_subscriber as _;
 
 mod browse;
+mod control;
 mod expose_http;
 mod expose_tcp;
 mod expose_tcp_udp;
 mod expose_udp;
 mod folder;
+mod handle;
 mod http_bridge;
 mod http_proxy;
 mod http_proxy_remote;
@@ -21,22 +22,35 @@ mod identity;
 mod keygen;
 mod run;
 mod tcp_bridge;
+mod token;
 mod udp_bridge;
 
 pub use browse::browse;
-pub use expose_http::expose_http;
-pub use expose_tcp::expose_tcp;
-pub use expose_tcp_udp::expose_tcp_udp;
-pub use expose_udp::expose_udp;
-pub use folder::folder;
-pub use http_bridge::http_bridge;
-pub use http_proxy::{ProxyData, http_proxy};
-pub use http_proxy_remote::http_proxy_remote;
-pub use identity::{create_identity, delete_identity};
-pub use keygen::keygen;
-pub use run::run;
-pub use tcp_bridge::tcp_bridge;
-pub use udp_bridge::udp_bridge;
+pub use control::{CtlRequest, ctl};
+pub(crate) use expose_http::InfoMode;
+pub use expose_http::{ExposeHttpConfig, expose_http, start_expose_http};
+pub use expose_tcp::{expose_tcp, start_expose_tcp};
+pub use expose_tcp_udp::{expose_tcp_udp, start_expose_tcp_udp};
+pub use expose_udp::{expose_udp, start_expose_udp};
+pub use folder::{FolderConfig, folder, start_folder};
+pub use handle::{BridgeConfig, ExposeConfig, ServiceHandle};
+pub use http_bridge::{
+    AliasesConfig, AllowlistConfig, AuthConfig, CacheBackend, CacheConfig,
+    DEFAULT_ALIAS_MIN_PREFIX, DomainsConfig, HttpBridgeConfig, HttpBridgeOptions, LimitsConfig,
+    PathRoutingConfig, domain_token, hash_password, http_bridge, start_http_bridge,
+};
+pub use http_proxy::{HttpProxyConfig, ProxyData, http_proxy, start_http_proxy};
+pub use http_proxy_remote::{HttpProxyRemoteConfig, http_proxy_remote, start_http_proxy_remote};
+pub use identity::{
+    KeyFormat, backup_identity, create_identity, default_identity, delete_identity,
+    export_identity, fetch_rotation, follow_rotation, import_identity, list_identities,
+    rename_identity, restore_identity, rotate_identity, show_identity,
+};
+pub use keygen::{keygen, write_key_file};
+pub use run::{run, validate};
+pub use tcp_bridge::{start_tcp_bridge, tcp_bridge};
+pub use token::{TokenProtocol, create_token, show_token};
+pub use udp_bridge::{start_udp_bridge, udp_bridge};
 
 pub fn public_check(public: bool, service: &str, cmd: &str) -> bool {
     use colored::Colorize;
@@ -49,7 +63,10 @@ pub fn public_check(public: bool, service: &str, cmd: &str) -> bool {
                     to the public without your knowledge."
         );
         eprintln!("Instead, run: {}", cmd.yellow());
-        eprintln!("In future, we will add a way to add access control.");
+        eprintln!(
+            "Or pass --require-token to only let in peers you give an access token with \
+             `malai token create`."
+        );
     }
 
     public
@@ -7,7 +7,9 @@ async fn main() -> eyre::Result<()> {
     use clap::Parser;
 
     let cli = Cli::parse();
-    let graceful = kulfi_utils::Graceful::default();
+    let graceful = kulfi_utils::Graceful::default()
+        .with_drain_timeout(std::time::Duration::from_secs(cli.shutdown_timeout))
+        .with_shutdown_mode(cli.shutdown_mode);
     if let Some(Command::Run { home }) = cli.command {
         let home = match &home {
             Some(home) => Path::new(home),
@@ -22,15 +24,36 @@ async fn main() -> eyre::Result<()> {
             eprintln!("Unable to find malai.toml in {}", conf_file.display());
             return Ok(());
         }
+        // held till malai exits, so a second `malai run` can not use the same kulfi folder
+        let _data_dir = match init_data_dir().await {
+            Ok(data_dir) => data_dir,
+            Err(e) => {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+        };
+        if let Some(addr) = cli.metrics_addr {
+            kulfi_utils::metrics::serve(addr, graceful.clone()).await?;
+        }
         malai::run(conf_file, graceful.clone()).await;
+        kulfi_utils::systemd::notify_ready();
+        kulfi_utils::systemd::watchdog(graceful.clone());
         graceful.shutdown().await
     } else {
         // run with RUST_LOG="malai=trace,kulfi_utils=trace" to see logs
         tracing_subscriber::fmt::init();
+        if let Some(addr) = cli.metrics_addr {
+            kulfi_utils::metrics::serve(addr, graceful.clone()).await?;
+        }
         match_cli(cli, graceful.clone()).await
     }
 }
 
+async fn init_data_dir() -> eyre::Result<kulfi_utils::dot_kulfi::DataDir> {
+    let dir = kulfi_utils::dot_kulfi::dir()?;
+    kulfi_utils::dot_kulfi::init_if_required(&dir, kulfi_utils::dot_kulfi::MALAI_LOCK).await
+}
+
 async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
     match cli.command {
         Some(Command::Http {
@@ -38,11 +61,12 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
             host,
             bridge,
             public,
+            require_token,
             // secure,
             // what_to_do,
         }) => {
             if !malai::public_check(
-                public,
+                public || require_token,
                 "HTTP service",
                 &format!("malai http {port} --public"),
             ) {
@@ -59,27 +83,49 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
                         std::process::exit(1);
                     }
                 };
-                malai::expose_http(
-                    host,
-                    port,
-                    bridge.unwrap_or_default(),
-                    id52,
-                    secret_key,
-                    graceful_for_export_http,
-                )
-                .await
+                exit_on_error(
+                    malai::expose_http(
+                        host,
+                        port,
+                        bridge.unwrap_or_default(),
+                        id52,
+                        secret_key,
+                        require_token,
+                        graceful_for_export_http,
+                    )
+                    .await,
+                );
             });
         }
-        Some(Command::HttpBridge { proxy_target, port }) => {
+        Some(Command::HttpBridge {
+            proxy_target,
+            port,
+            options,
+        }) => {
             tracing::info!(port, proxy_target, verbose = ?cli.verbose, "Starting HTTP bridge.");
+            let config = options.config();
             let graceful_for_http_bridge = graceful.clone();
             graceful.spawn(async move {
-                malai::http_bridge(port, proxy_target, graceful_for_http_bridge, |_| Ok(())).await
+                exit_on_error(
+                    malai::http_bridge(
+                        port,
+                        proxy_target,
+                        config,
+                        graceful_for_http_bridge,
+                        |_| Ok(()),
+                    )
+                    .await,
+                );
             });
         }
-        Some(Command::Tcp { port, host, public }) => {
+        Some(Command::Tcp {
+            port,
+            host,
+            public,
+            require_token,
+        }) => {
             if !malai::public_check(
-                public,
+                public || require_token,
                 "HTTP service",
                 &format!("malai http {port} --public"),
             ) {
@@ -96,18 +142,43 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
                         std::process::exit(1);
                     }
                 };
-                malai::expose_tcp(host, port, id52, secret_key, graceful_for_expose_tcp).await;
+                exit_on_error(
+                    malai::expose_tcp(
+                        host,
+                        port,
+                        id52,
+                        secret_key,
+                        require_token,
+                        graceful_for_expose_tcp,
+                    )
+                    .await,
+                );
             });
         }
-        Some(Command::TcpBridge { proxy_target, port }) => {
+        Some(Command::TcpBridge {
+            proxy_target,
+            port,
+            token,
+        }) => {
             tracing::info!(port, proxy_target, verbose = ?cli.verbose, "Starting TCP bridge.");
             let graceful_for_tcp_bridge = graceful.clone();
             graceful.spawn(async move {
-                malai::tcp_bridge(port, proxy_target, graceful_for_tcp_bridge).await
+                exit_on_error(
+                    malai::tcp_bridge(port, proxy_target, token, graceful_for_tcp_bridge).await,
+                );
             });
         }
-        Some(Command::Udp { port, host, public }) => {
-            if !malai::public_check(public, "UDP service", &format!("malai udp {port} --public")) {
+        Some(Command::Udp {
+            port,
+            host,
+            public,
+            require_token,
+        }) => {
+            if !malai::public_check(
+                public || require_token,
+                "UDP service",
+                &format!("malai udp {port} --public"),
+            ) {
                 return Ok(());
             }
 
@@ -121,19 +192,40 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
                         std::process::exit(1);
                     }
                 };
-                malai::expose_udp(host, port, id52, secret_key, graceful_for_expose_udp).await;
+                exit_on_error(
+                    malai::expose_udp(
+                        host,
+                        port,
+                        id52,
+                        secret_key,
+                        require_token,
+                        graceful_for_expose_udp,
+                    )
+                    .await,
+                );
             });
         }
-        Some(Command::UdpBridge { proxy_target, port }) => {
+        Some(Command::UdpBridge {
+            proxy_target,
+            port,
+            token,
+        }) => {
             tracing::info!(port, proxy_target, verbose = ?cli.verbose, "Starting UDP bridge.");
             let graceful_for_udp_bridge = graceful.clone();
             graceful.spawn(async move {
-                malai::udp_bridge(port, proxy_target, graceful_for_udp_bridge).await
+                exit_on_error(
+                    malai::udp_bridge(port, proxy_target, token, graceful_for_udp_bridge).await,
+                );
             });
         }
-        Some(Command::TcpUdp { port, host, public }) => {
+        Some(Command::TcpUdp {
+            port,
+            host,
+            public,
+            require_token,
+        }) => {
             if !malai::public_check(
-                public,
+                public || require_token,
                 "TCP+UDP service",
                 &format!("malai tcp-udp {port} --public"),
             ) {
@@ -150,13 +242,23 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
                         std::process::exit(1);
                     }
                 };
-                malai::expose_tcp_udp(host, port, id52, secret_key, graceful_for_expose).await;
+                exit_on_error(
+                    malai::expose_tcp_udp(
+                        host,
+                        port,
+                        id52,
+                        secret_key,
+                        require_token,
+                        graceful_for_expose,
+                    )
+                    .await,
+                );
             });
         }
-        Some(Command::Browse { url }) => {
+        Some(Command::Browse { url, token }) => {
             tracing::info!(url, verbose = ?cli.verbose, "Opening browser.");
             let graceful_for_browse = graceful.clone();
-            graceful.spawn(async move { malai::browse(url, graceful_for_browse).await });
+            graceful.spawn(async move { malai::browse(url, token, graceful_for_browse).await });
         }
         Some(Command::Folder {
             path,
@@ -170,7 +272,23 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
             tracing::info!(path, verbose = ?cli.verbose, "Exposing folder to kulfi network.");
             let graceful_for_folder = graceful.clone();
             graceful.spawn(async move {
-                malai::folder(path, bridge.unwrap_or_default(), graceful_for_folder).await
+                let (id52, secret_key) = match kulfi_utils::read_or_create_key().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        malai::identity_read_err_msg(e);
+                        std::process::exit(1);
+                    }
+                };
+                exit_on_error(
+                    malai::folder(
+                        path,
+                        bridge.unwrap_or_default(),
+                        id52,
+                        secret_key,
+                        graceful_for_folder,
+                    )
+                    .await,
+                );
             });
         }
         Some(Command::Run { home: _ }) => {
@@ -187,24 +305,127 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
             }
             tracing::info!(verbose = ?cli.verbose, "Running HTTP Proxy Remote.");
             let graceful_for_run = graceful.clone();
-            graceful.spawn(async move { malai::http_proxy_remote(graceful_for_run).await });
+            graceful.spawn(async move {
+                let (id52, secret_key) = match kulfi_utils::read_or_create_key().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        malai::identity_read_err_msg(e);
+                        std::process::exit(1);
+                    }
+                };
+                exit_on_error(malai::http_proxy_remote(id52, secret_key, graceful_for_run).await);
+            });
         }
         Some(Command::HttpProxy { remote, port }) => {
             tracing::info!(port, remote, verbose = ?cli.verbose, "Starting HTTP Proxy.");
             let graceful_for_tcp_bridge = graceful.clone();
             graceful.spawn(async move {
-                malai::http_proxy(port, remote, graceful_for_tcp_bridge, |_| Ok(())).await
+                exit_on_error(
+                    malai::http_proxy(port, remote, graceful_for_tcp_bridge, |_| Ok(())).await,
+                );
             });
         }
-        Some(Command::Keygen { file }) => {
+        Some(Command::Keygen {
+            file,
+            encrypt,
+            prefix,
+        }) => {
             tracing::info!(verbose = ?cli.verbose, "Generating new identity.");
-            malai::keygen(file);
+            exit_on_error(malai::keygen(file, encrypt, prefix));
+            return Ok(());
+        }
+        Some(Command::DomainToken { domain, identity }) => {
+            let key = match identity {
+                Some(id52) => kulfi_utils::secret::handle_identity(id52),
+                // a token for an identity nothing runs as is of no use
+                None => kulfi_utils::read_key().await,
+            };
+            let (id52, secret_key) = match key {
+                Ok(v) => v,
+                Err(e) => {
+                    malai::identity_read_err_msg(e);
+                    std::process::exit(1);
+                }
+            };
+            eprintln!("Token for {domain} -> {id52}.");
+            eprintln!(
+                "Serve it from your service at /.well-known/kulfi-domain-verification, or add it as a TXT record on _kulfi.{domain}:"
+            );
+            println!("{}", malai::domain_token(&secret_key, &domain));
+            return Ok(());
+        }
+        Some(Command::HashPassword) => {
+            if let Err(e) = malai::hash_password() {
+                tracing::error!(error = ?e, "Error hashing password.");
+            }
+            return Ok(());
+        }
+        Some(Command::Validate { path }) => {
+            let path = match &path {
+                Some(path) => Path::new(path).to_path_buf(),
+                None => std::env::current_dir()?,
+            };
+            let conf_file = if path.is_dir() {
+                path.join("malai.toml")
+            } else {
+                path
+            };
+            if !malai::validate(&conf_file) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Ctl { home, cmd }) => {
+            let home = match &home {
+                Some(home) => Path::new(home).to_path_buf(),
+                None => std::env::current_dir()?,
+            };
+            // `--home` can point to malai.toml, like with `malai run`
+            let home = if home.is_file() {
+                home.parent().unwrap_or(Path::new(".")).to_path_buf()
+            } else {
+                home
+            };
+            let request = match cmd {
+                CtlCmd::Services => malai::CtlRequest::Services,
+                CtlCmd::Peers => malai::CtlRequest::Peers,
+                CtlCmd::Start { service } => malai::CtlRequest::Start(service),
+                CtlCmd::Stop { service } => malai::CtlRequest::Stop(service),
+                CtlCmd::Reload => malai::CtlRequest::Reload,
+            };
+            if let Err(e) = malai::ctl(&home, request).await {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Token { cmd }) => {
+            match cmd {
+                TokenCmd::Create {
+                    peer,
+                    protocol,
+                    identity,
+                    expires,
+                    valid_for,
+                } => {
+                    if let Err(e) =
+                        malai::create_token(peer, protocol, identity, expires, valid_for).await
+                    {
+                        tracing::error!(error = ?e, "Error creating access token.");
+                    }
+                }
+                TokenCmd::Show { token } => {
+                    if let Err(e) = malai::show_token(token) {
+                        tracing::error!(error = ?e, "Error reading access token.");
+                    }
+                }
+            }
             return Ok(());
         }
         Some(Command::Identity { cmd }) => {
             match cmd {
-                IdentityCmd::Create { file } => {
-                    if let Err(e) = malai::create_identity(file) {
+                IdentityCmd::Create { file, no_keyring } => {
+                    if let Err(e) = malai::create_identity(file, no_keyring) {
                         tracing::error!(error = ?e, "Error creating identity.");
                     }
                 }
@@ -213,6 +434,75 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
                         tracing::error!(error = ?e, "Error deleting identity.");
                     }
                 }
+                IdentityCmd::Import {
+                    input,
+                    file,
+                    key_file,
+                    encrypt,
+                } => {
+                    if let Err(e) = malai::import_identity(input, file, key_file, encrypt) {
+                        tracing::error!(error = ?e, "Error importing identity.");
+                    }
+                }
+                IdentityCmd::Export {
+                    id52,
+                    key_file,
+                    format,
+                    output,
+                } => {
+                    if let Err(e) = malai::export_identity(id52, key_file, format, output).await {
+                        tracing::error!(error = ?e, "Error exporting identity.");
+                    }
+                }
+                IdentityCmd::Backup { id52, key_file } => {
+                    if let Err(e) = malai::backup_identity(id52, key_file).await {
+                        tracing::error!(error = ?e, "Error backing up identity.");
+                    }
+                }
+                IdentityCmd::Restore {
+                    file,
+                    key_file,
+                    encrypt,
+                } => {
+                    if let Err(e) = malai::restore_identity(file, key_file, encrypt) {
+                        tracing::error!(error = ?e, "Error restoring identity.");
+                    }
+                }
+                IdentityCmd::List => {
+                    if let Err(e) = malai::list_identities() {
+                        tracing::error!(error = ?e, "Error listing identities.");
+                    }
+                }
+                IdentityCmd::Show { name } => {
+                    if let Err(e) = malai::show_identity(name) {
+                        tracing::error!(error = ?e, "Error showing identity.");
+                    }
+                }
+                IdentityCmd::Rename { name, label } => {
+                    if let Err(e) = malai::rename_identity(name, label) {
+                        tracing::error!(error = ?e, "Error renaming identity.");
+                    }
+                }
+                IdentityCmd::Default { name } => {
+                    if let Err(e) = malai::default_identity(name) {
+                        tracing::error!(error = ?e, "Error setting default identity.");
+                    }
+                }
+                IdentityCmd::Rotate { old, new, since } => {
+                    if let Err(e) = malai::rotate_identity(old, new, since).await {
+                        tracing::error!(error = ?e, "Error rotating identity.");
+                    }
+                }
+                IdentityCmd::Follow { file } => {
+                    if let Err(e) = malai::follow_rotation(file) {
+                        tracing::error!(error = ?e, "Error following rotation.");
+                    }
+                }
+                IdentityCmd::FetchRotation { peer, about } => {
+                    if let Err(e) = malai::fetch_rotation(peer, about, graceful.clone()).await {
+                        tracing::error!(error = ?e, "Error fetching rotation.");
+                    }
+                }
             }
             return Ok(());
         }
@@ -223,6 +513,8 @@ async fn match_cli(cli: Cli, graceful: Graceful) -> eyre::Result<()> {
             return Ok(());
         }
     };
+    // the cli wrappers, e.g., `malai::expose_tcp()`, tell systemd we are ready once they are bound
+    kulfi_utils::systemd::watchdog(graceful.clone());
     graceful.shutdown().await
 }
 
@@ -232,10 +524,46 @@ pub struct Cli {
     #[command(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 
+    #[arg(
+        long,
+        global = true,
+        env = "MALAI_METRICS_ADDR",
+        help = "Serve prometheus metrics at http://<addr>/metrics, e.g., 127.0.0.1:9090"
+    )]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "MALAI_SHUTDOWN_TIMEOUT",
+        default_value_t = kulfi_utils::DEFAULT_DRAIN_TIMEOUT.as_secs(),
+        help = "Seconds to wait for connections to finish when shutting down"
+    )]
+    shutdown_timeout: u64,
+
+    #[arg(
+        long,
+        global = true,
+        env = "MALAI_SHUTDOWN_MODE",
+        default_value = "interactive",
+        help = "What ctrl+c does: interactive (press twice, first shows info), immediate, or drain"
+    )]
+    shutdown_mode: kulfi_utils::ShutdownMode,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 }
 
+/// the cli runs a single service, there is nothing left to do if it fails.
+fn exit_on_error(r: eyre::Result<()>) {
+    if let Err(e) = r {
+        eprintln!("{e:?}");
+        std::process::exit(1);
+    }
+}
+
+// parsed once at startup, the size of the biggest variant does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(clap::Subcommand, Debug)]
 pub enum Command {
     // TODO: add this to the docs when we have ACL
@@ -261,6 +589,11 @@ pub enum Command {
             help = "Make the exposed service public. Anyone will be able to access."
         )]
         public: bool,
+        #[arg(
+            long,
+            help = "Only let in peers with an access token signed by this identity, see `malai token create`. No need for --public then."
+        )]
+        require_token: bool,
         // #[arg(
         //     long,
         //     default_value_t = false,
@@ -278,6 +611,11 @@ pub enum Command {
     Browse {
         #[arg(help = "The Kulfi URL to browse. Should look like kulfi://<id52>/<path>")]
         url: String,
+        #[arg(
+            long,
+            help = "The access token to present, for sites started with --require-token."
+        )]
+        token: Vec<String>,
     },
     #[clap(about = "Expose TCP Service on kulfi.")]
     Tcp {
@@ -293,6 +631,11 @@ pub enum Command {
             help = "Make the exposed service public. Anyone will be able to access."
         )]
         public: bool,
+        #[arg(
+            long,
+            help = "Only let in peers with an access token signed by this identity, see `malai token create`. No need for --public then."
+        )]
+        require_token: bool,
     },
     #[clap(
         about = "Run an http server that forwards requests to the given id52 taken from the HOST header"
@@ -311,6 +654,8 @@ pub enum Command {
             default_value = "0"
         )]
         port: u16,
+        #[command(flatten)]
+        options: malai::HttpBridgeOptions,
     },
     #[clap(about = "Expose UDP Service on kulfi.")]
     Udp {
@@ -326,6 +671,11 @@ pub enum Command {
             help = "Make the exposed service public. Anyone will be able to access."
         )]
         public: bool,
+        #[arg(
+            long,
+            help = "Only let in peers with an access token signed by this identity, see `malai token create`. No need for --public then."
+        )]
+        require_token: bool,
     },
     #[clap(about = "Run a TCP server that forwards incoming requests to the given id52.")]
     TcpBridge {
@@ -336,6 +686,11 @@ pub enum Command {
             default_value = "0"
         )]
         port: u16,
+        #[arg(
+            long,
+            help = "The access token to present, for services started with --require-token."
+        )]
+        token: Option<String>,
     },
     #[clap(about = "Run a UDP server that forwards incoming datagrams to the given id52.")]
     UdpBridge {
@@ -346,6 +701,11 @@ pub enum Command {
             default_value = "0"
         )]
         port: u16,
+        #[arg(
+            long,
+            help = "The access token to present, for services started with --require-token."
+        )]
+        token: Option<String>,
     },
     #[clap(about = "Expose both TCP and UDP on the same port on kulfi.")]
     TcpUdp {
@@ -361,6 +721,11 @@ pub enum Command {
             help = "Make the exposed service public. Anyone will be able to access."
         )]
         public: bool,
+        #[arg(
+            long,
+            help = "Only let in peers with an access token signed by this identity, see `malai token create`. No need for --public then."
+        )]
+        require_token: bool,
     },
     #[clap(about = "Expose a folder to kulfi network")]
     Folder {
@@ -409,12 +774,117 @@ pub enum Command {
             help = "The file where the private key of the identity will be stored. If not provided, the private key will be printed to stdout."
         )]
         file: Option<String>,
+        #[arg(
+            long,
+            help = "Encrypt the private key with a passphrase, asked for, or read from KULFI_KEY_PASSPHRASE."
+        )]
+        encrypt: bool,
+        #[arg(
+            long,
+            help = "Keep generating keys, on all cores, till the ID52 starts with this. Only 0-9 and a-v can appear in an ID52, and every character makes the search 32 times longer."
+        )]
+        prefix: Option<kulfi_id52::VanityPrefix>,
+    },
+    #[clap(
+        about = "Print a token proving that a custom domain on http-bridge belongs to your identity."
+    )]
+    DomainToken {
+        #[arg(help = "The custom domain, e.g. docs.example.com.")]
+        domain: String,
+        #[arg(
+            long,
+            help = "The id52 of an identity in the system keyring. By default the identity `malai http` would use, it is not created if missing."
+        )]
+        identity: Option<String>,
+    },
+    #[clap(
+        about = "Print the hash of a password, asked for, or read from stdin, for the users in an http-bridge auth file."
+    )]
+    HashPassword,
+    #[clap(about = "Check malai.toml for problems without starting any service")]
+    Validate {
+        #[arg(
+            help = "Malai Home directory or the config file, the current directory by default",
+            env = "MALAI_HOME"
+        )]
+        path: Option<String>,
+    },
+    #[clap(about = "Inspect and control a running `malai run`, needs `control` in malai.toml")]
+    Ctl {
+        #[arg(
+            long,
+            help = "Malai Home directory or the config file",
+            env = "MALAI_HOME"
+        )]
+        home: Option<String>,
+        #[clap(subcommand)]
+        cmd: CtlCmd,
     },
     #[clap(about = "Create or delete ID52s in the system keyring")]
     Identity {
         #[clap(subcommand)]
         cmd: IdentityCmd,
     },
+    #[clap(about = "Create or inspect access tokens, for services started with --require-token.")]
+    Token {
+        #[clap(subcommand)]
+        cmd: TokenCmd,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TokenCmd {
+    #[clap(
+        about = "Sign an access token letting a peer use a service of this identity for a while."
+    )]
+    Create {
+        #[arg(help = "The ID52 of the peer, it connects with this identity.")]
+        peer: String,
+        #[arg(long, help = "The protocol of the service.")]
+        protocol: malai::TokenProtocol,
+        #[arg(
+            long,
+            help = "The ID52 or label of the identity of the service. By default the one `malai http` would use."
+        )]
+        identity: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "valid_for",
+            help = "When the token expires, e.g., 2026-01-31T12:00:00Z."
+        )]
+        expires: Option<String>,
+        #[arg(
+            long,
+            help = "How many seconds the token is valid for.",
+            default_value_t = 86400
+        )]
+        valid_for: u64,
+    },
+    #[clap(about = "Show which peer an access token is for, and till when.")]
+    Show {
+        #[arg(help = "The access token.")]
+        token: String,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum CtlCmd {
+    #[clap(about = "List the services in malai.toml, with their ports and id52s.")]
+    Services,
+    #[clap(about = "List connected peers and their connection type.")]
+    Peers,
+    #[clap(about = "Start a service, even if it is not active in malai.toml.")]
+    Start {
+        #[arg(help = "The service, as <type>.<name>, e.g. http.my_web_app.")]
+        service: String,
+    },
+    #[clap(about = "Stop a running service.")]
+    Stop {
+        #[arg(help = "The service, as <type>.<name>, e.g. http.my_web_app.")]
+        service: String,
+    },
+    #[clap(about = "Re-read malai.toml and restart the services that changed.")]
+    Reload,
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -429,6 +899,11 @@ pub enum IdentityCmd {
             help = "The file or the folder to store the private key."
         )]
         file: Option<String>,
+        #[arg(
+            long,
+            help = "Store the secret key in the kulfi data folder instead of system keyring, e.g., on a server without one."
+        )]
+        no_keyring: bool,
     },
     #[clap(about = "Delete the identity from system keyring.")]
     Delete {
@@ -448,4 +923,157 @@ pub enum IdentityCmd {
         )]
         file: Option<String>,
     },
+    #[clap(
+        about = "Import a secret key, e.g., an OpenSSH or PKCS#8 ed25519 key, and store it to system keyring."
+    )]
+    Import {
+        #[arg(
+            help = "The key file: OpenSSH, PKCS#8 PEM or DER, or anything `malai keygen` writes."
+        )]
+        input: String,
+        #[arg(
+            long,
+            short,
+            num_args=0..=1,
+            default_missing_value=kulfi_utils::ID52_FILE,
+            help = "The file or the folder to store the ID52 in."
+        )]
+        file: Option<String>,
+        #[arg(
+            long,
+            num_args=0..=1,
+            default_missing_value=kulfi_utils::SECRET_KEY_FILE,
+            help = "Save the secret key to this file, like `malai keygen --file`, instead of system keyring."
+        )]
+        key_file: Option<String>,
+        #[arg(
+            long,
+            requires = "key_file",
+            help = "Encrypt the key file with a passphrase, asked for, or read from KULFI_KEY_PASSPHRASE."
+        )]
+        encrypt: bool,
+    },
+    #[clap(about = "Export an identity as an OpenSSH, PKCS#8 or SPKI key.")]
+    Export {
+        #[arg(
+            long,
+            short,
+            help = "The ID52 or label of an identity, see `malai identity list`. By default the identity `malai http` would use."
+        )]
+        id52: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "id52",
+            help = "Export the secret key in this file instead."
+        )]
+        key_file: Option<String>,
+        #[arg(
+            long,
+            default_value = "openssh",
+            help = "One of hex, openssh, openssh-pub, pkcs8, pkcs8-der, spki or spki-der."
+        )]
+        format: malai::KeyFormat,
+        #[arg(
+            long,
+            short,
+            help = "The file to write to, it must not exist yet. By default the key is printed to stdout."
+        )]
+        output: Option<String>,
+    },
+    #[clap(
+        about = "Print an identity as 24 words to write down, `malai identity restore` reads them back."
+    )]
+    Backup {
+        #[arg(
+            help = "The ID52 or label of an identity, see `malai identity list`. By default the identity `malai http` would use."
+        )]
+        id52: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "id52",
+            help = "Back up the secret key in this file instead."
+        )]
+        key_file: Option<String>,
+    },
+    #[clap(
+        about = "Restore an identity from the 24 words of `malai identity backup`, asked for, or read from stdin."
+    )]
+    Restore {
+        #[arg(
+            long,
+            short,
+            num_args=0..=1,
+            default_missing_value=kulfi_utils::ID52_FILE,
+            help = "The file or the folder to store the ID52 in."
+        )]
+        file: Option<String>,
+        #[arg(
+            long,
+            num_args=0..=1,
+            default_missing_value=kulfi_utils::SECRET_KEY_FILE,
+            help = "Save the secret key to this file, like `malai keygen --file`, instead of system keyring."
+        )]
+        key_file: Option<String>,
+        #[arg(
+            long,
+            requires = "key_file",
+            help = "Encrypt the key file with a passphrase, asked for, or read from KULFI_KEY_PASSPHRASE."
+        )]
+        encrypt: bool,
+    },
+    #[clap(about = "List the identities created, imported or restored on this machine.")]
+    List,
+    #[clap(about = "Show where an identity is stored, when it was created and what uses it.")]
+    Show {
+        #[arg(help = "The ID52 or label of the identity. By default the default identity.")]
+        name: Option<String>,
+    },
+    #[clap(
+        alias = "label",
+        about = "Label an identity, e.g., with the service using it, to refer to it by that name."
+    )]
+    Rename {
+        #[arg(help = "The ID52 or current label of the identity.")]
+        name: String,
+        #[arg(help = "The new label. Without one the label is removed.")]
+        label: Option<String>,
+    },
+    #[clap(
+        about = "Print the default identity, or set it. It is used when there is no .malai.id52 file."
+    )]
+    Default {
+        #[arg(help = "The ID52 or label of the identity to make the default.")]
+        name: Option<String>,
+    },
+    #[clap(
+        about = "Sign that an identity is superseded by another, e.g., after its key leaked. Peers following it connect to the new one."
+    )]
+    Rotate {
+        #[arg(help = "The ID52 or label of the identity to retire.")]
+        old: String,
+        #[arg(help = "The ID52 or label of the identity replacing it, create it first.")]
+        new: String,
+        #[arg(
+            long,
+            help = "When the rotation applies, e.g., 2026-01-31T12:00:00Z. By default right away."
+        )]
+        since: Option<String>,
+    },
+    #[clap(
+        about = "Follow a rotation printed by `malai identity rotate`, connections to the old identity go to the new one."
+    )]
+    Follow {
+        #[arg(help = "The file with the rotation. By default it is read from stdin.")]
+        file: Option<String>,
+    },
+    #[clap(about = "Ask a peer for a rotation, and follow it.")]
+    FetchRotation {
+        #[arg(help = "The ID52 of the peer to ask.")]
+        peer: String,
+        #[arg(
+            long,
+            help = "The ID52 whose rotation to ask for. By default the peer's own."
+        )]
+        about: Option<String>,
+    },
 }
@@ -1,6 +1,7 @@
 use eyre::Context;
 use eyre::ContextCompat;
 use eyre::eyre;
+use futures_util::future::BoxFuture;
 use serde::Deserialize;
 use serde::de;
 use std::collections::HashMap;
@@ -15,10 +16,17 @@ use tracing_appender::non_blocking::WorkerGuard;
 use tracing_appender::rolling;
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
+mod expand;
+mod supervise;
+mod validate;
+
+pub use validate::validate;
+
 static LOG_GUARD: OnceLock<WorkerGuard> = OnceLock::new();
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default = "default_malai_conf")]
     malai: MalaiConf,
@@ -26,14 +34,34 @@ pub struct Config {
     tcp: Option<TcpServices>,
     udp: Option<UdpServices>,
     tcp_udp: Option<TcpUdpServices>,
+    tcp_bridge: Option<Section<TcpBridgeConf>>,
+    udp_bridge: Option<Section<UdpBridgeConf>>,
+    http_bridge: Option<Section<HttpBridgeConf>>,
+    http_proxy: Option<Section<HttpProxyConf>>,
+    http_proxy_remote: Option<Section<HttpProxyRemoteConf>>,
+    folder: Option<Section<FolderConf>>,
+    /// merged in by `expand`, only here so files that include others are not rejected by
+    /// `locate_error`
+    include: Option<toml::Value>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 struct MalaiConf {
     log: Option<String>,
+    /// address to serve prometheus metrics on, e.g., `127.0.0.1:9090`
+    metrics: Option<std::net::SocketAddr>,
+    /// loopback address for the control api used by `malai ctl`, e.g., `127.0.0.1:7070`
+    control: Option<std::net::SocketAddr>,
+    /// reload when malai.toml changes, it is always reloaded on SIGHUP
+    #[serde(default)]
+    watch: bool,
+    /// how many times in a row a failing service is restarted before giving up on it
+    #[serde(default = "default_max_restarts")]
+    max_restarts: u32,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 enum StringOrVec {
     Single(String),
@@ -58,28 +86,90 @@ impl StringOrVec {
     }
 }
 
+/// where the secret key of each port is, only one of these can be set, see
+/// `kulfi_utils::secret::SecretStore` for what each one reads.
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 struct IdentityConf {
     identity: Option<StringOrVec>,
     secret_file: Option<StringOrVec>,
+    secret_env: Option<StringOrVec>,
+    secret_command: Option<StringOrVec>,
+    secret_credential: Option<StringOrVec>,
+}
+
+impl IdentityConf {
+    /// the key that is set, and its value, errors if more than one is.
+    fn source(&self) -> eyre::Result<Option<(&'static str, &StringOrVec)>> {
+        let set: Vec<_> = [
+            ("identity", &self.identity),
+            ("secret_file", &self.secret_file),
+            ("secret_env", &self.secret_env),
+            ("secret_command", &self.secret_command),
+            ("secret_credential", &self.secret_credential),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_ref().map(|v| (key, v)))
+        .collect();
+        match set.as_slice() {
+            [] => Ok(None),
+            [one] => Ok(Some(*one)),
+            [first, second, ..] => Err(eyre!(
+                "both {} and {} are set, only one can be used",
+                first.0,
+                second.0
+            )),
+        }
+    }
+}
+
+/// the store `key` of `IdentityConf` stands for, with one of its values.
+fn secret_store(key: &str, value: &str) -> Box<dyn kulfi_utils::secret::SecretStore> {
+    use kulfi_utils::secret::*;
+
+    match key {
+        "identity" => Box::new(KeyringStore {
+            name: value.to_string(),
+        }),
+        "secret_file" => Box::new(FileStore { path: value.into() }),
+        "secret_env" => Box::new(EnvStore {
+            var: value.to_string(),
+        }),
+        "secret_command" => Box::new(CommandStore {
+            command: value.to_string(),
+        }),
+        "secret_credential" => Box::new(CredentialStore {
+            name: value.to_string(),
+        }),
+        _ => unreachable!("IdentityConf::source() only returns its own keys"),
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct HttpServices {
+/// a `[<type>.<name>]` table, e.g., all the `[http.*]` services.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct Section<C> {
     #[allow(dead_code)]
     #[serde(flatten)]
-    services: HashMap<String, HttpServiceConf>,
+    services: HashMap<String, C>,
 }
 
+type HttpServices = Section<HttpServiceConf>;
+type TcpServices = Section<TcpServiceConf>;
+type UdpServices = Section<UdpServiceConf>;
+type TcpUdpServices = Section<TcpUdpServiceConf>;
+
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 struct HttpServiceConf {
     #[serde(flatten)]
     identity_conf: IdentityConf, // Leave None to read from env, .malai.secret-key file or .malai.id52 file and system keyring
     #[serde(alias = "ports", deserialize_with = "deserialize_ports")]
     port: Vec<u16>,
     public: bool,
+    /// peers need an access token signed by the identity, no need for `public` then
+    #[serde(default)]
+    require_token: bool,
     active: bool,
     #[serde(default = "default_host")]
     host: String,
@@ -87,66 +177,126 @@ struct HttpServiceConf {
     bridge: String,
 }
 
-#[derive(Deserialize, Debug)]
-struct TcpServices {
-    #[allow(dead_code)]
-    #[serde(flatten)]
-    services: HashMap<String, TcpServiceConf>,
-}
-
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 struct TcpServiceConf {
     #[serde(flatten)]
     identity_conf: IdentityConf, // Leave None to read from env, .malai.secret-key file or .malai.id52 file and system keyring
     #[serde(alias = "ports", deserialize_with = "deserialize_ports")]
     port: Vec<u16>,
     public: bool,
+    /// peers need an access token signed by the identity, no need for `public` then
+    #[serde(default)]
+    require_token: bool,
     active: bool,
     #[serde(default = "default_host")]
     host: String,
 }
 
-#[derive(Deserialize, Debug)]
-struct UdpServices {
-    #[allow(dead_code)]
-    #[serde(flatten)]
-    services: HashMap<String, UdpServiceConf>,
-}
-
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 struct UdpServiceConf {
     #[serde(flatten)]
     identity_conf: IdentityConf,
     #[serde(alias = "ports", deserialize_with = "deserialize_ports")]
     port: Vec<u16>,
     public: bool,
+    /// peers need an access token signed by the identity, no need for `public` then
+    #[serde(default)]
+    require_token: bool,
     active: bool,
     #[serde(default = "default_host")]
     host: String,
 }
 
-#[derive(Deserialize, Debug)]
-struct TcpUdpServices {
-    #[allow(dead_code)]
-    #[serde(flatten)]
-    services: HashMap<String, TcpUdpServiceConf>,
-}
-
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 struct TcpUdpServiceConf {
     #[serde(flatten)]
     identity_conf: IdentityConf,
     #[serde(alias = "ports", deserialize_with = "deserialize_ports")]
     port: Vec<u16>,
     public: bool,
+    /// peers need an access token signed by the identity, no need for `public` then
+    #[serde(default)]
+    require_token: bool,
     active: bool,
     #[serde(default = "default_host")]
     host: String,
 }
 
+/// `malai tcp-bridge`, the port is picked at random if not set.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct TcpBridgeConf {
+    proxy_target: String,
+    #[serde(default)]
+    port: u16,
+    active: bool,
+    /// the access token to present, for services that require one
+    token: Option<String>,
+}
+
+/// `malai udp-bridge`, the port is picked at random if not set.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct UdpBridgeConf {
+    proxy_target: String,
+    #[serde(default)]
+    port: u16,
+    active: bool,
+    /// the access token to present, for services that require one
+    token: Option<String>,
+}
+
+/// `malai http-bridge`, takes all the `malai http-bridge` flags, with `_` instead of `-`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct HttpBridgeConf {
+    proxy_target: Option<String>,
+    #[serde(default)]
+    port: u16,
+    active: bool,
+    #[serde(flatten)]
+    options: malai::HttpBridgeOptions,
+}
+
+/// `malai http-proxy`, forwards to the `http_proxy_remote` with id52 `remote`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct HttpProxyConf {
+    remote: String,
+    #[serde(default)]
+    port: u16,
+    active: bool,
+}
+
+/// `malai http-proxy-remote`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct HttpProxyRemoteConf {
+    #[serde(flatten)]
+    identity_conf: IdentityConf,
+    public: bool,
+    active: bool,
+}
+
+/// `malai folder`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct FolderConf {
+    #[serde(flatten)]
+    identity_conf: IdentityConf,
+    path: String,
+    public: bool,
+    active: bool,
+    #[serde(default = "default_bridge")]
+    bridge: String,
+}
+
 fn default_host() -> String {
     "127.0.0.1".to_string()
 }
@@ -156,7 +306,17 @@ fn default_bridge() -> String {
 }
 
 fn default_malai_conf() -> MalaiConf {
-    MalaiConf { log: None }
+    MalaiConf {
+        log: None,
+        metrics: None,
+        control: None,
+        watch: false,
+        max_restarts: supervise::DEFAULT_MAX_RESTARTS,
+    }
+}
+
+fn default_max_restarts() -> u32 {
+    supervise::DEFAULT_MAX_RESTARTS
 }
 
 /// Deserializes either a single port (`port = 3000`) or a list of ports (`port = [3000, 3001]`).
@@ -215,8 +375,35 @@ fn parse_config(path: &Path) -> eyre::Result<Config> {
     let conf_str = fs::read_to_string(path)
         .with_context(|| format!("Failed to read config file at {}", path.display()))?;
 
-    let conf = toml::from_str(&conf_str).context("Failed to parse config file")?;
-    Ok(conf)
+    let mut included = vec![];
+    let table =
+        expand::expand(&conf_str, path, &mut included).context("Failed to parse config file")?;
+    toml::Value::Table(table).try_into().map_err(|e| {
+        let files: Vec<_> = std::iter::once((path, conf_str.as_str()))
+            .chain(included.iter().map(|(p, c)| (p.as_path(), c.as_str())))
+            .collect();
+        match locate_error(&e, files.iter().map(|(_, c)| *c)) {
+            Some((i, e)) => eyre!("{e}").wrap_err(format!(
+                "Failed to parse config file {}",
+                files[i].0.display()
+            )),
+            None => eyre!("{e}").wrap_err("Failed to parse config file"),
+        }
+    })
+}
+
+/// the merged config has no spans, so errors like a typo in a key are looked for in each of the
+/// files it is made of, the index of the file with the same error is returned, with its span.
+fn locate_error<'a>(
+    e: &toml::de::Error,
+    files: impl Iterator<Item = &'a str>,
+) -> Option<(usize, toml::de::Error)> {
+    files
+        .enumerate()
+        .find_map(|(i, content)| match toml::from_str::<Config>(content) {
+            Err(found) if found.message() == e.message() => Some((i, found)),
+            _ => None,
+        })
 }
 
 fn set_up_logging(conf: &Config) -> eyre::Result<()> {
@@ -258,11 +445,6 @@ fn set_up_logging(conf: &Config) -> eyre::Result<()> {
     Ok(())
 }
 
-fn load_secret_from_file(path: &Path) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
-    let secret_key = fs::read_to_string(path)?.trim().to_string();
-    kulfi_utils::secret::handle_secret(&secret_key)
-}
-
 fn check_used(used_id52: &mut HashSet<String>, id52: &str) -> eyre::Result<()> {
     if used_id52.contains(id52) {
         Err(eyre!("Identity already used."))
@@ -277,57 +459,30 @@ fn validate_identity_conf(
     port_count: usize,
     service_name: &str,
 ) -> eyre::Result<()> {
+    let source = identity_conf
+        .source()
+        .wrap_err_with(|| format!("Service '{service_name}'"))?;
     if port_count <= 1 {
         return Ok(());
     }
 
-    if let Some(ref secret_file) = identity_conf.secret_file {
-        match secret_file {
-            StringOrVec::Single(_) => {
-                return Err(eyre!(
-                    "Service '{}' has {} ports but only a single secret_file. \
-                     Provide an array of {} secret_file entries, one per port.",
-                    service_name,
-                    port_count,
-                    port_count
-                ));
-            }
-            StringOrVec::Multiple(v) if v.len() != port_count => {
-                return Err(eyre!(
-                    "Service '{}' has {} ports but {} secret_file entries. \
-                     The counts must match.",
-                    service_name,
-                    port_count,
-                    v.len()
-                ));
-            }
-            _ => {}
-        }
-    } else if let Some(ref identity) = identity_conf.identity {
-        match identity {
-            StringOrVec::Single(_) => {
-                return Err(eyre!(
-                    "Service '{}' has {} ports but only a single identity. \
-                     Provide an array of {} identity entries, one per port.",
-                    service_name,
-                    port_count,
-                    port_count
-                ));
-            }
-            StringOrVec::Multiple(v) if v.len() != port_count => {
-                return Err(eyre!(
-                    "Service '{}' has {} ports but {} identity entries. \
-                     The counts must match.",
-                    service_name,
-                    port_count,
-                    v.len()
-                ));
-            }
-            _ => {}
-        }
+    match source {
+        Some((key, StringOrVec::Single(_))) => Err(eyre!(
+            "Service '{}' has {} ports but only a single {key}. \
+             Provide an array of {} {key} entries, one per port.",
+            service_name,
+            port_count,
+            port_count
+        )),
+        Some((key, StringOrVec::Multiple(v))) if v.len() != port_count => Err(eyre!(
+            "Service '{}' has {} ports but {} {key} entries. \
+             The counts must match.",
+            service_name,
+            port_count,
+            v.len()
+        )),
+        _ => Ok(()),
     }
-
-    Ok(())
 }
 
 async fn load_identity(
@@ -335,246 +490,819 @@ async fn load_identity(
     port_index: usize,
     used_id52: &mut HashSet<String>,
 ) -> eyre::Result<(String, kulfi_id52::SecretKey)> {
-    let (id52, secret_key) = if let Some(ref secret_file) = identity_conf.secret_file {
-        let secret_path = secret_file
-            .get(port_index)
-            .context("secret_file index out of bounds")?;
-        load_secret_from_file(Path::new(secret_path))?
-    } else {
-        let identity = identity_conf
-            .identity
-            .as_ref()
-            .context("No identity specified. Please specify an identity or a secret key file.")?;
-        let id52_str = identity
-            .get(port_index)
-            .context("identity index out of bounds")?;
-        kulfi_utils::secret::handle_identity(id52_str.to_string()).context(format!(
-            "Failed to load identity {} from system keyring.",
-            id52_str
-        ))?
-    };
+    let (key, values) = identity_conf.source()?.context(
+        "No identity specified. Please specify an identity, secret_file, secret_env, \
+         secret_command or secret_credential.",
+    )?;
+    let value = values
+        .get(port_index)
+        .with_context(|| format!("{key} index out of bounds"))?;
+    // secret_command runs a command, and the keyring can block too, so not on the runtime
+    let store = secret_store(key, value);
+    let (id52, secret_key) = tokio::task::spawn_blocking(move || store.load())
+        .await
+        .map_err(|e| eyre!("loading the secret key failed: {e}"))?
+        .wrap_err_with(|| format!("Failed to load the secret key from {key} {value}."))?;
     check_used(used_id52, &id52)?;
     Ok((id52, secret_key))
 }
 
-/// Generic trait for service configuration shared across HTTP, TCP, UDP, and TCP+UDP
-trait ServiceConfig {
-    fn port(&self) -> &Vec<u16>;
-    fn identity_conf(&self) -> &IdentityConf;
+/// Generic trait for service configuration shared across all service types.
+///
+/// exposers (http, tcp, udp, tcp_udp) have a port and an identity per port. bridges and proxies
+/// only connect to others, so they have no identity, and `http_proxy_remote` and `folder` have
+/// an identity but no port.
+trait ServiceConfig: AnyService + Send + Sync {
+    /// empty for services without a port, they are started once
+    fn port(&self) -> &[u16];
+    /// `None` for services that do not need an identity of their own
+    fn identity_conf(&self) -> Option<&IdentityConf>;
     fn active(&self) -> bool;
-    fn public(&self) -> bool;
-    fn host(&self) -> &str;
+    /// always true for services without an identity, there is nothing to make public
+    fn public(&self) -> bool {
+        true
+    }
+    fn host(&self) -> &str {
+        "127.0.0.1"
+    }
+    /// starts one port of the service, it then runs till it fails or `graceful` is cancelled.
+    /// called again by the supervisor to restart it.
+    fn start(
+        &self,
+        port: Option<u16>,
+        identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>>;
+}
+
+/// what `dyn ServiceConfig` needs from the concrete types: `Config::same_service` compares two
+/// services without knowing their types, and the supervisor keeps its own copy to restart from.
+trait AnyService {
+    fn as_any(&self) -> &dyn std::any::Any;
+    fn same_as(&self, other: &dyn ServiceConfig) -> bool;
+    fn to_arc(&self) -> std::sync::Arc<dyn ServiceConfig>;
+}
+
+impl<C: ServiceConfig + Clone + PartialEq + 'static> AnyService for C {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn same_as(&self, other: &dyn ServiceConfig) -> bool {
+        other.as_any().downcast_ref::<C>() == Some(self)
+    }
+    fn to_arc(&self) -> std::sync::Arc<dyn ServiceConfig> {
+        std::sync::Arc::new(self.clone())
+    }
 }
 
 impl ServiceConfig for HttpServiceConf {
-    fn port(&self) -> &Vec<u16> {
+    fn port(&self) -> &[u16] {
         &self.port
     }
-    fn identity_conf(&self) -> &IdentityConf {
-        &self.identity_conf
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        Some(&self.identity_conf)
     }
     fn active(&self) -> bool {
         self.active
     }
     fn public(&self) -> bool {
-        self.public
+        self.public || self.require_token
     }
     fn host(&self) -> &str {
         &self.host
     }
+    fn start(
+        &self,
+        port: Option<u16>,
+        identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (host, port) = (
+            self.host.clone(),
+            port.expect("exposers always have a port"),
+        );
+        let (_, secret_key) = identity.expect("exposers always have an identity");
+        let require_token = self.require_token;
+        Box::pin(async move {
+            malai::start_expose_http(
+                malai::ExposeHttpConfig {
+                    host,
+                    port,
+                    secret_key,
+                    require_token,
+                },
+                graceful,
+            )
+            .await
+        })
+    }
 }
 
 impl ServiceConfig for TcpServiceConf {
-    fn port(&self) -> &Vec<u16> {
+    fn port(&self) -> &[u16] {
         &self.port
     }
-    fn identity_conf(&self) -> &IdentityConf {
-        &self.identity_conf
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        Some(&self.identity_conf)
     }
     fn active(&self) -> bool {
         self.active
     }
     fn public(&self) -> bool {
-        self.public
+        self.public || self.require_token
     }
     fn host(&self) -> &str {
         &self.host
     }
+    fn start(
+        &self,
+        port: Option<u16>,
+        identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (host, port) = (
+            self.host.clone(),
+            port.expect("exposers always have a port"),
+        );
+        let (_, secret_key) = identity.expect("exposers always have an identity");
+        let require_token = self.require_token;
+        Box::pin(async move {
+            malai::start_expose_tcp(
+                malai::ExposeConfig {
+                    host,
+                    port,
+                    secret_key,
+                    require_token,
+                },
+                graceful,
+            )
+            .await
+        })
+    }
 }
 
 impl ServiceConfig for UdpServiceConf {
-    fn port(&self) -> &Vec<u16> {
+    fn port(&self) -> &[u16] {
         &self.port
     }
-    fn identity_conf(&self) -> &IdentityConf {
-        &self.identity_conf
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        Some(&self.identity_conf)
     }
     fn active(&self) -> bool {
         self.active
     }
     fn public(&self) -> bool {
-        self.public
+        self.public || self.require_token
     }
     fn host(&self) -> &str {
         &self.host
     }
+    fn start(
+        &self,
+        port: Option<u16>,
+        identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (host, port) = (
+            self.host.clone(),
+            port.expect("exposers always have a port"),
+        );
+        let (_, secret_key) = identity.expect("exposers always have an identity");
+        let require_token = self.require_token;
+        Box::pin(async move {
+            malai::start_expose_udp(
+                malai::ExposeConfig {
+                    host,
+                    port,
+                    secret_key,
+                    require_token,
+                },
+                graceful,
+            )
+            .await
+        })
+    }
 }
 
 impl ServiceConfig for TcpUdpServiceConf {
-    fn port(&self) -> &Vec<u16> {
+    fn port(&self) -> &[u16] {
         &self.port
     }
-    fn identity_conf(&self) -> &IdentityConf {
-        &self.identity_conf
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        Some(&self.identity_conf)
     }
     fn active(&self) -> bool {
         self.active
     }
     fn public(&self) -> bool {
-        self.public
+        self.public || self.require_token
     }
     fn host(&self) -> &str {
         &self.host
     }
+    fn start(
+        &self,
+        port: Option<u16>,
+        identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (host, port) = (
+            self.host.clone(),
+            port.expect("exposers always have a port"),
+        );
+        let (_, secret_key) = identity.expect("exposers always have an identity");
+        let require_token = self.require_token;
+        Box::pin(async move {
+            malai::start_expose_tcp_udp(
+                malai::ExposeConfig {
+                    host,
+                    port,
+                    secret_key,
+                    require_token,
+                },
+                graceful,
+            )
+            .await
+        })
+    }
 }
 
-/// Generic service setup function that handles validation, identity loading, and spawning
-async fn process_services<C, F>(
-    services: &HashMap<String, C>,
-    service_type: &str,
-    used_id52: &mut HashSet<String>,
-    graceful: kulfi_utils::Graceful,
-    mut spawn_service: F,
-) where
-    C: ServiceConfig,
-    F: FnMut(&C, String, u16, String, kulfi_id52::SecretKey, kulfi_utils::Graceful),
-{
-    for (name, service_conf) in services {
-        info!("Starting {} services: {}", service_type, name);
+impl ServiceConfig for TcpBridgeConf {
+    fn port(&self) -> &[u16] {
+        std::slice::from_ref(&self.port)
+    }
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        None
+    }
+    fn active(&self) -> bool {
+        self.active
+    }
+    fn start(
+        &self,
+        _port: Option<u16>,
+        _identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (port, proxy_target, token) =
+            (self.port, self.proxy_target.clone(), self.token.clone());
+        Box::pin(async move {
+            malai::start_tcp_bridge(
+                malai::BridgeConfig {
+                    port,
+                    proxy_target,
+                    token,
+                },
+                graceful,
+            )
+            .await
+        })
+    }
+}
 
-        if !service_conf.active() {
-            continue;
-        }
-        if !service_conf.public() {
-            tracing::warn!(
-                "You have to set public to true for service {}. Skipping.",
-                name
-            );
-            continue;
-        }
+impl ServiceConfig for UdpBridgeConf {
+    fn port(&self) -> &[u16] {
+        std::slice::from_ref(&self.port)
+    }
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        None
+    }
+    fn active(&self) -> bool {
+        self.active
+    }
+    fn start(
+        &self,
+        _port: Option<u16>,
+        _identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (port, proxy_target, token) =
+            (self.port, self.proxy_target.clone(), self.token.clone());
+        Box::pin(async move {
+            malai::start_udp_bridge(
+                malai::BridgeConfig {
+                    port,
+                    proxy_target,
+                    token,
+                },
+                graceful,
+            )
+            .await
+        })
+    }
+}
 
-        if let Err(e) = validate_identity_conf(
-            service_conf.identity_conf(),
-            service_conf.port().len(),
-            name,
+impl ServiceConfig for HttpBridgeConf {
+    fn port(&self) -> &[u16] {
+        std::slice::from_ref(&self.port)
+    }
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        None
+    }
+    fn active(&self) -> bool {
+        self.active
+    }
+    fn start(
+        &self,
+        _port: Option<u16>,
+        _identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (port, proxy_target) = (self.port, self.proxy_target.clone());
+        let config = self.options.clone().config();
+        Box::pin(
+            async move { malai::start_http_bridge(port, proxy_target, config, graceful).await },
+        )
+    }
+}
+
+impl ServiceConfig for HttpProxyConf {
+    fn port(&self) -> &[u16] {
+        std::slice::from_ref(&self.port)
+    }
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        None
+    }
+    fn active(&self) -> bool {
+        self.active
+    }
+    fn start(
+        &self,
+        _port: Option<u16>,
+        _identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (port, remote) = (self.port, self.remote.clone());
+        Box::pin(async move {
+            malai::start_http_proxy(malai::HttpProxyConfig { port, remote }, graceful).await
+        })
+    }
+}
+
+impl ServiceConfig for HttpProxyRemoteConf {
+    fn port(&self) -> &[u16] {
+        &[]
+    }
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        Some(&self.identity_conf)
+    }
+    fn active(&self) -> bool {
+        self.active
+    }
+    fn public(&self) -> bool {
+        self.public
+    }
+    fn start(
+        &self,
+        _port: Option<u16>,
+        identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (_, secret_key) = identity.expect("http_proxy_remote always has an identity");
+        Box::pin(async move {
+            malai::start_http_proxy_remote(malai::HttpProxyRemoteConfig { secret_key }, graceful)
+                .await
+        })
+    }
+}
+
+impl ServiceConfig for FolderConf {
+    fn port(&self) -> &[u16] {
+        &[]
+    }
+    fn identity_conf(&self) -> Option<&IdentityConf> {
+        Some(&self.identity_conf)
+    }
+    fn active(&self) -> bool {
+        self.active
+    }
+    fn public(&self) -> bool {
+        self.public
+    }
+    fn start(
+        &self,
+        _port: Option<u16>,
+        identity: Option<(String, kulfi_id52::SecretKey)>,
+        graceful: kulfi_utils::Graceful,
+    ) -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>> {
+        let (_, secret_key) = identity.expect("folder always has an identity");
+        let path = self.path.clone();
+        Box::pin(async move {
+            malai::start_folder(malai::FolderConfig { path, secret_key }, graceful).await
+        })
+    }
+}
+
+impl Config {
+    /// all services, as `<type>.<name>`, e.g., `http.my_web_app`, sorted by name.
+    fn services(&self) -> Vec<(String, &dyn ServiceConfig)> {
+        fn add<'a, C: ServiceConfig>(
+            all: &mut Vec<(String, &'a dyn ServiceConfig)>,
+            kind: &str,
+            section: Option<&'a Section<C>>,
         ) {
-            error!("{} Skipping.", e);
-            continue;
+            for (name, c) in section.into_iter().flat_map(|s| s.services.iter()) {
+                all.push((format!("{kind}.{name}"), c as &dyn ServiceConfig));
+            }
         }
 
-        for (i, &port) in service_conf.port().iter().enumerate() {
-            let (id52, secret_key) =
-                match load_identity(service_conf.identity_conf(), i, used_id52).await {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!(
-                            "Failed to load identity for service {} port {}: {} Skipping.",
-                            name, port, e
-                        );
-                        continue;
-                    }
-                };
+        let mut all = vec![];
+        add(&mut all, "http", self.http.as_ref());
+        add(&mut all, "tcp", self.tcp.as_ref());
+        add(&mut all, "udp", self.udp.as_ref());
+        add(&mut all, "tcp_udp", self.tcp_udp.as_ref());
+        add(&mut all, "tcp_bridge", self.tcp_bridge.as_ref());
+        add(&mut all, "udp_bridge", self.udp_bridge.as_ref());
+        add(&mut all, "http_bridge", self.http_bridge.as_ref());
+        add(&mut all, "http_proxy", self.http_proxy.as_ref());
+        add(
+            &mut all,
+            "http_proxy_remote",
+            self.http_proxy_remote.as_ref(),
+        );
+        add(&mut all, "folder", self.folder.as_ref());
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all
+    }
 
-            let host = service_conf.host().to_string();
-            let graceful_clone = graceful.clone();
+    fn service_names(&self) -> Vec<String> {
+        self.services().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// looks up a service by `<type>.<name>`.
+    fn service(&self, name: &str) -> Option<&dyn ServiceConfig> {
+        self.services()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, c)| c)
+    }
 
-            spawn_service(service_conf, host, port, id52, secret_key, graceful_clone);
+    /// whether the service `<type>.<name>` is configured the same way in both.
+    fn same_service(&self, other: &Config, name: &str) -> bool {
+        match (self.service(name), other.service(name)) {
+            (Some(a), Some(b)) => a.same_as(b),
+            (None, None) => true,
+            _ => false,
         }
     }
 }
 
-async fn set_up_http_services(
-    conf: &Config,
-    used_id52: &mut HashSet<String>,
-    graceful: kulfi_utils::Graceful,
-) {
-    if let Some(http_conf) = &conf.http {
-        process_services(
-            &http_conf.services,
-            "HTTP",
-            used_id52,
-            graceful.clone(),
-            |service_conf, host, port, id52, secret_key, graceful_clone| {
-                let bridge = service_conf.bridge.clone();
-                graceful.spawn(async move {
-                    malai::expose_http(host, port, bridge, id52, secret_key, graceful_clone).await
-                });
-            },
-        )
-        .await;
+#[derive(Debug, Default, PartialEq)]
+struct ReloadPlan {
+    stop: Vec<String>,
+    start: Vec<String>,
+}
+
+fn plan_reload(old: &Config, new: &Config, running: &[String]) -> ReloadPlan {
+    let mut plan = ReloadPlan::default();
+
+    for name in running {
+        if !old.same_service(new, name) {
+            plan.stop.push(name.clone());
+        }
     }
+
+    for name in new.service_names() {
+        let active = new.service(&name).is_some_and(|c| c.active());
+        // unchanged services that are not running were stopped by hand, or failed to start, and
+        // are left that way
+        if active && !old.same_service(new, &name) {
+            plan.start.push(name);
+        }
+    }
+
+    plan
 }
 
-async fn set_up_tcp_services(
-    conf: &Config,
-    used_id52: &mut HashSet<String>,
+/// reloads on SIGHUP, and, if `watch` is set, when the config file, or a file it includes,
+/// changes.
+fn reload_on_change(
+    services: std::sync::Arc<Services>,
+    watch: bool,
     graceful: kulfi_utils::Graceful,
 ) {
-    if let Some(tcp_conf) = &conf.tcp {
-        process_services(
-            &tcp_conf.services,
-            "TCP",
-            used_id52,
-            graceful.clone(),
-            |_service_conf, host, port, id52, secret_key, graceful_clone| {
-                graceful.spawn(async move {
-                    malai::expose_tcp(host, port, id52, secret_key, graceful_clone).await
-                });
-            },
-        )
-        .await;
+    const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+    // of malai.toml and every file it includes, so adding or removing an included file counts
+    let mtime = |path: &Path| {
+        expand::files(path)
+            .iter()
+            .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect::<Vec<_>>()
+    };
+
+    let graceful_for_task = graceful.clone();
+    graceful.spawn(async move {
+        let mut sighup = sighup();
+        let mut last_mtime = mtime(&services.conf_path);
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = graceful_for_task.cancelled() => break,
+                _ = hangup(&mut sighup) => info!("Received SIGHUP."),
+                _ = interval.tick(), if watch => {
+                    let current = mtime(&services.conf_path);
+                    if current == last_mtime {
+                        continue;
+                    }
+                    info!("{} changed.", services.conf_path.display());
+                }
+            }
+
+            last_mtime = mtime(&services.conf_path);
+            if let Err(e) = services.reload().await {
+                error!("Failed to reload config: {e:#}");
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn sighup() -> Option<tokio::signal::unix::Signal> {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .inspect_err(|e| error!("Failed to listen for SIGHUP: {e}"))
+        .ok()
+}
+
+#[cfg(unix)]
+async fn hangup(sighup: &mut Option<tokio::signal::unix::Signal>) {
+    match sighup {
+        Some(s) => {
+            s.recv().await;
+        }
+        None => std::future::pending().await,
     }
 }
 
-async fn set_up_udp_services(
-    conf: &Config,
-    used_id52: &mut HashSet<String>,
+#[cfg(not(unix))]
+fn sighup() {}
+
+#[cfg(not(unix))]
+async fn hangup(_: &mut ()) {
+    std::future::pending().await
+}
+
+struct Running {
+    /// every port that was started
+    instances: Vec<Instance>,
     graceful: kulfi_utils::Graceful,
-) {
-    if let Some(udp_conf) = &conf.udp {
-        process_services(
-            &udp_conf.services,
-            "UDP",
-            used_id52,
-            graceful.clone(),
-            |_service_conf, host, port, id52, secret_key, graceful_clone| {
-                graceful.spawn(async move {
-                    malai::expose_udp(host, port, id52, secret_key, graceful_clone).await
-                });
-            },
-        )
-        .await;
-    }
 }
 
-async fn set_up_tcp_udp_services(
-    conf: &Config,
-    used_id52: &mut HashSet<String>,
+struct Instance {
+    /// `None` for services without a port
+    port: Option<u16>,
+    /// `None` for bridges and proxies
+    id52: Option<String>,
+    health: supervise::SharedHealth,
+}
+
+/// The services started by `malai run`, shared with the control api (see `control.rs`).
+///
+/// every service runs on its own child of the main `Graceful`, so it can be stopped without
+/// touching the others.
+pub(crate) struct Services {
+    conf_path: std::path::PathBuf,
+    conf: tokio::sync::Mutex<Config>,
+    /// keyed by `<type>.<name>`
+    running: tokio::sync::Mutex<std::collections::BTreeMap<String, Running>>,
     graceful: kulfi_utils::Graceful,
-) {
-    if let Some(tcp_udp_conf) = &conf.tcp_udp {
-        process_services(
-            &tcp_udp_conf.services,
-            "TCP+UDP",
-            used_id52,
-            graceful.clone(),
-            |_service_conf, host, port, id52, secret_key, graceful_clone| {
-                graceful.spawn(async move {
-                    malai::expose_tcp_udp(host, port, id52, secret_key, graceful_clone).await
-                });
+}
+
+impl Services {
+    fn new(conf_path: &Path, conf: Config, graceful: kulfi_utils::Graceful) -> Self {
+        Self {
+            conf_path: conf_path.to_path_buf(),
+            conf: tokio::sync::Mutex::new(conf),
+            running: Default::default(),
+            graceful,
+        }
+    }
+
+    /// where the control api writes its address and token, next to malai.toml.
+    pub(crate) fn control_file(&self) -> std::path::PathBuf {
+        self.conf_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(malai::control::CONTROL_FILE)
+    }
+
+    /// starts all active services.
+    async fn start_all(&self) {
+        let names = self.conf.lock().await.service_names();
+        for name in names {
+            let active = self
+                .conf
+                .lock()
+                .await
+                .service(&name)
+                .is_some_and(|c| c.active());
+            if !active {
+                continue;
+            }
+            if let Err(e) = self.start(&name).await {
+                error!("{e} Skipping.");
+            }
+        }
+    }
+
+    /// starts the service `<type>.<name>`, even if it is not `active` in the config, or if it
+    /// failed too many times. returns once every port of it has bound, or failed to.
+    pub(crate) async fn start(&self, name: &str) -> eyre::Result<()> {
+        let mut running = self.running.lock().await;
+        if let Some(r) = running.get(name) {
+            // services the supervisor gave up on can be started again
+            if !r.instances.iter().all(|i| i.health.lock().unwrap().failed) {
+                return Err(eyre!("Service {name} is already running."));
+            }
+            if let Some(r) = running.remove(name) {
+                r.graceful.cancel();
+            }
+        }
+
+        let conf = self.conf.lock().await;
+        let service_conf = conf
+            .service(name)
+            .with_context(|| format!("No service named {name} in the config."))?;
+
+        info!("Starting service: {}", name);
+
+        // identities of other running services can not be reused
+        let mut used_id52: HashSet<String> = running
+            .values()
+            .flat_map(|r| r.instances.iter().filter_map(|i| i.id52.clone()))
+            .collect();
+
+        // services without a port are started once
+        let ports: Vec<Option<u16>> = if service_conf.port().is_empty() {
+            vec![None]
+        } else {
+            service_conf.port().iter().copied().map(Some).collect()
+        };
+
+        if let Some(identity_conf) = service_conf.identity_conf() {
+            if !service_conf.public() {
+                return Err(eyre!(
+                    "You have to set public or require_token to true for service {}.",
+                    name
+                ));
+            }
+            validate_identity_conf(identity_conf, ports.len(), name)?;
+        }
+
+        let graceful = self.graceful.child();
+        let max_restarts = conf.malai.max_restarts;
+        // the supervisors restart from their own copy, the config can be reloaded meanwhile
+        let service_conf_for_restart = service_conf.to_arc();
+        let mut instances = vec![];
+        let mut started = vec![];
+        let mut last_error = None;
+
+        for (i, port) in ports.into_iter().enumerate() {
+            let identity = match service_conf.identity_conf() {
+                Some(identity_conf) => {
+                    match load_identity(identity_conf, i, &mut used_id52).await {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            error!(
+                                "Failed to load identity for service {} port {:?}: {} Skipping.",
+                                name, port, e
+                            );
+                            last_error = Some(e);
+                            continue;
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            let instance = Instance {
+                port,
+                id52: identity.as_ref().map(|(id52, _)| id52.clone()),
+                health: Default::default(),
+            };
+
+            let service_conf = service_conf_for_restart.clone();
+            let graceful_for_start = graceful.clone();
+            let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+            graceful.spawn(supervise::supervise(
+                match port {
+                    Some(port) => format!("{name} port {port}"),
+                    None => name.to_string(),
+                },
+                max_restarts,
+                instance.health.clone(),
+                graceful.clone(),
+                started_tx,
+                move || service_conf.start(port, identity.clone(), graceful_for_start.clone()),
+            ));
+            started.push(started_rx);
+            instances.push(instance);
+        }
+
+        if instances.is_empty() {
+            return Err(last_error.unwrap_or_else(|| eyre!("Service {name} has no ports.")));
+        }
+
+        running.insert(
+            name.to_string(),
+            Running {
+                instances,
+                graceful,
             },
-        )
-        .await;
+        );
+        drop(conf);
+        drop(running);
+
+        // so `malai run` tells systemd it is ready only once the ports are bound
+        for started in started {
+            let _ = started.await;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn stop(&self, name: &str) -> eyre::Result<()> {
+        let r = self
+            .running
+            .lock()
+            .await
+            .remove(name)
+            .with_context(|| format!("Service {name} is not running."))?;
+        info!("Stopping service: {}", name);
+        r.graceful.cancel();
+        Ok(())
+    }
+
+    /// re-reads the config file and applies the difference: services whose section did not change
+    /// are left alone, removed ones are stopped, and added or changed ones are (re)started if
+    /// they are active. if the new config does not parse, nothing is touched.
+    pub(crate) async fn reload(&self) -> eyre::Result<()> {
+        let new = parse_config(&self.conf_path)?;
+        info!("Reloading {}", self.conf_path.display());
+
+        let plan = {
+            let running = self.running.lock().await;
+            let running: Vec<_> = running.keys().cloned().collect();
+            let old = self.conf.lock().await;
+            if old.malai != new.malai {
+                tracing::warn!("Changes to the [malai] section need a restart.");
+            }
+            plan_reload(&old, &new, &running)
+        };
+
+        // stopped first, so an id52 can move from one service to another
+        for name in plan.stop.iter() {
+            self.stop(name).await?;
+        }
+        *self.conf.lock().await = new;
+        for name in plan.start.iter() {
+            if let Err(e) = self.start(name).await {
+                error!("{e} Skipping.");
+            }
+        }
+
+        info!(
+            "Reloaded: {} stopped, {} started.",
+            plan.stop.len(),
+            plan.start.len()
+        );
+        Ok(())
+    }
+
+    pub(crate) async fn status(&self) -> Vec<malai::control::ServiceStatus> {
+        // same order as `start()`
+        let running = self.running.lock().await;
+        let conf = self.conf.lock().await;
+        conf.service_names()
+            .into_iter()
+            .filter_map(|name| {
+                let c = conf.service(&name)?;
+                let r = running.get(&name);
+                Some(malai::control::ServiceStatus {
+                    host: c.host().to_string(),
+                    ports: if c.port().is_empty() {
+                        vec![None]
+                    } else {
+                        c.port().iter().copied().map(Some).collect()
+                    }
+                    .into_iter()
+                    .map(|port| {
+                        let instance = r.and_then(|r| r.instances.iter().find(|i| i.port == port));
+                        let health = instance.map(|i| i.health.lock().unwrap());
+                        malai::control::PortStatus {
+                            port,
+                            id52: instance.and_then(|i| i.id52.clone()),
+                            restarts: health.as_ref().map_or(0, |h| h.restarts),
+                            last_error: health.as_ref().and_then(|h| h.last_error.clone()),
+                            failed: health.is_some_and(|h| h.failed),
+                        }
+                    })
+                    .collect(),
+                    active: c.active(),
+                    running: r.is_some(),
+                    name,
+                })
+            })
+            .collect()
     }
 }
 
@@ -594,15 +1322,30 @@ pub async fn run(conf_path: &Path, graceful: kulfi_utils::Graceful) {
         }
     };
 
-    let mut used_id52: HashSet<String> = HashSet::new();
+    // `--metrics-addr` wins over the config file
+    if let Some(addr) = conf.malai.metrics
+        && !kulfi_utils::metrics::is_enabled()
+        && let Err(e) = kulfi_utils::metrics::serve(addr, graceful.clone()).await
+    {
+        eprintln!("Failed to serve metrics: {e:#}. Skipping.");
+    }
+
+    let control = conf.malai.control;
+    let watch = conf.malai.watch;
+    let services = std::sync::Arc::new(Services::new(conf_path, conf, graceful.clone()));
 
-    set_up_http_services(&conf, &mut used_id52, graceful.clone()).await;
-    set_up_tcp_services(&conf, &mut used_id52, graceful.clone()).await;
-    set_up_udp_services(&conf, &mut used_id52, graceful.clone()).await;
-    set_up_tcp_udp_services(&conf, &mut used_id52, graceful.clone()).await;
+    if let Some(addr) = control
+        && let Err(e) = malai::control::serve(addr, services.clone(), graceful.clone()).await
+    {
+        eprintln!("Failed to start control api: {e:#}. Skipping.");
+    }
+
+    services.start_all().await;
+    reload_on_change(services, watch, graceful);
 }
 
 #[test]
+#[allow(clippy::unnecessary_get_then_check)]
 fn parse_config_test() {
     let conf = parse_config(Path::new("tests/http_example_conf.toml")).unwrap();
     println!("{:?}", conf);
@@ -643,6 +1386,47 @@ fn parse_config_test() {
     assert!(conf.udp.is_some());
     let udp = conf.udp.as_ref().expect("UDP services should be present");
     assert!(udp.services.get("service4").is_some());
+
+    let web = &conf.http_bridge.as_ref().unwrap().services["web"];
+    assert_eq!(web.port, 8080);
+    assert!(web.options.path_routing);
+    assert_eq!(web.options.cache, Some(malai::CacheBackend::Memory));
+    assert_eq!(
+        web.options.alias_min_prefix,
+        malai::DEFAULT_ALIAS_MIN_PREFIX
+    );
+
+    // http_proxy has no port set, so a random one is picked
+    assert_eq!(conf.http_proxy.as_ref().unwrap().services["out"].port, 0);
+
+    let services = conf.service_names();
+    assert!(services.contains(&"tcp_bridge.db".to_string()));
+    assert!(services.contains(&"http_proxy_remote.out".to_string()));
+    let folder = conf.service("folder.docs").unwrap();
+    assert!(folder.port().is_empty());
+    assert!(folder.identity_conf().is_some());
+    assert!(
+        conf.service("tcp_bridge.db")
+            .unwrap()
+            .identity_conf()
+            .is_none()
+    );
+}
+
+#[test]
+fn parse_config_unknown_key_test() {
+    let path = std::env::temp_dir().join(format!("malai-unknown-key-{}.toml", std::process::id()));
+    fs::write(
+        &path,
+        "[tcp.ssh]\nidentity = \"<id52>\"\nport = 22\npublic = true\nactive = true\nhots = \"x\"\n",
+    )
+    .unwrap();
+    let e = parse_config(&path).unwrap_err();
+    fs::remove_file(&path).unwrap();
+
+    let e = format!("{e:#}");
+    assert!(e.contains("unknown field `hots`"), "{e}");
+    assert!(e.contains("line 1, column 2"), "{e}");
 }
 
 #[test]
@@ -655,6 +1439,9 @@ fn validate_identity_conf_mismatched_count() {
             "id4".to_string(),
         ])),
         secret_file: None,
+        secret_env: None,
+        secret_command: None,
+        secret_credential: None,
     };
 
     let result = validate_identity_conf(&conf, 5, "test_service");
@@ -671,3 +1458,123 @@ fn validate_identity_conf_mismatched_count() {
         err_msg
     );
 }
+
+#[test]
+fn secret_stores_test() {
+    let conf: Config = toml::from_str(
+        r#"
+        [tcp.ssh]
+        secret_command = "pass show malai/ssh"
+        port = 22
+        public = true
+        active = true
+
+        [http.web]
+        secret_credential = ["web-a", "web-b"]
+        ports = [3000, 3001]
+        public = true
+        active = true
+
+        [http.both]
+        identity = "<id52>"
+        secret_env = "WEB_KEY"
+        port = 4000
+        public = true
+        active = true
+        "#,
+    )
+    .unwrap();
+
+    let ssh = conf.service("tcp.ssh").unwrap().identity_conf().unwrap();
+    let (key, value) = ssh.source().unwrap().unwrap();
+    assert_eq!(
+        (key, value.get(0)),
+        ("secret_command", Some("pass show malai/ssh"))
+    );
+    assert_eq!(
+        format!("{:?}", secret_store(key, value.get(0).unwrap())),
+        r#"CommandStore { command: "pass show malai/ssh" }"#
+    );
+
+    let web = conf.service("http.web").unwrap().identity_conf().unwrap();
+    assert!(validate_identity_conf(web, 2, "http.web").is_ok());
+    let e = validate_identity_conf(web, 3, "http.web").unwrap_err();
+    assert!(e.to_string().contains("2 secret_credential entries"));
+
+    let both = conf.service("http.both").unwrap().identity_conf().unwrap();
+    let e = validate_identity_conf(both, 1, "http.both").unwrap_err();
+    assert!(
+        format!("{e:#}").contains("both identity and secret_env are set"),
+        "{e:#}"
+    );
+}
+
+#[test]
+fn plan_reload_test() {
+    let old: Config = toml::from_str(
+        r#"
+        [tcp.ssh]
+        identity = "<id52-a>"
+        port = 22
+        public = true
+        active = true
+
+        [tcp.db]
+        identity = "<id52-b>"
+        port = 5432
+        public = true
+        active = true
+
+        [http.web]
+        identity = "<id52-c>"
+        port = 3000
+        public = true
+        active = true
+
+        [udp.game]
+        identity = "<id52-d>"
+        port = 27015
+        public = true
+        active = true
+        "#,
+    )
+    .unwrap();
+    let new: Config = toml::from_str(
+        r#"
+        [tcp.ssh]
+        identity = "<id52-a>"
+        port = 22
+        public = true
+        active = true
+
+        [tcp.db]
+        identity = "<id52-b>"
+        port = 5433
+        public = true
+        active = true
+
+        [http.docs]
+        identity = "<id52-c>"
+        port = 3001
+        public = true
+        active = true
+
+        [udp.game]
+        identity = "<id52-d>"
+        port = 27015
+        public = true
+        active = true
+        "#,
+    )
+    .unwrap();
+
+    // udp.game was stopped by hand, it is unchanged so stays stopped
+    let running = ["http.web", "tcp.db", "tcp.ssh"].map(String::from);
+    assert_eq!(
+        plan_reload(&old, &new, &running),
+        ReloadPlan {
+            stop: vec!["http.web".to_string(), "tcp.db".to_string()],
+            start: vec!["http.docs".to_string(), "tcp.db".to_string()],
+        }
+    );
+}
@@ -0,0 +1,290 @@
+//! `${VAR}` interpolation and `include` files in `malai.toml`.
+//!
+//! every string value can use `${VAR}`, replaced by the environment variable `VAR`, or
+//! `${VAR:-default}`, which uses `default` if `VAR` is not set or is empty. an undefined variable
+//! without a default is an error, we do not want to silently run with an empty identity or
+//! bridge. `$${` is a literal `${`.
+//!
+//! the top level `include` key takes a path or glob, or a list of them, relative to the file it
+//! is in:
+//!
+//! ```toml
+//! include = ["conf.d/*.toml"]
+//! ```
+//!
+//! included files are merged into the including file in order, glob matches sorted by name.
+//! tables are merged key by key, and for anything else the later file wins, so an included file
+//! can override a value from the file that includes it. included files can include other files.
+
+use eyre::WrapErr;
+use std::path::{Path, PathBuf};
+
+const INCLUDE: &str = "include";
+
+/// parses `content`, the content of `path`, interpolates it, and merges in the files it includes.
+/// the included files, and their content, are added to `included`.
+pub(super) fn expand(
+    content: &str,
+    path: &Path,
+    included: &mut Vec<(PathBuf, String)>,
+) -> eyre::Result<toml::Table> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    expand_(content, path, &mut vec![canonical], included)
+}
+
+/// the config file and all the files it includes, for watching. errors are ignored, they are
+/// reported when the config is parsed.
+pub(super) fn files(path: &Path) -> Vec<PathBuf> {
+    let mut included = vec![];
+    if let Ok(content) = std::fs::read_to_string(path) {
+        let _ = expand(&content, path, &mut included);
+    }
+    std::iter::once(path.to_path_buf())
+        .chain(included.into_iter().map(|(p, _)| p))
+        .collect()
+}
+
+fn expand_(
+    content: &str,
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    included: &mut Vec<(PathBuf, String)>,
+) -> eyre::Result<toml::Table> {
+    let mut table: toml::Table = toml::from_str(content)?;
+    for (key, value) in table.iter_mut() {
+        interpolate_value(value, key, &|name| std::env::var(name).ok())?;
+    }
+
+    let Some(include) = table.remove(INCLUDE) else {
+        return Ok(table);
+    };
+    let patterns: Vec<String> = match include {
+        toml::Value::String(pattern) => vec![pattern],
+        include => include.try_into().map_err(|_| {
+            eyre::anyhow!(
+                "{INCLUDE} should be a path or a list of paths in {}",
+                path.display()
+            )
+        })?,
+    };
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for pattern in patterns {
+        for file in matches(dir, &pattern)? {
+            let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+            if stack.contains(&canonical) {
+                return Err(eyre::anyhow!(
+                    "{} includes itself via {}",
+                    file.display(),
+                    path.display()
+                ));
+            }
+
+            let content = std::fs::read_to_string(&file)
+                .wrap_err_with(|| format!("failed to read included file {}", file.display()))?;
+            included.push((file.clone(), content.clone()));
+
+            stack.push(canonical);
+            let other = expand_(&content, &file, stack, included)
+                .wrap_err_with(|| format!("in included file {}", file.display()))?;
+            stack.pop();
+
+            merge(&mut table, other);
+        }
+    }
+
+    Ok(table)
+}
+
+/// the files matching `pattern`, sorted. a path without glob characters must exist.
+fn matches(dir: &Path, pattern: &str) -> eyre::Result<Vec<PathBuf>> {
+    let full = dir.join(pattern);
+    let full = full.to_string_lossy();
+
+    if !pattern.contains(['*', '?', '[']) {
+        let file = PathBuf::from(full.as_ref());
+        if !file.is_file() {
+            return Err(eyre::anyhow!("included file {full} does not exist"));
+        }
+        return Ok(vec![file]);
+    }
+
+    let mut files = glob::glob(&full)
+        .wrap_err_with(|| format!("invalid {INCLUDE} pattern {pattern}"))?
+        .collect::<Result<Vec<_>, _>>()?;
+    files.retain(|f| f.is_file());
+    files.sort();
+    Ok(files)
+}
+
+fn merge(base: &mut toml::Table, other: toml::Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(other)) => merge(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// interpolates every string in `value`, `at` is the dotted key, for errors.
+fn interpolate_value(
+    value: &mut toml::Value,
+    at: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> eyre::Result<()> {
+    match value {
+        toml::Value::String(s) => {
+            *s = interpolate(s, lookup).map_err(|e| eyre::anyhow!("{e} in {at}"))?;
+        }
+        toml::Value::Array(values) => {
+            for (i, v) in values.iter_mut().enumerate() {
+                interpolate_value(v, &format!("{at}[{i}]"), lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, v) in table.iter_mut() {
+                interpolate_value(v, &format!("{at}.{key}"), lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate(s: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if let Some(r) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = r;
+            continue;
+        }
+        let Some(r) = rest.strip_prefix("${") else {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let end = r
+            .find('}')
+            .ok_or_else(|| format!("unterminated ${{ in {s:?}"))?;
+        let (name, default) = match r[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&r[..end], None),
+        };
+
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("invalid variable name {name:?}"));
+        }
+
+        match (
+            lookup(name).filter(|v| !v.is_empty() || default.is_none()),
+            default,
+        ) {
+            (Some(v), _) => out.push_str(&v),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => return Err(format!("undefined variable {name}")),
+        }
+        rest = &r[end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("bridge.example.com".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn interpolate() {
+        let i = |s: &str| super::interpolate(s, &lookup);
+
+        assert_eq!(i("no variables").unwrap(), "no variables");
+        assert_eq!(i("${HOST}:8080").unwrap(), "bridge.example.com:8080");
+        assert_eq!(i("${PORT:-80}").unwrap(), "80");
+        assert_eq!(i("${HOST:-x}").unwrap(), "bridge.example.com");
+        assert_eq!(i("${EMPTY:-default}").unwrap(), "default");
+        assert_eq!(i("[${EMPTY}]").unwrap(), "[]");
+        assert_eq!(i("$5 and $${HOST}").unwrap(), "$5 and ${HOST}");
+        assert_eq!(i("${PORT}").unwrap_err(), "undefined variable PORT");
+        assert_eq!(i("${HOST").unwrap_err(), "unterminated ${ in \"${HOST\"");
+        assert_eq!(i("${1X}").unwrap_err(), "invalid variable name \"1X\"");
+    }
+
+    #[test]
+    fn interpolate_value() {
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            [http.web]
+            bridge = "${HOST}"
+            identity = ["a", "${MISSING}"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            super::interpolate_value(value.get_mut("http").unwrap(), "http", &lookup)
+                .unwrap_err()
+                .to_string(),
+            "undefined variable MISSING in http.web.identity[1]"
+        );
+    }
+
+    #[test]
+    fn include() {
+        let dir = std::env::temp_dir().join(format!("malai-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        std::fs::write(
+            dir.join("conf.d/10-web.toml"),
+            "[http.web]\nport = 3000\npublic = true\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("conf.d/20-prod.toml"), "[http.web]\nport = 80\n").unwrap();
+        std::fs::write(dir.join("conf.d/ignored.txt"), "not toml").unwrap();
+
+        let path = dir.join("malai.toml");
+        let content = "include = [\"conf.d/*.toml\"]\n[http.web]\nactive = true\nport = 1\n";
+        let mut included = vec![];
+        let table = super::expand(content, &path, &mut included).unwrap();
+        assert_eq!(
+            table,
+            toml::from_str("[http.web]\nactive = true\npublic = true\nport = 80\n").unwrap()
+        );
+        assert_eq!(
+            included.into_iter().map(|(p, _)| p).collect::<Vec<_>>(),
+            vec![
+                dir.join("conf.d/10-web.toml"),
+                dir.join("conf.d/20-prod.toml")
+            ]
+        );
+
+        std::fs::write(dir.join("loop.toml"), "include = \"malai.toml\"\n").unwrap();
+        std::fs::write(&path, "include = \"loop.toml\"\n").unwrap();
+        let e = super::expand("include = \"loop.toml\"\n", &path, &mut vec![]).unwrap_err();
+        assert!(format!("{e:#}").contains("includes itself"), "{e:#}");
+
+        assert!(
+            super::expand("include = \"missing.toml\"\n", &path, &mut vec![])
+                .unwrap_err()
+                .to_string()
+                .contains("does not exist")
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! restarts the services of `malai run` when they fail.
+//!
+//! every port of a service is supervised on its own. when it returns, with an error or not,
+//! without being stopped, it is restarted after a backoff that starts at a second and doubles up
+//! to a minute. a service that failed `max_restarts` times in a row is given up on, and shows up
+//! as `failed` in `malai ctl services` till it is started again. a run of a minute or longer
+//! counts as healthy, and resets both the backoff and the count.
+
+use futures_util::future::BoxFuture;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+pub(super) const DEFAULT_MAX_RESTARTS: u32 = 5;
+
+/// the state of one supervised port, shared with `Services::status`.
+#[derive(Debug, Default)]
+pub(super) struct Health {
+    /// since the service was started, or the config reloaded
+    pub(super) restarts: u32,
+    pub(super) last_error: Option<String>,
+    /// gave up after `max_restarts`
+    pub(super) failed: bool,
+}
+
+pub(super) type SharedHealth = Arc<Mutex<Health>>;
+
+/// runs the service `start()` starts till `graceful` is cancelled, restarting it when it stops.
+/// `started` is sent once the first `start()` returns, whether it bound or failed. `name` is only
+/// used in logs.
+pub(super) async fn supervise(
+    name: String,
+    max_restarts: u32,
+    health: SharedHealth,
+    graceful: kulfi_utils::Graceful,
+    started: tokio::sync::oneshot::Sender<()>,
+    start: impl Fn() -> BoxFuture<'static, eyre::Result<malai::ServiceHandle>>,
+) {
+    let mut backoff = MIN_BACKOFF;
+    let mut failures = 0;
+    let mut started = Some(started);
+
+    loop {
+        let started_at = std::time::Instant::now();
+        let handle = start().await;
+        if let Some(started) = started.take() {
+            let _ = started.send(());
+        }
+        let result = match handle {
+            Ok(mut handle) => {
+                tracing::info!(id52 = ?handle.id52(), addr = ?handle.local_addr(), "{name} started");
+                handle.wait().await
+            }
+            Err(e) => Err(e),
+        };
+        if graceful.is_cancelled() {
+            break;
+        }
+
+        let e = match result {
+            Ok(()) => eyre::anyhow!("stopped unexpectedly"),
+            Err(e) => e,
+        };
+
+        if started_at.elapsed() >= HEALTHY_AFTER {
+            backoff = MIN_BACKOFF;
+            failures = 0;
+        }
+        failures += 1;
+
+        {
+            let mut health = health.lock().unwrap();
+            health.last_error = Some(format!("{e:#}"));
+            if failures > max_restarts {
+                tracing::error!("{name} failed {failures} time(s) in a row, giving up: {e:#}");
+                health.failed = true;
+                break;
+            }
+            health.restarts += 1;
+        }
+
+        tracing::warn!("{name} failed: {e:#}, restarting in {backoff:?}");
+        tokio::select! {
+            _ = graceful.cancelled() => break,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        backoff = next_backoff(backoff);
+    }
+}
+
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn next_backoff() {
+        let mut backoff = super::MIN_BACKOFF;
+        let mut all = vec![];
+        for _ in 0..8 {
+            all.push(backoff.as_secs());
+            backoff = super::next_backoff(backoff);
+        }
+        assert_eq!(all, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[test]
+    fn gives_up() {
+        let health = super::SharedHealth::default();
+        let calls = std::sync::Arc::new(AtomicU32::new(0));
+
+        let calls_for_start = calls.clone();
+        let (started, mut started_rx) = tokio::sync::oneshot::channel();
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(super::supervise(
+                "tcp.test".to_string(),
+                0,
+                health.clone(),
+                kulfi_utils::Graceful::new(),
+                started,
+                move || {
+                    calls_for_start.fetch_add(1, Ordering::SeqCst);
+                    Box::pin(async { Err(eyre::anyhow!("failed to bind")) })
+                },
+            ));
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // a service that failed to start does not hold up the others
+        assert!(started_rx.try_recv().is_ok());
+        let health = health.lock().unwrap();
+        assert!(health.failed);
+        assert_eq!(health.restarts, 0);
+        assert_eq!(health.last_error.as_deref(), Some("failed to bind"));
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let health = super::SharedHealth::default();
+        let graceful = kulfi_utils::Graceful::new();
+
+        let graceful_for_start = graceful.clone();
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(super::supervise(
+                "tcp.test".to_string(),
+                5,
+                health.clone(),
+                graceful.clone(),
+                tokio::sync::oneshot::channel().0,
+                move || {
+                    let graceful = graceful_for_start.clone();
+                    Box::pin(async move {
+                        graceful.cancel();
+                        Err(eyre::anyhow!("failed to bind, as it was stopped"))
+                    })
+                },
+            ));
+
+        let health = health.lock().unwrap();
+        assert!(!health.failed);
+        assert_eq!(health.restarts, 0);
+        assert_eq!(health.last_error, None);
+    }
+}
@@ -0,0 +1,664 @@
+//! `malai validate`: checks a `malai.toml` without starting anything.
+//!
+//! `malai run` logs config problems and skips the affected services, which is easy to miss on a
+//! server. this reports all of them at once, with the line and column they are on, so it can be
+//! used to gate deployments. unknown keys and type errors stop `malai run` from starting at all, so
+//! the other checks are skipped if there are any.
+
+use super::{Config, IdentityConf, secret_store, validate_identity_conf};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Problem {
+    /// `None` for malai.toml itself, or the included file the problem is in
+    file: Option<PathBuf>,
+    /// 1 based, like editors
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+/// prints the problems in `conf_path`, returns `false` if there are any.
+pub fn validate(conf_path: &Path) -> bool {
+    let content = match std::fs::read_to_string(conf_path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: failed to read: {e}", conf_path.display());
+            return false;
+        }
+    };
+
+    let problems = check(&content, conf_path, true);
+    for p in problems.iter() {
+        eprintln!(
+            "{}:{}:{}: {}",
+            p.file.as_deref().unwrap_or(conf_path).display(),
+            p.line,
+            p.column,
+            p.message
+        );
+    }
+
+    if problems.is_empty() {
+        println!("{} is valid.", conf_path.display());
+        true
+    } else {
+        eprintln!("{} problem(s) found.", problems.len());
+        false
+    }
+}
+
+/// `content` is the content of `path`, the files it includes are read from disk.
+/// `load_identities` is off in tests, they have no keyring.
+pub(crate) fn check(content: &str, path: &Path, load_identities: bool) -> Vec<Problem> {
+    let root = match DeTable::parse(content) {
+        Ok(root) => root,
+        Err(e) => {
+            let (line, column) = line_column(content, e.span().unwrap_or_default().start);
+            return vec![Problem {
+                file: None,
+                line,
+                column,
+                message: e.message().to_string(),
+            }];
+        }
+    };
+
+    // syntax errors in included files are reported by `expand`
+    let mut included = vec![];
+    let expanded = super::expand::expand(content, path, &mut included);
+    let mut files = vec![(None, content, root)];
+    for (file, content) in included.iter() {
+        if let Ok(root) = DeTable::parse(content) {
+            files.push((Some(file.clone()), content.as_str(), root));
+        }
+    }
+
+    // span is in files[i]
+    let problem = |(i, span): (usize, Range<usize>), message: String| {
+        let (file, content, _) = &files[i];
+        let (line, column) = line_column(content, span.start);
+        Problem {
+            file: file.clone(),
+            line,
+            column,
+            message,
+        }
+    };
+
+    let mut problems = vec![];
+    let conf: Config = match expanded {
+        Ok(table) => match toml::Value::Table(table).try_into() {
+            Ok(conf) => conf,
+            Err(e) => {
+                // unknown keys and type errors are reported where they are, if the file they are
+                // in can be found
+                let at = super::locate_error(&e, files.iter().map(|(_, content, _)| *content))
+                    .map(|(i, e)| (i, e.span().unwrap_or_default()))
+                    .unwrap_or_default();
+                problems.push(problem(at, e.message().to_string()));
+                return problems;
+            }
+        },
+        Err(e) => {
+            problems.push(problem((0, 0..0), format!("{e:#}")));
+            return problems;
+        }
+    };
+
+    let service_in = |root: &Spanned<DeTable>, name: &str| {
+        let (kind, name) = name.split_once('.').unwrap_or_default();
+        get(root.get_ref(), kind)
+            .and_then(|v| v.get_ref().as_table())
+            .and_then(|t| get(t, name))
+            .map(|v| v.span())
+    };
+    let key_in = |root: &Spanned<DeTable>, name: &str, key: &str| {
+        let (kind, name) = name.split_once('.').unwrap_or_default();
+        get(root.get_ref(), kind)
+            .and_then(|v| v.get_ref().as_table())
+            .and_then(|t| get(t, name))
+            .and_then(|v| v.get_ref().as_table())
+            .and_then(|t| get(t, key))
+            .map(|v| v.span())
+    };
+    // where `key` of the service is set, the last file wins like when merging, or the service
+    // itself if `key` is not set
+    let span_of = |name: &str, key: Option<&str>| {
+        key.and_then(|key| {
+            files
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(i, (_, _, root))| Some((i, key_in(root, name, key)?)))
+        })
+        .or_else(|| {
+            files
+                .iter()
+                .enumerate()
+                .find_map(|(i, (_, _, root))| Some((i, service_in(root, name)?)))
+        })
+        .unwrap_or_default()
+    };
+
+    // (host, port, protocol) -> first service exposing it
+    let mut exposed: HashMap<(String, u16, &str), String> = HashMap::new();
+    // id52 -> first service using it
+    let mut used: HashMap<String, String> = HashMap::new();
+    // (port, protocol) -> first bridge or proxy listening on it
+    let mut listening: HashMap<(u16, &str), String> = HashMap::new();
+
+    for name in conf.service_names() {
+        let service = conf.service(&name).expect("name is from service_names");
+        let port_key = if files
+            .iter()
+            .any(|(_, _, root)| key_in(root, &name, "ports").is_some())
+        {
+            "ports"
+        } else {
+            "port"
+        };
+
+        if service.active() && !service.public() {
+            problems.push(problem(
+                span_of(&name, Some("public")),
+                format!("{name} is active but not public, malai run will skip it"),
+            ));
+        }
+
+        if let Some((key, bridge)) = bridge(&conf, &name)
+            && let Err(e) = check_bridge(bridge)
+        {
+            problems.push(problem(span_of(&name, Some(key)), e));
+        }
+
+        if let Some((key, id52)) = remote(&conf, &name)
+            && let Err(e) = kulfi_id52::PublicKey::from_str(id52)
+        {
+            problems.push(problem(
+                span_of(&name, Some(key)),
+                format!("{key} should be an id52: {e}"),
+            ));
+        }
+
+        for token in tokens(&conf, &name) {
+            if let Err(e) = kulfi_utils::AccessToken::verify(token) {
+                problems.push(problem(
+                    span_of(&name, Some("token")),
+                    format!("token is not valid: {e}"),
+                ));
+            }
+        }
+
+        // urls are only fetched when the bridge starts
+        if let Some(path) = name
+            .strip_prefix("http_bridge.")
+            .and_then(|n| {
+                conf.http_bridge
+                    .as_ref()?
+                    .services
+                    .get(n)?
+                    .options
+                    .auth
+                    .as_deref()
+            })
+            .filter(|p| !p.starts_with("http://") && !p.starts_with("https://"))
+            && let Err(e) = crate::http_bridge::check_auth_file(path)
+        {
+            problems.push(problem(
+                span_of(&name, Some("auth")),
+                format!("auth file is not valid: {e}"),
+            ));
+        }
+
+        if let Some(path) = name
+            .strip_prefix("folder.")
+            .and_then(|n| conf.folder.as_ref()?.services.get(n))
+            .map(|c| c.path.as_str())
+            && !Path::new(path).is_dir()
+        {
+            problems.push(problem(
+                span_of(&name, Some("path")),
+                format!("{path} is not a directory"),
+            ));
+        }
+
+        let Some(identity_conf) = service.identity_conf() else {
+            // bridges and proxies only listen locally
+            let protocol = if name.starts_with("udp_bridge.") {
+                "udp"
+            } else {
+                "tcp"
+            };
+            for &port in service.port().iter().filter(|p| **p != 0) {
+                match listening.get(&(port, protocol)) {
+                    Some(other) => problems.push(problem(
+                        span_of(&name, Some("port")),
+                        format!("port {port} ({protocol}) is used by both {other} and {name}"),
+                    )),
+                    None => {
+                        listening.insert((port, protocol), name.clone());
+                    }
+                }
+            }
+            continue;
+        };
+
+        // services without a port have one identity
+        let identities = service.port().len().max(1);
+        if let Err(e) = validate_identity_conf(identity_conf, identities, &name) {
+            let key = match identity_conf.source() {
+                Ok(Some((key, _))) => key,
+                _ => "identity",
+            };
+            problems.push(problem(span_of(&name, Some(key)), format!("{e}")));
+        }
+
+        let protocols: &[&str] = match name.split_once('.').map(|(kind, _)| kind) {
+            Some("udp") => &["udp"],
+            Some("tcp_udp") => &["tcp", "udp"],
+            _ => &["tcp"],
+        };
+        for &port in service.port() {
+            for protocol in protocols {
+                let key = (service.host().to_string(), port, *protocol);
+                match exposed.get(&key) {
+                    Some(other) if *other == name => problems.push(problem(
+                        span_of(&name, Some(port_key)),
+                        format!("{name} lists port {port} more than once"),
+                    )),
+                    Some(other) => problems.push(problem(
+                        span_of(&name, Some(port_key)),
+                        format!(
+                            "{}:{port} ({protocol}) is exposed by both {other} and {name}",
+                            service.host()
+                        ),
+                    )),
+                    None => {
+                        exposed.insert(key, name.clone());
+                    }
+                }
+            }
+        }
+
+        for i in 0..identities {
+            let (key, id52) = match identity(identity_conf, i, load_identities) {
+                Ok(v) => v,
+                Err((key, e)) => {
+                    problems.push(problem(span_of(&name, key), e));
+                    continue;
+                }
+            };
+            let Some(id52) = id52 else {
+                continue;
+            };
+            match used.get(&id52) {
+                Some(other) => problems.push(problem(
+                    span_of(&name, Some(key)),
+                    format!("identity {id52} is used by both {other} and {name}"),
+                )),
+                None => {
+                    used.insert(id52, name.clone());
+                }
+            }
+        }
+    }
+
+    problems.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+    problems.dedup();
+    problems
+}
+
+/// the id52 of port `i`, and the key it came from. without `load`, only the id52 of an
+/// `identity` is known.
+#[allow(clippy::type_complexity)]
+fn identity(
+    conf: &IdentityConf,
+    i: usize,
+    load: bool,
+) -> Result<(&'static str, Option<String>), (Option<&'static str>, String)> {
+    let (key, values) = match conf.source() {
+        Ok(Some(source)) => source,
+        Ok(None) => {
+            return Err((
+                None,
+                "no identity specified, set identity, secret_file, secret_env, secret_command \
+                 or secret_credential"
+                    .to_string(),
+            ));
+        }
+        // reported by `validate_identity_conf`
+        Err(_) => return Ok(("identity", None)),
+    };
+    // count mismatches are reported by `validate_identity_conf`
+    let Some(value) = values.get(i) else {
+        return Ok((key, None));
+    };
+    if !load {
+        return Ok((key, (key == "identity").then(|| value.to_string())));
+    }
+    secret_store(key, value)
+        .load()
+        .map(|(id52, _)| (key, Some(id52)))
+        .map_err(|e| (Some(key), format!("failed to load {key} {value}: {e:#}")))
+}
+
+/// the `bridge` of `http` and `folder` services, and the key it is set with.
+fn bridge<'a>(conf: &'a Config, name: &str) -> Option<(&'static str, &'a str)> {
+    let (kind, name) = name.split_once('.')?;
+    let bridge = match kind {
+        "http" => &conf.http.as_ref()?.services.get(name)?.bridge,
+        "folder" => &conf.folder.as_ref()?.services.get(name)?.bridge,
+        _ => return None,
+    };
+    Some(("bridge", bridge))
+}
+
+/// the id52 bridges and proxies connect to, and the key it is set with.
+fn remote<'a>(conf: &'a Config, name: &str) -> Option<(&'static str, &'a str)> {
+    let (kind, name) = name.split_once('.')?;
+    match kind {
+        "tcp_bridge" => Some((
+            "proxy_target",
+            &conf.tcp_bridge.as_ref()?.services.get(name)?.proxy_target,
+        )),
+        "udp_bridge" => Some((
+            "proxy_target",
+            &conf.udp_bridge.as_ref()?.services.get(name)?.proxy_target,
+        )),
+        "http_bridge" => Some((
+            "proxy_target",
+            conf.http_bridge
+                .as_ref()?
+                .services
+                .get(name)?
+                .proxy_target
+                .as_deref()?,
+        )),
+        "http_proxy" => Some((
+            "remote",
+            &conf.http_proxy.as_ref()?.services.get(name)?.remote,
+        )),
+        _ => None,
+    }
+}
+
+/// the access tokens bridges present.
+fn tokens<'a>(conf: &'a Config, name: &str) -> Vec<&'a str> {
+    let Some((kind, name)) = name.split_once('.') else {
+        return vec![];
+    };
+    let tokens = match kind {
+        "tcp_bridge" => conf
+            .tcp_bridge
+            .as_ref()
+            .and_then(|s| s.services.get(name)?.token.as_deref()),
+        "udp_bridge" => conf
+            .udp_bridge
+            .as_ref()
+            .and_then(|s| s.services.get(name)?.token.as_deref()),
+        "http_bridge" => {
+            return conf
+                .http_bridge
+                .as_ref()
+                .and_then(|s| s.services.get(name))
+                .map(|c| c.options.token.iter().map(String::as_str).collect())
+                .unwrap_or_default();
+        }
+        _ => None,
+    };
+    tokens.into_iter().collect()
+}
+
+/// the bridge is a host, with an optional port, like `bridge.example.com`.
+fn check_bridge(bridge: &str) -> Result<(), String> {
+    if bridge.is_empty() {
+        return Ok(());
+    }
+    if bridge.contains("://") || bridge.contains('/') {
+        return Err(format!(
+            "bridge should be a host like bridge.example.com, not a url: {bridge}"
+        ));
+    }
+    let host = match bridge.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_err() => {
+            return Err(format!("invalid port in bridge {bridge}"));
+        }
+        Some((host, _)) => host,
+        None => bridge,
+    };
+    let valid = !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if !valid {
+        return Err(format!("invalid host in bridge {bridge}"));
+    }
+    Ok(())
+}
+
+fn get<'a, 'i>(table: &'a DeTable<'i>, key: &str) -> Option<&'a Spanned<DeValue<'i>>> {
+    table
+        .iter()
+        .find(|(k, _)| k.get_ref() == key)
+        .map(|(_, v)| v)
+}
+
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod test {
+    fn messages(content: &str) -> Vec<String> {
+        super::check(content, std::path::Path::new("malai.toml"), false)
+            .into_iter()
+            .map(|p| format!("{}:{}: {}", p.line, p.column, p.message))
+            .collect()
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(
+            messages(
+                r#"
+[malai]
+log = "/var/log/malai.log"
+
+[http.web]
+identity = "<id52-a>"
+port = 3000
+public = true
+active = true
+bridge = "bridge.example.com:8080"
+
+[tcp_udp.game]
+identity = ["<id52-b>", "<id52-c>"]
+port = [4000, 4001]
+public = true
+active = true
+"#
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn problems() {
+        assert_eq!(
+            messages(
+                r#"[malai]
+log = "x"
+
+[http.web]
+identity = "<id52-a>"
+port = 3000
+public = false
+active = true
+bridge = "https://bridge.example.com"
+
+[tcp.ssh]
+identity = "<id52-a>"
+port = [22, 3000, 22]
+public = true
+active = true
+host = "127.0.0.1"
+
+[udp.game]
+port = 27015
+public = true
+active = true
+"#
+            ),
+            vec![
+                "7:10: http.web is active but not public, malai run will skip it",
+                "9:10: bridge should be a host like bridge.example.com, not a url: https://bridge.example.com",
+                "12:12: Service 'tcp.ssh' has 3 ports but only a single identity. \
+                 Provide an array of 3 identity entries, one per port.",
+                "12:12: identity <id52-a> is used by both http.web and tcp.ssh",
+                "13:8: 127.0.0.1:3000 (tcp) is exposed by both http.web and tcp.ssh",
+                "13:8: tcp.ssh lists port 22 more than once",
+                "18:1: no identity specified, set identity, secret_file, secret_env, secret_command or secret_credential",
+            ]
+        );
+    }
+
+    #[test]
+    fn bridges_and_proxies() {
+        assert_eq!(
+            messages(
+                r#"[tcp_bridge.db]
+proxy_target = "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60"
+port = 5432
+active = true
+token = "garbage"
+
+[http_bridge.web]
+proxy_target = "not-an-id52"
+port = 5432
+active = true
+path_routing = true
+cache = "memory"
+cache_dir = "/tmp"
+auth = "/does/not/exist.toml"
+
+[http_proxy.out]
+remote = "i66fo538lfl5ombdf6tcdbrabp4hmp9asv7nrffuc2im13ct4q60"
+active = true
+
+[http_proxy_remote.out]
+public = false
+active = true
+
+[folder.docs]
+identity = "<id52-a>"
+path = "/does/not/exist"
+public = true
+active = true
+"#
+            ),
+            vec![
+                "3:8: port 5432 (tcp) is used by both http_bridge.web and tcp_bridge.db",
+                "5:9: token is not valid: not an access token",
+                "8:16: proxy_target should be an id52: Invalid ID52 'not-an-id52': invalid BASE32_DNSSEC encoding: invalid length at 10",
+                "14:8: auth file is not valid: can not read /does/not/exist.toml",
+                "20:1: no identity specified, set identity, secret_file, secret_env, secret_command or secret_credential",
+                "21:10: http_proxy_remote.out is active but not public, malai run will skip it",
+                "26:8: /does/not/exist is not a directory",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(
+            messages("[malai]\nlogs = \"x\"\n"),
+            vec![
+                "2:1: unknown field `logs`, expected one of `log`, `metrics`, `control`, `watch`, \
+                 `max_restarts`"
+                    .to_string()
+            ]
+        );
+        assert_eq!(
+            messages(
+                "[tcp.ssh]\nidentity = \"<id52-a>\"\nport = 22\npublic = true\nactive = true\nhots = \"x\"\n"
+            ),
+            // like type errors, only the table is known for fields of flattened tables
+            vec!["1:2: unknown field `hots`".to_string()]
+        );
+    }
+
+    #[test]
+    fn include() {
+        let dir = std::env::temp_dir().join(format!("malai-validate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let web = dir.join("web.toml");
+        let check = |web_content: &str| {
+            std::fs::write(&web, web_content).unwrap();
+            super::check(
+                "include = \"web.toml\"\n\n[tcp.ssh]\nidentity = \"<id52-a>\"\nport = 3000\npublic = true\nactive = true\n",
+                &dir.join("malai.toml"),
+                false,
+            )
+            .into_iter()
+            .map(|p| (p.file, p.line, p.column, p.message))
+            .collect::<Vec<_>>()
+        };
+        let web_content =
+            "[http.web]\nidentity = \"<id52-b>\"\nport = 3000\npublic = true\nactive = true\n";
+        let conflict = check(web_content);
+        let typo = check(&format!("{web_content}hots = \"x\"\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            conflict,
+            vec![(
+                None,
+                5,
+                8,
+                "127.0.0.1:3000 (tcp) is exposed by both http.web and tcp.ssh".to_string()
+            )]
+        );
+        assert_eq!(
+            typo,
+            vec![(Some(web), 1, 2, "unknown field `hots`".to_string())]
+        );
+    }
+
+    #[test]
+    fn undefined_variable() {
+        assert_eq!(
+            messages("[http.web]\nidentity = \"${MALAI_VALIDATE_TEST_UNDEFINED}\"\n"),
+            vec![
+                "1:1: undefined variable MALAI_VALIDATE_TEST_UNDEFINED in http.web.identity"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_error() {
+        assert_eq!(
+            messages("[http.web]\nport = 3000\nport = 3001\n"),
+            vec!["3:1: duplicate key".to_string()]
+        );
+        assert_eq!(
+            messages("[http.web]\nport = \"x\"\npublic = true\nactive = true\n"),
+            // serde does not keep the span of fields in flattened tables, so only the table is known
+            vec![
+                "1:2: invalid type: string \"x\", expected a port number or a list of port numbers"
+                    .to_string()
+            ]
+        );
+    }
+}
@@ -1,15 +1,70 @@
-pub async fn tcp_bridge(port: u16, proxy_target: String, graceful: kulfi_utils::Graceful) {
+pub async fn tcp_bridge(
+    port: u16,
+    proxy_target: String,
+    token: Option<String>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    let mut handle = start_tcp_bridge(
+        malai::BridgeConfig {
+            port,
+            proxy_target,
+            token,
+        },
+        graceful,
+    )
+    .await?;
+    kulfi_utils::systemd::notify_ready();
+    if let Some(local_addr) = handle.local_addr() {
+        println!("Listening on {local_addr}");
+    }
+    handle.wait().await
+}
+
+/// listens on the local port, and forwards the connections to the tcp service of the peer,
+/// without printing anything.
+pub async fn start_tcp_bridge(
+    config: malai::BridgeConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
     use eyre::WrapErr;
 
-    let listener = tokio::net::TcpListener::bind(format!("127.0.0.1:{port}"))
+    let tokens = crate::token::Tokens::new(
+        config.token.as_slice(),
+        kulfi_utils::Protocol::Tcp,
+        Some(&config.proxy_target),
+    )
+    .await?;
+    let port = config.port;
+    let listener = kulfi_utils::systemd::listen_tcp(port)
         .await
         .wrap_err_with(|| {
             format!("can not listen to port {port}, is it busy, or you do not have root access?")
-        })
-        .unwrap();
+        })?;
+    let local_addr = listener
+        .local_addr()
+        .wrap_err("failed to get local address")?;
 
-    println!("Listening on 127.0.0.1:{port}");
+    let graceful = graceful.child();
+    let task = graceful.spawn(serve(
+        listener,
+        config.proxy_target,
+        tokens,
+        graceful.clone(),
+    ));
+    Ok(malai::ServiceHandle::new(
+        None,
+        Some(local_addr),
+        graceful,
+        task,
+    ))
+}
 
+async fn serve(
+    listener: tokio::net::TcpListener,
+    proxy_target: String,
+    tokens: Option<crate::token::Tokens>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
     let peer_connections = kulfi_utils::PeerStreamSenders::default();
 
     loop {
@@ -20,13 +75,14 @@ pub async fn tcp_bridge(port: u16, proxy_target: String, graceful: kulfi_utils::
             }
             val = listener.accept() => {
                 tracing::info!("got connection");
-                let self_endpoint = kulfi_utils::global_iroh_endpoint().await;
+                let self_endpoint = crate::token::self_endpoint(tokens.as_ref()).await;
+                let header = crate::token::header(tokens.as_ref(), kulfi_utils::Protocol::Tcp, &proxy_target);
                 let graceful_for_handle_connection = graceful.clone();
                 let peer_connections = peer_connections.clone();
                 let proxy_target = proxy_target.clone();
                 match val {
                     Ok((stream, _addr)) => {
-                        graceful.spawn(async move { handle_connection(self_endpoint, stream, graceful_for_handle_connection, peer_connections, proxy_target).await });
+                        graceful.spawn(async move { handle_connection(self_endpoint, header, stream, graceful_for_handle_connection, peer_connections, proxy_target).await });
                     },
                     Err(e) => {
                         tracing::error!("failed to accept: {e:?}");
@@ -35,18 +91,21 @@ pub async fn tcp_bridge(port: u16, proxy_target: String, graceful: kulfi_utils::
             }
         }
     }
+
+    Ok(())
 }
 
 pub async fn handle_connection(
     self_endpoint: iroh::Endpoint,
+    header: kulfi_utils::ProtocolHeader,
     stream: tokio::net::TcpStream,
     graceful: kulfi_utils::Graceful,
     peer_connections: kulfi_utils::PeerStreamSenders,
     remote_node_id52: String,
 ) {
-    println!("forwarding tcp connection to {remote_node_id52}");
+    tracing::info!("forwarding tcp connection to {remote_node_id52}");
     if let Err(e) = kulfi_utils::tcp_to_peer(
-        kulfi_utils::Protocol::Tcp.into(),
+        header,
         self_endpoint,
         stream,
         &remote_node_id52,
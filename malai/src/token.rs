@@ -0,0 +1,178 @@
+/// the protocols an access token can be for, `--protocol` of `malai token create`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TokenProtocol {
+    Http,
+    Tcp,
+    Udp,
+}
+
+impl From<TokenProtocol> for kulfi_utils::Protocol {
+    fn from(protocol: TokenProtocol) -> Self {
+        match protocol {
+            TokenProtocol::Http => kulfi_utils::Protocol::Http,
+            TokenProtocol::Tcp => kulfi_utils::Protocol::Tcp,
+            TokenProtocol::Udp => kulfi_utils::Protocol::Udp,
+        }
+    }
+}
+
+/// signs, with `identity`, or the one `malai http` would use, an access token letting `peer` use
+/// `protocol` of the service till `expires`, an RFC 3339 time, or for `valid_for` seconds.
+pub async fn create_token(
+    peer: String,
+    protocol: TokenProtocol,
+    identity: Option<String>,
+    expires: Option<String>,
+    valid_for: u64,
+) -> eyre::Result<()> {
+    use eyre::WrapErr;
+
+    let _: kulfi_id52::PublicKey = peer
+        .parse()
+        .wrap_err_with(|| format!("{peer} is not an id52"))?;
+    let (id52, secret_key) = crate::identity::load_identity(identity, None).await?;
+    let expires = match expires {
+        Some(expires) => {
+            let expires = chrono::DateTime::parse_from_rfc3339(&expires)
+                .wrap_err_with(|| format!("--expires {expires} is not like 2026-01-31T12:00:00Z"))?
+                .timestamp();
+            u64::try_from(expires).map_err(|_| eyre::anyhow!("--expires is before 1970"))?
+        }
+        None => now().saturating_add(valid_for),
+    };
+
+    let token = kulfi_utils::AccessToken {
+        service: id52.clone(),
+        peer: peer.clone(),
+        protocol: protocol.into(),
+        expires,
+    }
+    .sign(&secret_key)?;
+
+    eprintln!(
+        "Access token for {peer} to use {protocol:?} of {id52} till {}. It only works for \
+         services started with --require-token, and can not be taken back before it expires:",
+        crate::identity::format_unix(expires)
+    );
+    println!("{token}");
+    Ok(())
+}
+
+/// prints what `token` grants, after checking its signature.
+pub fn show_token(token: String) -> eyre::Result<()> {
+    let token = kulfi_utils::AccessToken::verify(&token)?;
+    println!("Service: {}", token.service);
+    println!("Peer: {}", token.peer);
+    println!("Protocol: {:?}", token.protocol);
+    let state = if token.expires > now() {
+        "valid"
+    } else {
+        "expired"
+    };
+    println!(
+        "Expires: {} ({state})",
+        crate::identity::format_unix(token.expires)
+    );
+    Ok(())
+}
+
+/// the access tokens a bridge presents, by the id52 of the service they are for, and the
+/// endpoint of the identity they are issued to. tokens are useless to other identities, so the
+/// bridge connects as that identity, which must be on this machine.
+#[derive(Clone)]
+pub(crate) struct Tokens {
+    tokens: std::collections::HashMap<String, String>,
+    endpoint: iroh::Endpoint,
+}
+
+impl Tokens {
+    /// `None` if there are no tokens. they must all be for `protocol`, for the same peer, and,
+    /// if it is known, for `target`.
+    pub(crate) async fn new(
+        tokens: &[String],
+        protocol: kulfi_utils::Protocol,
+        target: Option<&str>,
+    ) -> eyre::Result<Option<Self>> {
+        use eyre::WrapErr;
+
+        let mut peer = None;
+        let mut by_service = std::collections::HashMap::new();
+        for token in tokens {
+            let claims = kulfi_utils::AccessToken::verify(token)?;
+            if claims.protocol != protocol {
+                return Err(eyre::anyhow!(
+                    "the token for {} is for {:?}, not {protocol:?}",
+                    claims.service,
+                    claims.protocol
+                ));
+            }
+            if let Some(target) = target
+                && claims.service != target
+            {
+                return Err(eyre::anyhow!(
+                    "the token is for {}, not {target}",
+                    claims.service
+                ));
+            }
+            if claims.expires <= now() {
+                tracing::warn!(service = claims.service, "the access token has expired");
+            }
+            match &peer {
+                Some(peer) if peer != &claims.peer => {
+                    return Err(eyre::anyhow!(
+                        "the tokens are for both {peer} and {}, one bridge can only use tokens \
+                         of one identity",
+                        claims.peer
+                    ));
+                }
+                Some(_) => {}
+                None => peer = Some(claims.peer.clone()),
+            }
+            by_service.insert(claims.service, token.trim().to_string());
+        }
+
+        let Some(peer) = peer else {
+            return Ok(None);
+        };
+        let dir = kulfi_utils::dot_kulfi::dir()?;
+        let secret_key =
+            kulfi_utils::get_secret_key(&peer, &dir.to_string_lossy()).wrap_err_with(|| {
+                format!("the token is for {peer}, its identity is not on this machine")
+            })?;
+        let endpoint = kulfi_utils::get_endpoint(secret_key)
+            .await
+            .wrap_err("failed to bind to iroh network")?;
+
+        Ok(Some(Self {
+            tokens: by_service,
+            endpoint,
+        }))
+    }
+}
+
+/// the endpoint to connect from, the one of the identity the tokens are for if there are any.
+pub(crate) async fn self_endpoint(tokens: Option<&Tokens>) -> iroh::Endpoint {
+    match tokens {
+        Some(tokens) => tokens.endpoint.clone(),
+        None => kulfi_utils::global_iroh_endpoint().await,
+    }
+}
+
+/// the stream header for `protocol` of `service`, with the token for it if there is one.
+pub(crate) fn header(
+    tokens: Option<&Tokens>,
+    protocol: kulfi_utils::Protocol,
+    service: &str,
+) -> kulfi_utils::ProtocolHeader {
+    kulfi_utils::ProtocolHeader {
+        protocol,
+        extra: None,
+        token: tokens.and_then(|t| t.tokens.get(service).cloned()),
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
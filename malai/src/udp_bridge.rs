@@ -3,23 +3,79 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-pub async fn udp_bridge(port: u16, proxy_target: String, graceful: kulfi_utils::Graceful) {
-    use eyre::WrapErr;
+pub async fn udp_bridge(
+    port: u16,
+    proxy_target: String,
+    token: Option<String>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    let mut handle = start_udp_bridge(
+        malai::BridgeConfig {
+            port,
+            proxy_target,
+            token,
+        },
+        graceful,
+    )
+    .await?;
+    kulfi_utils::systemd::notify_ready();
+    if let Some(local_addr) = handle.local_addr() {
+        println!("Listening on UDP {local_addr}");
+    }
+    handle.wait().await
+}
 
-    let socket = Arc::new(
-        tokio::net::UdpSocket::bind(format!("127.0.0.1:{port}"))
-            .await
-            .wrap_err_with(|| {
-                format!(
-                    "can not listen to UDP port {port}, is it busy, or you do not have root access?"
-                )
-            })
-            .unwrap(),
-    );
+/// listens on the local udp port, and forwards the datagrams to the udp service of the peer,
+/// without printing anything.
+pub async fn start_udp_bridge(
+    config: malai::BridgeConfig,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<malai::ServiceHandle> {
+    use eyre::WrapErr;
 
-    let local_addr = socket.local_addr().unwrap();
-    println!("Listening on UDP {local_addr}");
+    let tokens = crate::token::Tokens::new(
+        config.token.as_slice(),
+        kulfi_utils::Protocol::Udp,
+        Some(&config.proxy_target),
+    )
+    .await?;
+    let port = config.port;
+    let socket = kulfi_utils::systemd::listen_udp(port)
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "can not listen to UDP port {port}, is it busy, or you do not have root access?"
+            )
+        })?;
+    let local_addr = socket
+        .local_addr()
+        .wrap_err("failed to get local address")?;
+
+    let graceful = graceful.child();
+    let task = graceful.spawn(serve(
+        Arc::new(socket),
+        config.proxy_target,
+        tokens,
+        graceful.clone(),
+    ));
+    Ok(malai::ServiceHandle::new(
+        None,
+        Some(local_addr),
+        graceful,
+        task,
+    ))
+}
 
+async fn serve(
+    socket: Arc<tokio::net::UdpSocket>,
+    proxy_target: String,
+    tokens: Option<crate::token::Tokens>,
+    graceful: kulfi_utils::Graceful,
+) -> eyre::Result<()> {
+    let target = Target {
+        id52: proxy_target,
+        tokens,
+    };
     let peer_connections = kulfi_utils::PeerStreamSenders::default();
     // Track active sessions: client_addr -> sender channel for forwarding datagrams
     let sessions: Arc<Mutex<HashMap<SocketAddr, tokio::sync::mpsc::Sender<Vec<u8>>>>> =
@@ -48,7 +104,7 @@ pub async fn udp_bridge(port: u16, proxy_target: String, graceful: kulfi_utils::
                                     socket.clone(),
                                     client_addr,
                                     data.clone(),
-                                    proxy_target.clone(),
+                                    target.clone(),
                                     peer_connections.clone(),
                                     sessions.clone(),
                                     graceful.clone(),
@@ -60,7 +116,7 @@ pub async fn udp_bridge(port: u16, proxy_target: String, graceful: kulfi_utils::
                                 socket.clone(),
                                 client_addr,
                                 data,
-                                proxy_target.clone(),
+                                target.clone(),
                                 peer_connections.clone(),
                                 sessions.clone(),
                                 graceful.clone(),
@@ -74,13 +130,22 @@ pub async fn udp_bridge(port: u16, proxy_target: String, graceful: kulfi_utils::
             }
         }
     }
+
+    Ok(())
+}
+
+/// the peer the sessions are forwarded to, and the access token to present to it.
+#[derive(Clone)]
+struct Target {
+    id52: String,
+    tokens: Option<crate::token::Tokens>,
 }
 
 async fn start_session(
     socket: Arc<tokio::net::UdpSocket>,
     client_addr: SocketAddr,
     initial_data: Vec<u8>,
-    remote_node_id52: String,
+    target: Target,
     peer_connections: kulfi_utils::PeerStreamSenders,
     sessions: Arc<Mutex<HashMap<SocketAddr, tokio::sync::mpsc::Sender<Vec<u8>>>>>,
     graceful: kulfi_utils::Graceful,
@@ -94,10 +159,18 @@ async fn start_session(
 
     let graceful_for_session = graceful.clone();
     graceful.spawn(async move {
-        println!("forwarding UDP datagrams to {remote_node_id52}");
-
-        let self_endpoint = kulfi_utils::global_iroh_endpoint().await;
-        let header = kulfi_utils::ProtocolHeader::from(kulfi_utils::Protocol::Udp);
+        let Target {
+            id52: remote_node_id52,
+            tokens,
+        } = target;
+        tracing::info!("forwarding UDP datagrams to {remote_node_id52}");
+
+        let self_endpoint = crate::token::self_endpoint(tokens.as_ref()).await;
+        let header = crate::token::header(
+            tokens.as_ref(),
+            kulfi_utils::Protocol::Udp,
+            &remote_node_id52,
+        );
 
         let result = async {
             let (mut send, mut recv) = kulfi_utils::get_stream(
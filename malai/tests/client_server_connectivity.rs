@@ -58,7 +58,16 @@ async fn test_tcp_echo_connection_inner() {
     let expose_host = "127.0.0.1".to_string();
 
     let expose_handle = tokio::spawn(async move {
-        malai::expose_tcp(expose_host, echo_port, expose_id52, secret, expose_graceful).await;
+        malai::expose_tcp(
+            expose_host,
+            echo_port,
+            expose_id52,
+            secret,
+            false,
+            expose_graceful,
+        )
+        .await
+        .unwrap();
     });
 
     // Give server time to start
@@ -128,6 +137,122 @@ async fn test_tcp_echo_connection_inner() {
 
     // Cleanup: best-effort, don't block the test
     drop(stream);
+    graceful.cancel();
+    let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
+    echo_handle.abort();
+    expose_handle.abort();
+    bridge_handle.abort();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tcp_token_to_open_service() {
+    tokio::time::timeout(TEST_TIMEOUT, test_tcp_token_to_open_service_inner())
+        .await
+        .expect("test_tcp_token_to_open_service timed out");
+}
+
+/// a bridge with `--token` talking to a service without `--require-token`, the token must not
+/// end up in the service's stream.
+async fn test_tcp_token_to_open_service_inner() {
+    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+
+    let (id52, secret) = create_test_identity();
+    let token = kulfi_utils::AccessToken {
+        service: id52.clone(),
+        peer: create_test_identity().0,
+        protocol: kulfi_utils::Protocol::Tcp,
+        expires: u64::MAX,
+    }
+    .sign(&secret)
+    .expect("failed to sign token");
+
+    let echo_listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind echo server");
+    let echo_port = echo_listener.local_addr().unwrap().port();
+
+    let echo_handle = tokio::spawn(async move {
+        if let Ok((mut socket, _)) = echo_listener.accept().await {
+            let mut buf = vec![0u8; 1024];
+            if let Ok(n) = socket.read(&mut buf).await {
+                let _ = socket.write_all(&buf[..n]).await;
+            }
+        }
+    });
+
+    let graceful = kulfi_utils::Graceful::new();
+    let expose_graceful = graceful.clone();
+    let expose_id52 = id52.clone();
+    let expose_handle = tokio::spawn(async move {
+        malai::expose_tcp(
+            "127.0.0.1".to_string(),
+            echo_port,
+            expose_id52,
+            secret,
+            false,
+            expose_graceful,
+        )
+        .await
+        .unwrap();
+    });
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let bridge_listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind bridge");
+    let bridge_port = bridge_listener.local_addr().unwrap().port();
+
+    let bridge_id52 = id52.clone();
+    let bridge_graceful = graceful.clone();
+    let bridge_handle = tokio::spawn(async move {
+        if let Ok((local_stream, _)) = bridge_listener.accept().await {
+            let endpoint = iroh::Endpoint::builder()
+                .discovery(iroh::discovery::pkarr::PkarrPublisher::n0_dns())
+                .discovery(iroh::discovery::dns::DnsDiscovery::n0_dns())
+                .discovery(iroh::discovery::mdns::MdnsDiscovery::builder())
+                .alpns(vec![kulfi_utils::APNS_IDENTITY.into()])
+                .bind()
+                .await
+                .expect("failed to create bridge iroh endpoint");
+            let peer_connections = kulfi_utils::PeerStreamSenders::default();
+
+            let _ = kulfi_utils::tcp_to_peer(
+                kulfi_utils::ProtocolHeader {
+                    protocol: kulfi_utils::Protocol::Tcp,
+                    extra: None,
+                    token: Some(token),
+                },
+                endpoint,
+                local_stream,
+                &bridge_id52,
+                peer_connections,
+                bridge_graceful,
+            )
+            .await;
+        }
+    });
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let test_data = b"Hello, kulfi!";
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", bridge_port))
+        .await
+        .expect("Failed to connect to bridge");
+    stream
+        .write_all(test_data)
+        .await
+        .expect("Failed to write test data");
+
+    let mut response = vec![0u8; test_data.len()];
+    tokio::time::timeout(Duration::from_secs(30), stream.read_exact(&mut response))
+        .await
+        .expect("Timeout waiting for response")
+        .expect("Read error");
+    assert_eq!(&response[..], test_data, "the token reached the service");
+
+    drop(stream);
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     echo_handle.abort();
     expose_handle.abort();
@@ -170,7 +295,16 @@ async fn test_udp_echo_connection_inner() {
     let expose_host = "127.0.0.1".to_string();
 
     let expose_handle = tokio::spawn(async move {
-        malai::expose_udp(expose_host, echo_port, expose_id52, secret, expose_graceful).await;
+        malai::expose_udp(
+            expose_host,
+            echo_port,
+            expose_id52,
+            secret,
+            false,
+            expose_graceful,
+        )
+        .await
+        .unwrap();
     });
 
     // Give server more time to register with relay for discovery
@@ -246,6 +380,7 @@ async fn test_udp_echo_connection_inner() {
     }
 
     // Cleanup: best-effort, don't block the test
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     echo_handle.abort();
     expose_handle.abort();
@@ -297,9 +432,11 @@ async fn test_multiple_tcp_streams_inner() {
             echo_port,
             expose_id52,
             secret,
+            false,
             expose_graceful,
         )
-        .await;
+        .await
+        .unwrap();
     });
 
     tokio::time::sleep(Duration::from_secs(2)).await;
@@ -314,7 +451,9 @@ async fn test_multiple_tcp_streams_inner() {
     drop(bridge_listener);
 
     let bridge_handle = tokio::spawn(async move {
-        malai::tcp_bridge(bridge_port, bridge_id52, bridge_graceful).await;
+        malai::tcp_bridge(bridge_port, bridge_id52, None, bridge_graceful)
+            .await
+            .unwrap();
     });
 
     tokio::time::sleep(Duration::from_secs(2)).await;
@@ -354,6 +493,7 @@ async fn test_multiple_tcp_streams_inner() {
     println!("✓ All 3 streams completed successfully");
 
     // Cleanup: best-effort, don't block the test
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     echo_handle.abort();
     expose_handle.abort();
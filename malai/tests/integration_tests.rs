@@ -145,9 +145,11 @@ async fn test_tcp_client_server_connection_inner() {
             echo_port,
             expose_id52,
             secret,
+            false,
             expose_graceful,
         )
-        .await;
+        .await
+        .unwrap();
     });
 
     // Give the server time to start
@@ -158,7 +160,9 @@ async fn test_tcp_client_server_connection_inner() {
     let bridge_graceful = graceful.clone();
     let bridge_id52 = id52.clone();
     let bridge_handle = tokio::spawn(async move {
-        malai::tcp_bridge(bridge_port, bridge_id52, bridge_graceful).await;
+        malai::tcp_bridge(bridge_port, bridge_id52, None, bridge_graceful)
+            .await
+            .unwrap();
     });
 
     // Give the bridge time to start
@@ -192,6 +196,7 @@ async fn test_tcp_client_server_connection_inner() {
     println!("✓ TCP client successfully connected and received echo response");
 
     // Cleanup: best-effort, don't block the test
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     echo_handle.abort();
     expose_handle.abort();
@@ -227,9 +232,11 @@ async fn test_udp_client_server_connection_inner() {
             echo_port,
             expose_id52,
             secret,
+            false,
             expose_graceful,
         )
-        .await;
+        .await
+        .unwrap();
     });
 
     // Give server more time to register with relay for discovery
@@ -240,7 +247,9 @@ async fn test_udp_client_server_connection_inner() {
     let bridge_graceful = graceful.clone();
     let bridge_id52 = id52.clone();
     let bridge_handle = tokio::spawn(async move {
-        malai::udp_bridge(bridge_port, bridge_id52, bridge_graceful).await;
+        malai::udp_bridge(bridge_port, bridge_id52, None, bridge_graceful)
+            .await
+            .unwrap();
     });
 
     // Give bridge time to start
@@ -275,6 +284,7 @@ async fn test_udp_client_server_connection_inner() {
     println!("✓ UDP client successfully connected and received echo response");
 
     // Cleanup: best-effort, don't block the test
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     echo_handle.abort();
     expose_handle.abort();
@@ -311,9 +321,11 @@ async fn test_http_client_server_connection_inner() {
             "test.local".to_string(), // Bridge domain (not used in this test)
             expose_id52,
             secret,
+            false,
             expose_graceful,
         )
-        .await;
+        .await
+        .unwrap();
     });
 
     // Give server time to start
@@ -327,6 +339,7 @@ async fn test_http_client_server_connection_inner() {
     println!("  (Full HTTP bridge test requires domain setup)");
 
     // Cleanup: best-effort, don't block the test
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     http_handle.abort();
     expose_handle.abort();
@@ -361,9 +374,11 @@ async fn test_tcp_multiple_connections_inner() {
             echo_port,
             expose_id52,
             secret,
+            false,
             expose_graceful,
         )
-        .await;
+        .await
+        .unwrap();
     });
 
     // Give server time to start
@@ -374,7 +389,9 @@ async fn test_tcp_multiple_connections_inner() {
     let bridge_graceful = graceful.clone();
     let bridge_id52 = id52.clone();
     let bridge_handle = tokio::spawn(async move {
-        malai::tcp_bridge(bridge_port, bridge_id52, bridge_graceful).await;
+        malai::tcp_bridge(bridge_port, bridge_id52, None, bridge_graceful)
+            .await
+            .unwrap();
     });
 
     // Give bridge time to start
@@ -415,6 +432,7 @@ async fn test_tcp_multiple_connections_inner() {
     println!("✓ All 5 concurrent connections successful");
 
     // Cleanup: best-effort, don't block the test
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     echo_handle.abort();
     expose_handle.abort();
@@ -450,9 +468,11 @@ async fn test_tcp_large_data_transfer_inner() {
             echo_port,
             expose_id52,
             secret,
+            false,
             expose_graceful,
         )
-        .await;
+        .await
+        .unwrap();
     });
 
     tokio::time::sleep(Duration::from_secs(2)).await;
@@ -462,7 +482,9 @@ async fn test_tcp_large_data_transfer_inner() {
     let bridge_graceful = graceful.clone();
     let bridge_id52 = id52.clone();
     let bridge_handle = tokio::spawn(async move {
-        malai::tcp_bridge(bridge_port, bridge_id52, bridge_graceful).await;
+        malai::tcp_bridge(bridge_port, bridge_id52, None, bridge_graceful)
+            .await
+            .unwrap();
     });
 
     tokio::time::sleep(Duration::from_secs(2)).await;
@@ -489,6 +511,7 @@ async fn test_tcp_large_data_transfer_inner() {
     println!("✓ Successfully transferred 1MB of data");
 
     // Cleanup: best-effort, don't block the test
+    graceful.cancel();
     let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown()).await;
     echo_handle.abort();
     expose_handle.abort();